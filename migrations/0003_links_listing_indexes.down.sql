@@ -0,0 +1,2 @@
+DROP INDEX links_metadata_idx;
+DROP INDEX links_platform_id_created_at_idx;
\ No newline at end of file
@@ -0,0 +1,2 @@
+CREATE INDEX links_platform_id_created_at_idx ON links (platform_id, created_at DESC, slug DESC);
+CREATE INDEX links_metadata_idx ON links USING GIN (metadata jsonb_path_ops);
\ No newline at end of file
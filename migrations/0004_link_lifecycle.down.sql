@@ -0,0 +1,4 @@
+ALTER TABLE links
+    DROP COLUMN visit_count,
+    DROP COLUMN max_visits,
+    DROP COLUMN expires_at;
\ No newline at end of file
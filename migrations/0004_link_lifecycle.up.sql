@@ -0,0 +1,4 @@
+ALTER TABLE links
+    ADD COLUMN expires_at   TIMESTAMPTZ,
+    ADD COLUMN max_visits   BIGINT CHECK (max_visits > 0),
+    ADD COLUMN visit_count  BIGINT NOT NULL DEFAULT 0;
\ No newline at end of file
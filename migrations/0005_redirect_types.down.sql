@@ -0,0 +1,5 @@
+ALTER TABLE links DROP COLUMN redirect_type;
+
+ALTER TABLE platforms DROP COLUMN default_redirect_type;
+
+DROP TYPE redirect_type;
\ No newline at end of file
@@ -0,0 +1,5 @@
+CREATE TYPE redirect_type AS ENUM ('moved_permanently', 'found', 'temporary_redirect', 'permanent_redirect');
+
+ALTER TABLE platforms ADD COLUMN default_redirect_type redirect_type NOT NULL DEFAULT 'temporary_redirect';
+
+ALTER TABLE links ADD COLUMN redirect_type redirect_type;
\ No newline at end of file
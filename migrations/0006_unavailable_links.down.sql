@@ -0,0 +1,5 @@
+ALTER TABLE platforms
+    DROP COLUMN unavailable_fallback_html,
+    DROP COLUMN unavailable_fallback_url;
+
+ALTER TABLE links DROP COLUMN disabled;
\ No newline at end of file
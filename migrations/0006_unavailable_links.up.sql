@@ -0,0 +1,5 @@
+ALTER TABLE links ADD COLUMN disabled BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE platforms
+    ADD COLUMN unavailable_fallback_url   VARCHAR,
+    ADD COLUMN unavailable_fallback_html  VARCHAR;
\ No newline at end of file
@@ -0,0 +1 @@
+DROP INDEX link_visits_link_slug_at_idx;
\ No newline at end of file
@@ -0,0 +1 @@
+CREATE INDEX link_visits_link_slug_at_idx ON link_visits (link_slug, at);
\ No newline at end of file
@@ -0,0 +1,3 @@
+DROP TABLE link_visit_daily;
+
+DROP INDEX link_visits_at_idx;
\ No newline at end of file
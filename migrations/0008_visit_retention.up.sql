@@ -0,0 +1,8 @@
+CREATE INDEX link_visits_at_idx ON link_visits (at);
+
+CREATE TABLE link_visit_daily (
+    link_slug  VARCHAR NOT NULL REFERENCES links (slug) ON DELETE CASCADE,
+    day        DATE NOT NULL,
+    visits     BIGINT NOT NULL,
+    PRIMARY KEY (link_slug, day)
+);
\ No newline at end of file
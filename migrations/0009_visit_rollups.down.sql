@@ -0,0 +1,8 @@
+DROP FUNCTION referrer_domain;
+
+DROP TABLE link_visit_rollup_state;
+
+ALTER TABLE link_visit_daily
+    DROP COLUMN unique_visitors,
+    DROP COLUMN referrer_domains,
+    DROP COLUMN user_agents;
\ No newline at end of file
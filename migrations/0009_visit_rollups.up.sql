@@ -0,0 +1,15 @@
+ALTER TABLE link_visit_daily
+    ADD COLUMN unique_visitors   BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN referrer_domains  JSONB NOT NULL DEFAULT '{}',
+    ADD COLUMN user_agents       JSONB NOT NULL DEFAULT '{}';
+
+-- Single row, every day before rolled_up_until has been rolled up into link_visit_daily
+CREATE TABLE link_visit_rollup_state (
+    id               BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    rolled_up_until  DATE NOT NULL
+);
+
+-- Lowercase host of the first Referer header of a visit, if it has one
+CREATE FUNCTION referrer_domain(headers JSONB) RETURNS VARCHAR
+    LANGUAGE SQL IMMUTABLE
+    RETURN LOWER(SUBSTRING(headers->'referer'->>0 FROM '^[a-zA-Z][a-zA-Z0-9+.-]*://(?:[^/?#@]*@)?([^/?#:]+)'));
\ No newline at end of file
@@ -0,0 +1,15 @@
+DELETE FROM link_visit_daily WHERE is_bot;
+
+ALTER TABLE link_visit_daily
+    DROP CONSTRAINT link_visit_daily_pkey,
+    ADD PRIMARY KEY (link_slug, day),
+    DROP COLUMN is_bot,
+    DROP COLUMN browsers,
+    DROP COLUMN operating_systems,
+    DROP COLUMN device_types;
+
+ALTER TABLE link_visits
+    DROP COLUMN browser,
+    DROP COLUMN os,
+    DROP COLUMN device_type,
+    DROP COLUMN is_bot;
\ No newline at end of file
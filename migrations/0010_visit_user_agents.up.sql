@@ -0,0 +1,14 @@
+ALTER TABLE link_visits
+    ADD COLUMN browser      VARCHAR,
+    ADD COLUMN os           VARCHAR,
+    ADD COLUMN device_type  VARCHAR,
+    ADD COLUMN is_bot       BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Bot and human visits are rolled up separately so stats can exclude bots
+ALTER TABLE link_visit_daily
+    ADD COLUMN is_bot             BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN browsers           JSONB NOT NULL DEFAULT '{}',
+    ADD COLUMN operating_systems  JSONB NOT NULL DEFAULT '{}',
+    ADD COLUMN device_types       JSONB NOT NULL DEFAULT '{}',
+    DROP CONSTRAINT link_visit_daily_pkey,
+    ADD PRIMARY KEY (link_slug, day, is_bot);
\ No newline at end of file
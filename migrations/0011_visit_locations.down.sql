@@ -0,0 +1,9 @@
+ALTER TABLE link_visit_daily
+    DROP COLUMN countries,
+    DROP COLUMN regions,
+    DROP COLUMN cities;
+
+ALTER TABLE link_visits
+    DROP COLUMN country,
+    DROP COLUMN region,
+    DROP COLUMN city;
\ No newline at end of file
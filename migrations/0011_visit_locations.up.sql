@@ -0,0 +1,9 @@
+ALTER TABLE link_visits
+    ADD COLUMN country  VARCHAR,
+    ADD COLUMN region   VARCHAR,
+    ADD COLUMN city     VARCHAR;
+
+ALTER TABLE link_visit_daily
+    ADD COLUMN countries  JSONB NOT NULL DEFAULT '{}',
+    ADD COLUMN regions    JSONB NOT NULL DEFAULT '{}',
+    ADD COLUMN cities     JSONB NOT NULL DEFAULT '{}';
\ No newline at end of file
@@ -0,0 +1,10 @@
+DROP INDEX link_visits_referrer_domain_at_idx;
+
+-- Lowercase host of the first Referer header of a visit, if it has one
+CREATE FUNCTION referrer_domain(headers JSONB) RETURNS VARCHAR
+    LANGUAGE SQL IMMUTABLE
+    RETURN LOWER(SUBSTRING(headers->'referer'->>0 FROM '^[a-zA-Z][a-zA-Z0-9+.-]*://(?:[^/?#@]*@)?([^/?#:]+)'));
+
+ALTER TABLE link_visits
+    DROP COLUMN referrer,
+    DROP COLUMN referrer_domain;
\ No newline at end of file
@@ -0,0 +1,53 @@
+ALTER TABLE link_visits
+    ADD COLUMN referrer         VARCHAR,
+    ADD COLUMN referrer_domain  VARCHAR;
+
+-- Mirrors normalize_referrer_domain in src/common/referrer.rs for visits stored before this
+CREATE FUNCTION normalize_referrer_domain(VARCHAR) RETURNS VARCHAR
+    LANGUAGE SQL IMMUTABLE
+    AS $$
+        SELECT NULLIF(COALESCE(networks.domain, host.domain), '')
+        FROM (SELECT REGEXP_REPLACE(RTRIM(LOWER($1), '.'), '^www\.', '') AS domain) AS host
+        LEFT JOIN (
+            VALUES
+                ('t.co', 'x.com'),
+                ('twitter.com', 'x.com'),
+                ('mobile.twitter.com', 'x.com'),
+                ('l.facebook.com', 'facebook.com'),
+                ('lm.facebook.com', 'facebook.com'),
+                ('m.facebook.com', 'facebook.com'),
+                ('l.instagram.com', 'instagram.com'),
+                ('l.messenger.com', 'messenger.com'),
+                ('l.threads.net', 'threads.net'),
+                ('out.reddit.com', 'reddit.com'),
+                ('old.reddit.com', 'reddit.com'),
+                ('lnkd.in', 'linkedin.com'),
+                ('m.youtube.com', 'youtube.com'),
+                ('away.vk.com', 'vk.com')
+        ) AS networks(redirector, domain) ON networks.redirector = host.domain
+    $$;
+
+UPDATE link_visits
+SET
+    referrer = NULLIF(TRIM(headers->'referer'->>0), ''),
+    referrer_domain = normalize_referrer_domain(referrer_domain(headers))
+WHERE headers ? 'referer';
+
+-- Merge the counts of domains which are the same once normalized
+UPDATE link_visit_daily
+SET referrer_domains = (
+    SELECT COALESCE(JSONB_OBJECT_AGG(counts.domain, counts.visits), '{}')
+    FROM (
+        SELECT normalize_referrer_domain(entry.key) AS domain, SUM(entry.value::BIGINT) AS visits
+        FROM JSONB_EACH_TEXT(link_visit_daily.referrer_domains) AS entry
+        GROUP BY 1
+    ) AS counts
+    WHERE counts.domain IS NOT NULL
+)
+WHERE referrer_domains <> '{}';
+
+DROP FUNCTION normalize_referrer_domain;
+DROP FUNCTION referrer_domain;
+
+CREATE INDEX link_visits_referrer_domain_at_idx ON link_visits (referrer_domain, at)
+    WHERE referrer_domain IS NOT NULL;
\ No newline at end of file
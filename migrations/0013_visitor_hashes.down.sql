@@ -0,0 +1,3 @@
+ALTER TABLE link_visits DROP COLUMN visitor_hash;
+
+DROP TABLE visitor_salts;
\ No newline at end of file
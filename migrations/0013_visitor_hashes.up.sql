@@ -0,0 +1,30 @@
+-- Random salts which visitors are hashed with, one per (UTC) day. Salts of past days are deleted
+-- so the hashes can't be linked to visitors anymore
+CREATE TABLE visitor_salts (
+    day   DATE PRIMARY KEY,
+    salt  BYTEA NOT NULL
+);
+
+ALTER TABLE link_visits ADD COLUMN visitor_hash VARCHAR;
+
+-- Visits stored before this are hashed with their IP address as it was stored
+INSERT INTO visitor_salts (day, salt)
+SELECT days.day, UUID_SEND(GEN_RANDOM_UUID()) || UUID_SEND(GEN_RANDOM_UUID())
+FROM (SELECT DISTINCT (at AT TIME ZONE 'UTC')::DATE AS day FROM link_visits) AS days;
+
+UPDATE link_visits
+SET visitor_hash = ENCODE(
+    SUBSTRING(
+        SHA256(
+            visitor_salts.salt
+            || CONVERT_TO(ip_address || E'\n' || COALESCE(headers->'user-agent'->>0, ''), 'UTF8')
+        )
+        FOR 16
+    ),
+    'hex'
+)
+FROM visitor_salts
+WHERE visitor_salts.day = (link_visits.at AT TIME ZONE 'UTC')::DATE
+    AND link_visits.ip_address IS NOT NULL;
+
+DELETE FROM visitor_salts WHERE day < (NOW() AT TIME ZONE 'UTC')::DATE - 1;
\ No newline at end of file
@@ -0,0 +1,5 @@
+DROP TABLE platform_monthly_usage;
+
+ALTER TABLE platforms
+    DROP COLUMN api_requests_per_minute,
+    DROP COLUMN monthly_link_quota;
\ No newline at end of file
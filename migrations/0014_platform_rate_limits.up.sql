@@ -0,0 +1,12 @@
+-- NULL means unlimited
+ALTER TABLE platforms
+    ADD COLUMN api_requests_per_minute  INTEGER DEFAULT 600 CHECK (api_requests_per_minute > 0),
+    ADD COLUMN monthly_link_quota       BIGINT CHECK (monthly_link_quota > 0);
+
+-- Links created through the API by each platform each (UTC) month, month is its first day
+CREATE TABLE platform_monthly_usage (
+    platform_id    UUID NOT NULL REFERENCES platforms (id) ON DELETE CASCADE,
+    month          DATE NOT NULL,
+    links_created  BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (platform_id, month)
+);
\ No newline at end of file
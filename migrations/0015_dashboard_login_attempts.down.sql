@@ -0,0 +1 @@
+DROP TABLE dashboard_login_attempts;
\ No newline at end of file
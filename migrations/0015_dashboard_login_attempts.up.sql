@@ -0,0 +1,10 @@
+-- Attempts are forgotten after a day, see common/login_throttle.rs
+CREATE TABLE dashboard_login_attempts (
+    id          UUID PRIMARY KEY,
+    at          TIMESTAMPTZ NOT NULL,
+    ip_address  VARCHAR,
+    succeeded   BOOLEAN NOT NULL
+);
+
+CREATE INDEX dashboard_login_attempts_ip_address_at_idx ON dashboard_login_attempts (ip_address, at);
+CREATE INDEX dashboard_login_attempts_at_idx ON dashboard_login_attempts (at);
\ No newline at end of file
@@ -0,0 +1,5 @@
+ALTER TABLE dashboard_login_tokens DROP COLUMN user_id;
+
+DROP TABLE dashboard_user_platforms;
+DROP TABLE dashboard_users;
+DROP TYPE dashboard_user_role;
\ No newline at end of file
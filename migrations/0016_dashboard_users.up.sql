@@ -0,0 +1,26 @@
+CREATE TYPE dashboard_user_role AS ENUM ('owner', 'editor', 'viewer');
+
+CREATE TABLE dashboard_users (
+    id             UUID PRIMARY KEY,
+    username       VARCHAR NOT NULL,
+    password_hash  VARCHAR NOT NULL,
+    role           dashboard_user_role NOT NULL,
+    created_at     TIMESTAMPTZ NOT NULL
+);
+
+CREATE UNIQUE INDEX dashboard_users_username_idx ON dashboard_users (LOWER(username));
+
+-- Platforms which editors and viewers can access, owners can access every platform
+CREATE TABLE dashboard_user_platforms (
+    user_id      UUID NOT NULL REFERENCES dashboard_users (id) ON DELETE CASCADE,
+    platform_id  UUID NOT NULL REFERENCES platforms (id) ON DELETE CASCADE,
+    PRIMARY KEY (user_id, platform_id)
+);
+
+CREATE INDEX dashboard_user_platforms_platform_id_idx ON dashboard_user_platforms (platform_id);
+
+-- Sessions logged in with the shared admin password don't belong to a user
+DELETE FROM dashboard_login_tokens;
+
+ALTER TABLE dashboard_login_tokens
+    ADD COLUMN user_id UUID NOT NULL REFERENCES dashboard_users (id) ON DELETE CASCADE;
\ No newline at end of file
@@ -0,0 +1,7 @@
+DROP TABLE dashboard_login_challenges;
+DROP TABLE dashboard_recovery_codes;
+
+ALTER TABLE dashboard_users
+    DROP COLUMN totp_secret,
+    DROP COLUMN totp_enabled,
+    DROP COLUMN totp_last_used_step;
\ No newline at end of file
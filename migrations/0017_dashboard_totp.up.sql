@@ -0,0 +1,22 @@
+-- A secret which isn't enabled yet is waiting for the user to confirm a code from their app
+ALTER TABLE dashboard_users
+    ADD COLUMN totp_secret          BYTEA,
+    ADD COLUMN totp_enabled         BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN totp_last_used_step  BIGINT;
+
+CREATE TABLE dashboard_recovery_codes (
+    id         UUID PRIMARY KEY,
+    user_id    UUID NOT NULL REFERENCES dashboard_users (id) ON DELETE CASCADE,
+    code_hash  VARCHAR NOT NULL,
+    used_at    TIMESTAMPTZ
+);
+
+CREATE INDEX dashboard_recovery_codes_user_id_idx ON dashboard_recovery_codes (user_id);
+
+-- Logins which got the password right and are waiting for a TOTP or recovery code
+CREATE TABLE dashboard_login_challenges (
+    id          UUID PRIMARY KEY,
+    user_id     UUID NOT NULL REFERENCES dashboard_users (id) ON DELETE CASCADE,
+    token_hash  VARCHAR NOT NULL,
+    created_at  TIMESTAMPTZ NOT NULL
+);
\ No newline at end of file
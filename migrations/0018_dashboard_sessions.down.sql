@@ -0,0 +1,5 @@
+DROP INDEX dashboard_login_tokens_user_id_idx;
+
+ALTER TABLE dashboard_login_tokens
+    DROP COLUMN ip_address,
+    DROP COLUMN user_agent;
\ No newline at end of file
@@ -0,0 +1,6 @@
+-- Shown on the sessions page so users can recognize their logins
+ALTER TABLE dashboard_login_tokens
+    ADD COLUMN ip_address VARCHAR,
+    ADD COLUMN user_agent VARCHAR;
+
+CREATE INDEX dashboard_login_tokens_user_id_idx ON dashboard_login_tokens (user_id);
\ No newline at end of file
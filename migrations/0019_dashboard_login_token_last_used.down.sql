@@ -0,0 +1 @@
+ALTER TABLE dashboard_login_tokens DROP COLUMN last_used_at;
\ No newline at end of file
@@ -0,0 +1,8 @@
+-- Tokens expire after a period without use instead of a period after logging in
+ALTER TABLE dashboard_login_tokens ADD COLUMN last_used_at TIMESTAMPTZ;
+
+UPDATE dashboard_login_tokens SET last_used_at = created_at;
+
+ALTER TABLE dashboard_login_tokens ALTER COLUMN last_used_at SET NOT NULL;
+
+CREATE INDEX dashboard_login_tokens_last_used_at_idx ON dashboard_login_tokens (last_used_at);
\ No newline at end of file
@@ -0,0 +1 @@
+ALTER TABLE dashboard_login_attempts DROP COLUMN username;
\ No newline at end of file
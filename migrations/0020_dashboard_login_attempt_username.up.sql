@@ -0,0 +1,2 @@
+-- A successful attempt only resets the failed attempts from its IP address for the same username
+ALTER TABLE dashboard_login_attempts ADD COLUMN username VARCHAR;
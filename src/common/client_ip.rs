@@ -0,0 +1,156 @@
+use std::net::IpAddr;
+
+use ipnetwork::IpNetwork;
+use poem::http::HeaderMap;
+
+use crate::config::CONFIG;
+
+/// Resolves the address of the client which made the request. The X-Forwarded-For and X-Real-IP
+/// headers are only honoured when the peer is a trusted proxy, otherwise anyone could spoof them.
+///
+/// X-Forwarded-For is read from the right, skipping trusted proxies, so the result is the last
+/// address which was added by a trusted proxy rather than whatever the client put first
+pub fn resolve_client_ip(
+    peer_ip: Option<IpAddr>,
+    headers: &HeaderMap,
+    trusted_proxies: &[IpNetwork],
+) -> Option<IpAddr> {
+    let is_trusted = |ip: IpAddr| trusted_proxies.iter().any(|network| network.contains(ip));
+
+    // Dual stack listeners report IPv4 peers as IPv4-mapped IPv6 addresses
+    let peer_ip = peer_ip?.to_canonical();
+
+    if !is_trusted(peer_ip) {
+        return Some(peer_ip);
+    }
+
+    let forwarded_for = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect::<Vec<_>>();
+
+    if !forwarded_for.is_empty() {
+        let mut client_ip = peer_ip;
+
+        for hop in forwarded_for.into_iter().rev() {
+            let Ok(hop_ip) = hop.parse::<IpAddr>() else {
+                break;
+            };
+
+            client_ip = hop_ip.to_canonical();
+
+            if !is_trusted(client_ip) {
+                break;
+            }
+        }
+
+        return Some(client_ip);
+    }
+
+    let real_ip = headers
+        .get("x-real-ip")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<IpAddr>().ok());
+
+    Some(real_ip.map_or(peer_ip, |ip| ip.to_canonical()))
+}
+
+/// Extracts the client's IP address with resolve_client_ip and the configured trusted proxies
+#[derive(Debug)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl<'a> poem::FromRequest<'a> for ClientIp {
+    async fn from_request(
+        req: &'a poem::Request,
+        _body: &mut poem::RequestBody,
+    ) -> poem::Result<Self> {
+        let peer_ip = req.remote_addr().as_socket_addr().map(|addr| addr.ip());
+
+        // Test requests have no socket address, treat them as coming from a local proxy
+        #[cfg(test)]
+        let peer_ip = peer_ip.or(Some(IpAddr::V6(std::net::Ipv6Addr::LOCALHOST)));
+
+        Ok(ClientIp(resolve_client_ip(
+            peer_ip,
+            req.headers(),
+            &CONFIG.trusted_proxies,
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use poem::http::HeaderValue;
+
+    use super::*;
+
+    fn trusted_proxies() -> Vec<IpNetwork> {
+        vec!["10.0.0.0/8".parse().unwrap(), "::1".parse().unwrap()]
+    }
+
+    fn headers(forwarded_for: &[&str], real_ip: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in forwarded_for {
+            headers.append("x-forwarded-for", HeaderValue::from_str(value).unwrap());
+        }
+        if let Some(real_ip) = real_ip {
+            headers.insert("x-real-ip", HeaderValue::from_str(real_ip).unwrap());
+        }
+        headers
+    }
+
+    fn resolve(peer_ip: &str, headers: &HeaderMap) -> Option<IpAddr> {
+        resolve_client_ip(peer_ip.parse().ok(), headers, &trusted_proxies())
+    }
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn test_untrusted_peer_ignores_forwarded_headers() {
+        let headers = headers(&["198.51.100.1"], Some("198.51.100.2"));
+
+        assert_eq!(resolve("203.0.113.7", &headers), ip("203.0.113.7"));
+        assert_eq!(resolve("::ffff:203.0.113.7", &headers), ip("203.0.113.7"));
+        assert_eq!(resolve("unknown", &headers), None);
+    }
+
+    #[test]
+    fn test_trusted_peer_uses_forwarded_for() {
+        // The first address was made up by the client, the second was added by our proxy
+        let headers = headers(&["1.2.3.4, 203.0.113.7", "10.0.0.2"], Some("10.0.0.3"));
+
+        assert_eq!(resolve("10.0.0.1", &headers), ip("203.0.113.7"));
+        assert_eq!(resolve("::1", &headers), ip("203.0.113.7"));
+        assert_eq!(resolve("::ffff:10.0.0.1", &headers), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn test_trusted_peer_with_only_trusted_or_invalid_hops() {
+        assert_eq!(
+            resolve("10.0.0.1", &headers(&["10.0.0.3, 10.0.0.2"], None)),
+            ip("10.0.0.3")
+        );
+        assert_eq!(
+            resolve("10.0.0.1", &headers(&["garbage, 10.0.0.2"], None)),
+            ip("10.0.0.2")
+        );
+    }
+
+    #[test]
+    fn test_trusted_peer_uses_real_ip() {
+        assert_eq!(
+            resolve("10.0.0.1", &headers(&[], Some("203.0.113.7"))),
+            ip("203.0.113.7")
+        );
+        assert_eq!(
+            resolve("10.0.0.1", &headers(&[], Some("not an ip"))),
+            ip("10.0.0.1")
+        );
+        assert_eq!(resolve("10.0.0.1", &headers(&[], None)), ip("10.0.0.1"));
+    }
+}
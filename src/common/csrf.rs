@@ -0,0 +1,134 @@
+use poem::{Endpoint, http::Method, http::StatusCode, session::Session};
+use rand::distr::{Alphanumeric, SampleString};
+
+const CSRF_TOKEN_DATA_KEY: &str = "__Host-CSRF";
+
+/// Name of the hidden input every dashboard form submits the token in
+pub const CSRF_TOKEN_FIELD: &str = "csrf_token";
+/// Header the token can be sent in instead, for requests which aren't form submissions
+pub const CSRF_TOKEN_HEADER: &str = "x-csrf-token";
+/// Largest form body read to look for the token, enough for a platform's fallback HTML
+const MAX_FORM_BODY_BYTES: usize = 1024 * 1024;
+
+/// The session's synchronizer token, added to the request's data by csrf_middleware for templates
+/// to embed in their forms
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsrfToken(pub String);
+
+/// Compares in constant time so the token can't be guessed a character at a time
+fn tokens_match(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+fn generate_csrf_token(session: &Session) -> String {
+    let token = Alphanumeric.sample_string(&mut rand::rng(), 32);
+    session.set(CSRF_TOKEN_DATA_KEY, &token);
+    token
+}
+
+/// Replaces the session's token, so a token from before logging in can't be used after it
+pub fn rotate_csrf_token(session: &Session) {
+    generate_csrf_token(session);
+}
+
+fn form_csrf_token(body: &[u8]) -> Option<String> {
+    url::form_urlencoded::parse(body)
+        .find(|(name, _)| name == CSRF_TOKEN_FIELD)
+        .map(|(_, value)| value.into_owned())
+}
+
+/// Gives each session a random token, and rejects requests which can change anything with a 403
+/// Forbidden unless they include it, so other sites can't submit forms on a user's behalf. Must be
+/// used inside the session middleware
+pub async fn csrf_middleware<E: Endpoint>(
+    next: E,
+    mut req: poem::Request,
+) -> poem::Result<E::Output> {
+    let session = req.data::<Session>().unwrap();
+
+    let token = match session.get::<String>(CSRF_TOKEN_DATA_KEY) {
+        Some(token) => token,
+        None => generate_csrf_token(session),
+    };
+
+    if !matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        let header_token = req
+            .header(CSRF_TOKEN_HEADER)
+            .map(|header_token| header_token.to_string());
+
+        let submitted_token = match header_token {
+            Some(header_token) => Some(header_token),
+            None => {
+                // Rejected with a 413 Payload Too Large if it's over the limit
+                let body = req
+                    .take_body()
+                    .into_bytes_limit(MAX_FORM_BODY_BYTES)
+                    .await?;
+                let form_token = form_csrf_token(&body);
+                req.set_body(body);
+                form_token
+            }
+        };
+
+        if !submitted_token.is_some_and(|submitted_token| tokens_match(&submitted_token, &token)) {
+            return Err(poem::Error::from_string(
+                "Invalid CSRF token, please reload the page and try again",
+                StatusCode::FORBIDDEN,
+            ));
+        }
+    }
+
+    req.set_data(CsrfToken(token));
+
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use poem::http::header;
+    use sqlx::PgPool;
+
+    use crate::common::testing::app::{api_test_client, dashboard_login_page_session};
+
+    use super::*;
+
+    #[test]
+    fn test_tokens_match() {
+        assert!(tokens_match("abc", "abc"));
+        assert!(!tokens_match("abc", "abd"));
+        assert!(!tokens_match("abc", "abcd"));
+        assert!(!tokens_match("", "abc"));
+    }
+
+    #[test]
+    fn test_form_csrf_token() {
+        assert_eq!(
+            form_csrf_token(b"platform_id=1&csrf_token=a%2Bb").as_deref(),
+            Some("a+b")
+        );
+        assert_eq!(form_csrf_token(b"platform_id=1"), None);
+    }
+
+    #[sqlx::test]
+    async fn test_csrf_middleware_body_limit(db_pool: PgPool) {
+        let cli = api_test_client(db_pool);
+        let session = dashboard_login_page_session(&cli).await;
+
+        let resp = cli
+            .post("/admin/dashboard/login/")
+            .header(header::COOKIE, &session.cookie)
+            .content_type("application/x-www-form-urlencoded")
+            .body(format!(
+                "csrf_token={}&username={}",
+                session.csrf_token,
+                "a".repeat(MAX_FORM_BODY_BYTES)
+            ))
+            .send()
+            .await;
+        resp.assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
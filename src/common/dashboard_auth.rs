@@ -1,17 +1,88 @@
-use poem::{Endpoint, IntoResponse, session::Session, web::Redirect};
+use chrono::{DateTime, TimeDelta, Utc};
+use poem::{Endpoint, IntoResponse, http::StatusCode, session::Session, web::Redirect};
 use rand::distr::{Alphanumeric, SampleString};
 use serde::{Deserialize, Serialize};
+use serde_valid::validation::Error;
 use sqlx::PgConnection;
-use std::error::Error as StdError;
+use std::{error::Error as StdError, sync::LazyLock};
 use uuid::Uuid;
 
 use crate::{
-    common::argon2::{argon2_check_key_against_hash, argon2_hash_key, setup_weak_argon2},
-    config::CONFIG,
-    db::dashboard_login_token::{create_dashboard_login_token, get_dashboard_login_token},
+    common::{
+        argon2::{
+            argon2_check_key_against_hash, argon2_hash_key, setup_strong_argon2, setup_weak_argon2,
+        },
+        csrf::rotate_csrf_token,
+        totp::{normalize_recovery_code, verify_totp_code},
+    },
+    db::{
+        dashboard_login_challenges::{
+            create_dashboard_login_challenge, delete_dashboard_login_challenge,
+            delete_expired_dashboard_login_challenges, get_dashboard_login_challenge,
+        },
+        dashboard_login_token::{
+            create_dashboard_login_token, delete_dashboard_login_token, get_dashboard_login_token,
+            touch_dashboard_login_token,
+        },
+        dashboard_recovery_codes::{
+            get_unused_dashboard_recovery_codes, use_dashboard_recovery_code,
+        },
+        dashboard_users::{
+            DashboardUser, get_dashboard_user, get_dashboard_user_by_username,
+            use_dashboard_user_totp_step,
+        },
+    },
 };
 
 pub const DASHBOARD_SESSION_TOKEN_DATA_KEY: &str = "__Host-DSTD";
+pub const DASHBOARD_SESSION_CHALLENGE_DATA_KEY: &str = "__Host-DSCD";
+
+/// How often a session's token is marked as used while it's being used, which also renews the
+/// session cookie, so sessions only expire once they've gone unused for the expiry duration
+const LOGIN_TOKEN_TOUCH_INTERVAL: TimeDelta = TimeDelta::minutes(1);
+
+/// How long after getting the password right the second factor has to be entered
+const LOGIN_CHALLENGE_EXPIRES_AFTER: TimeDelta = TimeDelta::minutes(5);
+
+pub const USERNAME_MIN_LENGTH: usize = 2;
+pub const USERNAME_MAX_LENGTH: usize = 32;
+
+pub const PASSWORD_MIN_LENGTH: usize = 8;
+pub const PASSWORD_MAX_LENGTH: usize = 256;
+
+/// Validates that a dashboard username is of a reasonable length and only contains characters which
+/// can't be confused with each other
+pub fn validate_dashboard_username(username: &str) -> Result<(), Error> {
+    let length = username.chars().count();
+    if !(USERNAME_MIN_LENGTH..=USERNAME_MAX_LENGTH).contains(&length) {
+        return Err(Error::Custom(format!(
+            "username must be between {USERNAME_MIN_LENGTH} and {USERNAME_MAX_LENGTH} characters long"
+        )));
+    }
+
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+    {
+        return Err(Error::Custom(
+            "username must only contain alphanumeric characters, dashes, underscores, and dots"
+                .to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn validate_dashboard_password(password: &str) -> Result<(), Error> {
+    let length = password.chars().count();
+    if !(PASSWORD_MIN_LENGTH..=PASSWORD_MAX_LENGTH).contains(&length) {
+        return Err(Error::Custom(format!(
+            "password must be between {PASSWORD_MIN_LENGTH} and {PASSWORD_MAX_LENGTH} characters long"
+        )));
+    }
+
+    Ok(())
+}
 
 #[derive(Serialize, Deserialize)]
 struct DashboardSessionLoginTokenData {
@@ -19,34 +90,245 @@ struct DashboardSessionLoginTokenData {
     token: String,
 }
 
-/// Checks the password, returning `false` if incorrect, otherwise updating the session with a new token and returning `true`
+/// Where a login came from, stored with its token so users can recognize their sessions
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DashboardLoginClient<'a> {
+    pub ip_address: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+}
+
+/// The id of the logged in session's login token, added to the request's data by
+/// dashboard_auth_middleware alongside the DashboardUser
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DashboardSessionId(pub Uuid);
+
+/// Checked against when the username doesn't exist, so that it takes as long as a wrong password
+static UNKNOWN_USER_PASSWORD_HASH: LazyLock<String> =
+    LazyLock::new(|| argon2_hash_key(&setup_strong_argon2(), "unknown user"));
+
+/// What happened when checking a login's username and password
+#[derive(Debug, PartialEq, Eq)]
+pub enum DashboardLoginAttempt {
+    WrongCredentials,
+    /// The password was right, but the user has two-factor authentication enabled so the session
+    /// has to complete a challenge with attempt_complete_dashboard_login_challenge
+    ChallengeRequired,
+    LoggedIn,
+}
+
+/// Checks the username and password. If correct, the session is either logged in with a new token
+/// for the user, or given a challenge if the user has two-factor authentication enabled
 pub async fn attempt_log_in_dashboard_session(
     db: &mut PgConnection,
     session: &Session,
+    username: &str,
     password: &str,
-) -> Result<bool, Box<dyn StdError>> {
+    client: DashboardLoginClient<'_>,
+) -> Result<DashboardLoginAttempt, Box<dyn StdError>> {
     let argon2 = setup_weak_argon2();
 
-    if !argon2_check_key_against_hash(&argon2, password, &CONFIG.admin_password_hash) {
-        return Ok(false);
+    let user = get_dashboard_user_by_username(&mut *db, username.trim()).await?;
+
+    let Some(user) = user else {
+        argon2_check_key_against_hash(&argon2, password, &UNKNOWN_USER_PASSWORD_HASH);
+        return Ok(DashboardLoginAttempt::WrongCredentials);
+    };
+
+    if !argon2_check_key_against_hash(&argon2, password, &user.password_hash) {
+        return Ok(DashboardLoginAttempt::WrongCredentials);
     }
 
+    if user.totp_enabled {
+        let GenerateLoginTokenAndHash {
+            token,
+            hash: token_hash,
+        } = generate_dashboard_login_token();
+
+        let challenge_row =
+            create_dashboard_login_challenge(&mut *db, &user.id, &token_hash).await?;
+
+        session.set(
+            DASHBOARD_SESSION_CHALLENGE_DATA_KEY,
+            &DashboardSessionLoginTokenData {
+                id: challenge_row.id,
+                token,
+            },
+        );
+
+        return Ok(DashboardLoginAttempt::ChallengeRequired);
+    }
+
+    log_in_dashboard_session(db, session, &user.id, client).await?;
+
+    Ok(DashboardLoginAttempt::LoggedIn)
+}
+
+async fn log_in_dashboard_session(
+    db: &mut PgConnection,
+    session: &Session,
+    user_id: &Uuid,
+    client: DashboardLoginClient<'_>,
+) -> sqlx::Result<()> {
     let GenerateLoginTokenAndHash {
         token,
         hash: token_hash,
     } = generate_dashboard_login_token();
 
-    let token_row = create_dashboard_login_token(&mut *db, &token_hash).await?;
+    let token_row = create_dashboard_login_token(
+        &mut *db,
+        &token_hash,
+        user_id,
+        client.ip_address,
+        client.user_agent,
+    )
+    .await?;
 
     session.set(
         DASHBOARD_SESSION_TOKEN_DATA_KEY,
         &DashboardSessionLoginTokenData {
             id: token_row.id,
-            token: token.to_string(),
+            token,
         },
     );
+    rotate_csrf_token(session);
+
+    Ok(())
+}
+
+/// The user of the session's login challenge, None if it doesn't have one or it has expired
+pub async fn get_dashboard_login_challenge_user(
+    db: &mut PgConnection,
+    session: &Session,
+) -> sqlx::Result<Option<DashboardUser>> {
+    let Some(challenge_data) =
+        session.get::<DashboardSessionLoginTokenData>(DASHBOARD_SESSION_CHALLENGE_DATA_KEY)
+    else {
+        return Ok(None);
+    };
+
+    let Some(challenge_row) = get_dashboard_login_challenge(
+        &mut *db,
+        &challenge_data.id,
+        Utc::now() - LOGIN_CHALLENGE_EXPIRES_AFTER,
+    )
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    if !argon2_check_key_against_hash(
+        &setup_weak_argon2(),
+        &challenge_data.token,
+        &challenge_row.token_hash,
+    ) {
+        return Ok(None);
+    }
 
-    Ok(true)
+    get_dashboard_user(&mut *db, &challenge_row.user_id).await
+}
+
+/// Checks the code against the user's TOTP secret, or their unused recovery codes if it isn't a
+/// TOTP code. A code which matches is used up
+pub async fn check_dashboard_second_factor(
+    db: &mut PgConnection,
+    user: &DashboardUser,
+    code: &str,
+    now: DateTime<Utc>,
+) -> sqlx::Result<bool> {
+    let Some(totp_secret) = &user.totp_secret else {
+        return Ok(false);
+    };
+
+    if let Some(step) = verify_totp_code(totp_secret, code, now, user.totp_last_used_step) {
+        return use_dashboard_user_totp_step(&mut *db, &user.id, step).await;
+    }
+
+    let code = normalize_recovery_code(code);
+    if code.is_empty() {
+        return Ok(false);
+    }
+
+    let argon2 = setup_weak_argon2();
+    for recovery_code in get_unused_dashboard_recovery_codes(&mut *db, &user.id).await? {
+        if argon2_check_key_against_hash(&argon2, &code, &recovery_code.code_hash) {
+            return use_dashboard_recovery_code(&mut *db, &recovery_code.id).await;
+        }
+    }
+
+    Ok(false)
+}
+
+/// Checks the code for the session's login challenge, logging the session in if it's correct.
+/// Returns None if the session has no challenge, e.g. because it expired
+pub async fn attempt_complete_dashboard_login_challenge(
+    db: &mut PgConnection,
+    session: &Session,
+    code: &str,
+    client: DashboardLoginClient<'_>,
+) -> Result<Option<bool>, Box<dyn StdError>> {
+    let Some(user) = get_dashboard_login_challenge_user(&mut *db, session).await? else {
+        return Ok(None);
+    };
+
+    if !user.totp_enabled
+        || !check_dashboard_second_factor(&mut *db, &user, code, Utc::now()).await?
+    {
+        return Ok(Some(false));
+    }
+
+    if let Some(challenge_data) =
+        session.get::<DashboardSessionLoginTokenData>(DASHBOARD_SESSION_CHALLENGE_DATA_KEY)
+    {
+        delete_dashboard_login_challenge(
+            &mut *db,
+            &challenge_data.id,
+            Utc::now() - LOGIN_CHALLENGE_EXPIRES_AFTER,
+        )
+        .await?;
+    }
+    session.remove(DASHBOARD_SESSION_CHALLENGE_DATA_KEY);
+
+    log_in_dashboard_session(db, session, &user.id, client).await?;
+
+    Ok(Some(true))
+}
+
+/// Deletes challenges which can't be completed anymore, returning how many were deleted
+pub async fn prune_expired_dashboard_login_challenges(db: &mut PgConnection) -> sqlx::Result<u64> {
+    delete_expired_dashboard_login_challenges(db, Utc::now() - LOGIN_CHALLENGE_EXPIRES_AFTER).await
+}
+
+/// Deletes the session's login token so it can't be used again, even if the cookie was copied, and
+/// clears the session
+pub async fn log_out_dashboard_session(
+    db: &mut PgConnection,
+    session: &Session,
+) -> sqlx::Result<()> {
+    if let Some(token_data) =
+        session.get::<DashboardSessionLoginTokenData>(DASHBOARD_SESSION_TOKEN_DATA_KEY)
+        && let Some(token_row) = get_dashboard_login_token(&mut *db, &token_data.id).await?
+        && argon2_check_key_against_hash(
+            &setup_weak_argon2(),
+            &token_data.token,
+            &token_row.token_hash,
+        )
+    {
+        delete_dashboard_login_token(&mut *db, &token_row.id, &token_row.user_id).await?;
+    }
+
+    session.purge();
+
+    Ok(())
+}
+
+/// Hashes recovery codes for storing, after normalizing them so they can be typed in any case
+pub fn hash_recovery_codes(codes: &[String]) -> Vec<String> {
+    let argon2 = setup_weak_argon2();
+
+    codes
+        .iter()
+        .map(|code| argon2_hash_key(&argon2, &normalize_recovery_code(code)))
+        .collect()
 }
 
 pub struct GenerateLoginTokenAndHash {
@@ -62,9 +344,12 @@ pub fn generate_dashboard_login_token() -> GenerateLoginTokenAndHash {
     GenerateLoginTokenAndHash { token, hash }
 }
 
+/// Redirects to the login page unless the session is logged in, otherwise adds the logged in
+/// DashboardUser to the request's data for handlers to check permissions with, along with the
+/// session's DashboardSessionId
 pub async fn dashboard_auth_middleware<E: Endpoint>(
     next: E,
-    req: poem::Request,
+    mut req: poem::Request,
 ) -> poem::Result<E::Output> {
     macro_rules! login_redirect_err {
         () => {
@@ -96,5 +381,35 @@ pub async fn dashboard_auth_middleware<E: Endpoint>(
         login_redirect_err!();
     }
 
+    let Some(user) = get_dashboard_user(&mut db, &token_row.user_id)
+        .await
+        .unwrap()
+    else {
+        login_redirect_err!();
+    };
+
+    if token_row.last_used_at <= Utc::now() - LOGIN_TOKEN_TOUCH_INTERVAL {
+        touch_dashboard_login_token(&mut db, &token_row.id)
+            .await
+            .unwrap();
+        session.renew();
+    }
+
+    drop(db);
+    req.set_data(user);
+    req.set_data(DashboardSessionId(token_row.id));
+
     next.call(req).await
 }
+
+/// Rejects the request with a 403 Forbidden unless the user is allowed to do what's being checked
+pub fn require_dashboard_permission(allowed: bool) -> poem::Result<()> {
+    if allowed {
+        Ok(())
+    } else {
+        Err(poem::Error::from_string(
+            "You don't have permission to do this",
+            StatusCode::FORBIDDEN,
+        ))
+    }
+}
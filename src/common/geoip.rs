@@ -0,0 +1,107 @@
+use std::{net::IpAddr, sync::Arc};
+
+use maxminddb::{MaxMindDbError, Reader, geoip2};
+
+/// Where a visit came from, as far as the GeoIP database knows
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VisitLocation {
+    /// ISO 3166-1 alpha-2 code
+    pub country: Option<String>,
+    /// English name of the largest subdivision, like a state or province
+    pub region: Option<String>,
+    /// English name
+    pub city: Option<String>,
+}
+
+/// Resolves IP addresses to locations with a local MaxMind City database (GeoIP2 or GeoLite2).
+/// Without a database every location is unknown
+#[derive(Clone, Default)]
+pub struct GeoIp {
+    reader: Option<Arc<Reader<Vec<u8>>>>,
+}
+
+impl GeoIp {
+    /// Reads the whole database into memory, if a path is specified
+    pub fn open(database_path: Option<&str>) -> Result<Self, MaxMindDbError> {
+        let reader = database_path.map(Reader::open_readfile).transpose()?;
+
+        Ok(GeoIp {
+            reader: reader.map(Arc::new),
+        })
+    }
+
+    pub fn locate(&self, ip_address: IpAddr) -> VisitLocation {
+        let Some(reader) = &self.reader else {
+            return VisitLocation::default();
+        };
+
+        let city = match reader
+            .lookup(ip_address)
+            .and_then(|result| result.decode::<geoip2::City>())
+        {
+            Ok(Some(city)) => city,
+            Ok(None) => return VisitLocation::default(),
+            Err(error) => {
+                tracing::warn!("Failed to look up the location of {ip_address}: {error}");
+                return VisitLocation::default();
+            }
+        };
+
+        VisitLocation {
+            country: city.country.iso_code.map(String::from),
+            region: city
+                .subdivisions
+                .first()
+                .and_then(|subdivision| subdivision.names.english)
+                .map(String::from),
+            city: city.city.names.english.map(String::from),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::CONFIG;
+
+    use super::*;
+
+    fn location(country: &str, region: &str, city: &str) -> VisitLocation {
+        VisitLocation {
+            country: Some(country.to_string()),
+            region: Some(region.to_string()),
+            city: Some(city.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_locate() {
+        let geoip = GeoIp::open(CONFIG.geoip_database_path.as_deref()).unwrap();
+
+        assert_eq!(
+            geoip.locate("203.0.113.195".parse().unwrap()),
+            location("GB", "England", "London")
+        );
+        assert_eq!(
+            geoip.locate("198.51.100.7".parse().unwrap()),
+            location("US", "California", "San Francisco")
+        );
+        assert_eq!(
+            geoip.locate("2001:db8::1".parse().unwrap()),
+            location("DE", "Berlin", "Berlin")
+        );
+        assert_eq!(
+            geoip.locate("192.0.2.1".parse().unwrap()),
+            VisitLocation::default()
+        );
+    }
+
+    #[test]
+    fn test_locate_without_database() {
+        let geoip = GeoIp::open(None).unwrap();
+
+        assert_eq!(
+            geoip.locate("203.0.113.195".parse().unwrap()),
+            VisitLocation::default()
+        );
+    }
+}
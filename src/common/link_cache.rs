@@ -0,0 +1,233 @@
+use std::time::{Duration, Instant};
+
+use moka::{Expiry, sync::Cache};
+use sqlx::PgPool;
+
+use crate::db::{
+    links::{Link, get_link},
+    platforms::{Platform, get_platform},
+};
+
+/// A link along with its platform, which redirects need for the default redirect type and the
+/// unavailable link fallbacks
+#[derive(Debug, Clone)]
+pub struct CachedLink {
+    pub link: Link,
+    pub platform: Platform,
+}
+
+struct LinkCacheExpiry {
+    ttl: Duration,
+    not_found_ttl: Duration,
+}
+
+impl Expiry<String, Option<CachedLink>> for LinkCacheExpiry {
+    fn expire_after_create(
+        &self,
+        _slug: &String,
+        link: &Option<CachedLink>,
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        match link {
+            Some(_) => Some(self.ttl),
+            None => Some(self.not_found_ttl),
+        }
+    }
+}
+
+/// In-memory cache of links and their platforms by slug, which also remembers slugs that don't
+/// exist. Only the current process is invalidated when links or platforms change, so other
+/// instances may serve stale links until the TTL runs out
+#[derive(Clone)]
+pub struct LinkCache {
+    cache: Cache<String, Option<CachedLink>>,
+}
+
+impl LinkCache {
+    /// A capacity of zero disables the cache
+    pub fn new(capacity: u64, ttl: Duration, not_found_ttl: Duration) -> Self {
+        LinkCache {
+            cache: Cache::builder()
+                .max_capacity(capacity)
+                .expire_after(LinkCacheExpiry { ttl, not_found_ttl })
+                .build(),
+        }
+    }
+
+    /// Cached version of db::links::get_link which also gets the link's platform, only acquiring a
+    /// connection if the slug isn't cached. Links whose platform was deleted are treated as not
+    /// existing
+    pub async fn get_link(&self, db_pool: &PgPool, slug: &str) -> sqlx::Result<Option<CachedLink>> {
+        if let Some(cached_link) = self.cache.get(slug) {
+            return Ok(cached_link);
+        }
+
+        let mut db = db_pool.acquire().await?;
+
+        let cached_link = match get_link(&mut db, slug).await? {
+            None => None,
+            Some(link) => get_platform(&mut db, &link.platform_id)
+                .await?
+                .map(|platform| CachedLink { link, platform }),
+        };
+        self.cache.insert(slug.to_string(), cached_link.clone());
+
+        Ok(cached_link)
+    }
+
+    /// Should be called whenever the link for the slug is created, updated, or deleted
+    pub fn invalidate(&self, slug: &str) {
+        self.cache.invalidate(slug);
+    }
+
+    /// Should be called whenever a platform is updated or deleted
+    pub fn invalidate_all(&self) {
+        self.cache.invalidate_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{
+        links::{LinkSettings, UpdateLinkData, create_link, update_link},
+        platforms::{UpdatePlatformData, create_platform, delete_platform, update_platform},
+    };
+
+    use super::*;
+
+    fn test_link_cache() -> LinkCache {
+        LinkCache::new(100, Duration::from_secs(3600), Duration::from_secs(3600))
+    }
+
+    #[sqlx::test]
+    async fn test_link_cache_until_invalidated(db_pool: PgPool) {
+        let link_cache = test_link_cache();
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (_, platform) = create_platform(&mut db, "Cache").await.unwrap();
+        let link = create_link(
+            &mut db,
+            &platform.id,
+            Some("cached".to_string()),
+            "https://example.com/".to_string(),
+            None,
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+
+        let cached_link = link_cache.get_link(&db_pool, &link.slug).await.unwrap();
+        assert_eq!(cached_link.unwrap().link.url, "https://example.com/");
+
+        update_link(
+            &mut db,
+            &link.platform_id,
+            &link.slug,
+            &UpdateLinkData {
+                url: Some("https://iapetus11.me/".to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let cached_link = link_cache.get_link(&db_pool, &link.slug).await.unwrap();
+        assert_eq!(cached_link.unwrap().link.url, "https://example.com/");
+
+        link_cache.invalidate(&link.slug);
+
+        let cached_link = link_cache.get_link(&db_pool, &link.slug).await.unwrap();
+        assert_eq!(cached_link.unwrap().link.url, "https://iapetus11.me/");
+    }
+
+    #[sqlx::test]
+    async fn test_link_cache_not_found(db_pool: PgPool) {
+        let link_cache = test_link_cache();
+        let mut db = db_pool.acquire().await.unwrap();
+
+        assert!(
+            link_cache
+                .get_link(&db_pool, "later")
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let (_, platform) = create_platform(&mut db, "Cache").await.unwrap();
+        create_link(
+            &mut db,
+            &platform.id,
+            Some("later".to_string()),
+            "https://example.com/".to_string(),
+            None,
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+
+        assert!(
+            link_cache
+                .get_link(&db_pool, "later")
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        link_cache.invalidate_all();
+
+        assert!(
+            link_cache
+                .get_link(&db_pool, "later")
+                .await
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_link_cache_includes_platform(db_pool: PgPool) {
+        let link_cache = test_link_cache();
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (_, platform) = create_platform(&mut db, "Cache").await.unwrap();
+        create_link(
+            &mut db,
+            &platform.id,
+            Some("platform".to_string()),
+            "https://example.com/".to_string(),
+            None,
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+
+        let cached_link = link_cache.get_link(&db_pool, "platform").await.unwrap();
+        assert_eq!(cached_link.unwrap().platform.name, "Cache");
+
+        update_platform(
+            &mut db,
+            &platform.id,
+            &UpdatePlatformData {
+                name: Some("Renamed".to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        link_cache.invalidate_all();
+
+        let cached_link = link_cache.get_link(&db_pool, "platform").await.unwrap();
+        assert_eq!(cached_link.unwrap().platform.name, "Renamed");
+
+        delete_platform(&mut db, &platform.id).await.unwrap();
+        link_cache.invalidate_all();
+
+        assert!(
+            link_cache
+                .get_link(&db_pool, "platform")
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+}
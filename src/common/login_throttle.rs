@@ -0,0 +1,322 @@
+use std::net::{IpAddr, Ipv6Addr};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use sqlx::{Connection, PgConnection};
+use uuid::Uuid;
+
+use crate::db::dashboard_login_attempts::{
+    create_dashboard_login_attempt, delete_dashboard_login_attempt, get_ip_login_failures,
+    get_nth_latest_login_failure_at, lock_dashboard_login_attempts, prune_dashboard_login_attempts,
+    set_dashboard_login_attempt_succeeded,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct LoginThrottleSettings {
+    /// Failed attempts an IP address can make before it's locked out after each further one
+    pub free_attempts_per_ip: i64,
+    /// How long an IP address is locked out after its first failed attempt over the free ones,
+    /// doubling with each one after that
+    pub base_lockout: TimeDelta,
+    pub max_lockout: TimeDelta,
+    /// Failed attempts from all IP addresses within global_window after which nobody can log in
+    /// until the window has passed, in case attempts are spread over many IP addresses
+    pub global_max_failures: i64,
+    pub global_window: TimeDelta,
+}
+
+/// Failed attempts older than this no longer count towards lockouts
+const FAILURE_MEMORY: TimeDelta = TimeDelta::days(1);
+
+pub enum LoginAttemptReservation {
+    /// The attempt's id, it counts as failed unless it's marked as succeeded or cancelled
+    Reserved(Uuid),
+    LockedOut {
+        until: DateTime<Utc>,
+    },
+}
+
+/// What attempts are throttled by, IPv6 addresses are throttled by their /64 prefix since a single
+/// client is usually given a whole /64
+pub fn login_throttle_ip_address(ip_address: IpAddr) -> String {
+    match ip_address {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => {
+            let [a, b, c, d, ..] = ip.segments();
+            format!("{}/64", Ipv6Addr::new(a, b, c, d, 0, 0, 0, 0))
+        }
+    }
+}
+
+/// Rounded up to whole seconds or minutes, e.g. "1 minute" or "45 seconds"
+pub fn format_wait(wait: TimeDelta) -> String {
+    let seconds = (wait.num_milliseconds() + 999) / 1000;
+
+    let (amount, unit) = if seconds > 60 {
+        ((seconds + 59) / 60, "minute")
+    } else {
+        (seconds.max(1), "second")
+    };
+
+    format!("{amount} {unit}{}", if amount == 1 { "" } else { "s" })
+}
+
+/// How long an IP address is locked out after its latest failed attempt
+fn ip_lockout(failures: i64, settings: &LoginThrottleSettings) -> Option<TimeDelta> {
+    let lockouts = failures - settings.free_attempts_per_ip;
+
+    if lockouts < 1 {
+        return None;
+    }
+
+    let lockout = 2_i32
+        .checked_pow((lockouts - 1).min(30) as u32)
+        .and_then(|multiplier| settings.base_lockout.checked_mul(multiplier))
+        .unwrap_or(settings.max_lockout);
+
+    Some(lockout.min(settings.max_lockout))
+}
+
+/// When the IP address can attempt to log in again, None if it can now
+pub async fn get_login_locked_until(
+    db: &mut PgConnection,
+    ip_address: Option<&str>,
+    now: DateTime<Utc>,
+    settings: &LoginThrottleSettings,
+) -> sqlx::Result<Option<DateTime<Utc>>> {
+    let ip_failures = get_ip_login_failures(&mut *db, ip_address, now - FAILURE_MEMORY).await?;
+
+    let ip_locked_until = ip_failures
+        .last_at
+        .zip(ip_lockout(ip_failures.count, settings))
+        .map(|(last_at, lockout)| last_at + lockout);
+
+    let globally_locked_until = get_nth_latest_login_failure_at(
+        &mut *db,
+        now - settings.global_window,
+        settings.global_max_failures,
+    )
+    .await?
+    .map(|at| at + settings.global_window);
+
+    Ok(ip_locked_until
+        .max(globally_locked_until)
+        .filter(|locked_until| *locked_until > now))
+}
+
+/// Records an attempt to log in as the username unless the IP address is locked out. The attempt
+/// counts as failed from the start, so concurrent attempts can't all get in before any of them fail
+pub async fn reserve_login_attempt(
+    db: &mut PgConnection,
+    ip_address: Option<&str>,
+    username: &str,
+    now: DateTime<Utc>,
+    settings: &LoginThrottleSettings,
+) -> sqlx::Result<LoginAttemptReservation> {
+    let mut tx = db.begin().await?;
+
+    lock_dashboard_login_attempts(&mut tx).await?;
+
+    if let Some(locked_until) = get_login_locked_until(&mut tx, ip_address, now, settings).await? {
+        return Ok(LoginAttemptReservation::LockedOut {
+            until: locked_until,
+        });
+    }
+
+    let attempt_id =
+        create_dashboard_login_attempt(&mut tx, ip_address, username, now, false).await?;
+
+    tx.commit().await?;
+
+    Ok(LoginAttemptReservation::Reserved(attempt_id))
+}
+
+/// Marks the reserved attempt as succeeded, which resets the IP address's failed attempts for the
+/// same username
+pub async fn login_attempt_succeeded(db: &mut PgConnection, attempt_id: &Uuid) -> sqlx::Result<()> {
+    set_dashboard_login_attempt_succeeded(db, attempt_id).await
+}
+
+/// Forgets the reserved attempt, for attempts which neither failed nor succeeded
+pub async fn cancel_login_attempt(db: &mut PgConnection, attempt_id: &Uuid) -> sqlx::Result<()> {
+    delete_dashboard_login_attempt(db, attempt_id).await
+}
+
+/// Deletes attempts which can't count towards lockouts anymore, returning how many were deleted
+pub async fn prune_login_attempts(
+    db: &mut PgConnection,
+    now: DateTime<Utc>,
+    settings: &LoginThrottleSettings,
+) -> sqlx::Result<u64> {
+    prune_dashboard_login_attempts(db, now - FAILURE_MEMORY.max(settings.global_window)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use crate::common::testing::db::PgPoolConn;
+
+    use super::*;
+
+    fn settings() -> LoginThrottleSettings {
+        LoginThrottleSettings {
+            free_attempts_per_ip: 2,
+            base_lockout: TimeDelta::seconds(30),
+            max_lockout: TimeDelta::minutes(5),
+            global_max_failures: 5,
+            global_window: TimeDelta::minutes(15),
+        }
+    }
+
+    #[test]
+    fn test_ip_lockout() {
+        let lockouts = (0..9)
+            .map(|failures| ip_lockout(failures, &settings()).map(|l| l.num_seconds()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            lockouts,
+            vec![
+                None,
+                None,
+                None,
+                Some(30),
+                Some(60),
+                Some(120),
+                Some(240),
+                Some(300),
+                Some(300)
+            ]
+        );
+        assert_eq!(
+            ip_lockout(i64::MAX, &settings()),
+            Some(TimeDelta::minutes(5))
+        );
+    }
+
+    #[test]
+    fn test_format_wait() {
+        assert_eq!(format_wait(TimeDelta::milliseconds(200)), "1 second");
+        assert_eq!(format_wait(TimeDelta::seconds(30)), "30 seconds");
+        assert_eq!(format_wait(TimeDelta::seconds(60)), "60 seconds");
+        assert_eq!(format_wait(TimeDelta::seconds(61)), "2 minutes");
+        assert_eq!(format_wait(TimeDelta::hours(1)), "60 minutes");
+    }
+
+    #[test]
+    fn test_login_throttle_ip_address() {
+        assert_eq!(
+            login_throttle_ip_address("203.0.113.195".parse().unwrap()),
+            "203.0.113.195"
+        );
+        assert_eq!(
+            login_throttle_ip_address("2001:db8:85a3:8d3:1319:8a2e:370:7348".parse().unwrap()),
+            "2001:db8:85a3:8d3::/64"
+        );
+    }
+
+    async fn assert_reserved(
+        db: &mut PgConnection,
+        ip_address: Option<&str>,
+        username: &str,
+        now: DateTime<Utc>,
+    ) -> Uuid {
+        match reserve_login_attempt(db, ip_address, username, now, &settings())
+            .await
+            .unwrap()
+        {
+            LoginAttemptReservation::Reserved(attempt_id) => attempt_id,
+            LoginAttemptReservation::LockedOut { .. } => panic!("attempt was locked out"),
+        }
+    }
+
+    #[sqlx::test]
+    async fn test_reserve_login_attempt(mut db: PgPoolConn) {
+        let settings = settings();
+        let now = Utc.with_ymd_and_hms(2025, 1, 1, 12, 0, 0).unwrap();
+        let ip = Some("1.1.1.1");
+
+        // Reserved attempts count as failed
+        for _ in 0..3 {
+            assert_reserved(&mut db, ip, "admin", now).await;
+        }
+
+        assert_eq!(
+            get_login_locked_until(&mut db, ip, now, &settings)
+                .await
+                .unwrap(),
+            Some(now + TimeDelta::seconds(30))
+        );
+        assert!(matches!(
+            reserve_login_attempt(&mut db, ip, "admin", now, &settings)
+                .await
+                .unwrap(),
+            LoginAttemptReservation::LockedOut { until } if until == now + TimeDelta::seconds(30)
+        ));
+
+        // Succeeding as another user doesn't reset the IP address's failures
+        let later = now + TimeDelta::seconds(30);
+        let attempt_id = assert_reserved(&mut db, ip, "other", later).await;
+        login_attempt_succeeded(&mut db, &attempt_id).await.unwrap();
+        assert_reserved(&mut db, ip, "admin", later).await;
+        assert_eq!(
+            get_login_locked_until(&mut db, ip, later, &settings)
+                .await
+                .unwrap(),
+            Some(later + TimeDelta::seconds(60))
+        );
+
+        // But succeeding as the same user does, and cancelled attempts don't count
+        let later = later + TimeDelta::seconds(60);
+        let attempt_id = assert_reserved(&mut db, ip, "Admin", later).await;
+        login_attempt_succeeded(&mut db, &attempt_id).await.unwrap();
+        for _ in 0..5 {
+            let attempt_id = assert_reserved(&mut db, ip, "admin", later).await;
+            cancel_login_attempt(&mut db, &attempt_id).await.unwrap();
+        }
+        assert_eq!(
+            get_login_locked_until(&mut db, ip, later, &settings)
+                .await
+                .unwrap(),
+            None
+        );
+
+        // Other IP addresses aren't locked out until there are too many failures overall
+        assert_reserved(&mut db, Some("2.2.2.2"), "admin", now).await;
+
+        assert_eq!(
+            get_login_locked_until(&mut db, Some("3.3.3.3"), now, &settings)
+                .await
+                .unwrap(),
+            Some(now + TimeDelta::minutes(15))
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_reserve_login_attempt_concurrently(db_pool: sqlx::PgPool) {
+        let now = Utc::now();
+
+        let tasks = (0..10)
+            .map(|_| {
+                let db_pool = db_pool.clone();
+
+                tokio::spawn(async move {
+                    let mut db = db_pool.acquire().await.unwrap();
+                    reserve_login_attempt(&mut db, Some("1.1.1.1"), "admin", now, &settings())
+                        .await
+                        .unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut reserved_count = 0;
+        for task in tasks {
+            if let LoginAttemptReservation::Reserved(_) = task.await.unwrap() {
+                reserved_count += 1;
+            }
+        }
+
+        // Two free attempts, and one more which causes the lockout
+        assert_eq!(reserved_count, 3);
+    }
+}
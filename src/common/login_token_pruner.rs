@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use sqlx::PgConnection;
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+
+use crate::{
+    common::{
+        dashboard_auth::prune_expired_dashboard_login_challenges,
+        login_throttle::prune_login_attempts,
+    },
+    config::CONFIG,
+    db::dashboard_login_token::delete_expired_dashboard_login_tokens,
+};
+
+/// Deletes expired dashboard login tokens and second factor challenges, and login attempts which
+/// no longer count towards lockouts. Returns the total number of rows deleted
+pub async fn prune_login_data(db: &mut PgConnection) -> sqlx::Result<u64> {
+    let tokens = delete_expired_dashboard_login_tokens(&mut *db).await?;
+    let challenges = prune_expired_dashboard_login_challenges(&mut *db).await?;
+    let attempts =
+        prune_login_attempts(&mut *db, Utc::now(), &CONFIG.dashboard_login_throttle).await?;
+
+    Ok(tokens + challenges + attempts)
+}
+
+/// Spawns a background task which prunes dashboard login data every interval, starting immediately
+pub fn start_login_token_pruner(db_pool: sqlx::PgPool, interval: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            let result = match db_pool.acquire().await {
+                Ok(mut db) => prune_login_data(&mut db).await,
+                Err(error) => Err(error),
+            };
+
+            match result {
+                Ok(0) => {}
+                Ok(pruned) => tracing::info!(
+                    "Pruned {pruned} expired dashboard login tokens, challenges and attempts"
+                ),
+                Err(error) => tracing::error!("Failed to prune dashboard login data: {error}"),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use crate::{
+        common::testing::db::PgPoolConn,
+        db::{
+            dashboard_login_attempts::create_dashboard_login_attempt,
+            dashboard_login_challenges::create_dashboard_login_challenge,
+            dashboard_login_token::create_dashboard_login_token,
+            dashboard_users::{DashboardUserRole, create_dashboard_user},
+        },
+    };
+
+    use super::*;
+
+    #[sqlx::test]
+    async fn test_prune_login_data(mut db: PgPoolConn) {
+        let user = create_dashboard_user(&mut db, "alice", "hash", DashboardUserRole::Owner)
+            .await
+            .unwrap();
+
+        // One of each is old enough to be pruned
+        for token_hash in ["hash1", "hash2"] {
+            create_dashboard_login_token(&mut db, token_hash, &user.id, None, None)
+                .await
+                .unwrap();
+            create_dashboard_login_challenge(&mut db, &user.id, token_hash)
+                .await
+                .unwrap();
+        }
+        let long_ago = Utc::now() - TimeDelta::days(30);
+        sqlx::query!(
+            "UPDATE dashboard_login_tokens SET last_used_at = $1 WHERE token_hash = 'hash1'",
+            long_ago,
+        )
+        .execute(&mut *db)
+        .await
+        .unwrap();
+        sqlx::query!(
+            "UPDATE dashboard_login_challenges SET created_at = $1 WHERE token_hash = 'hash1'",
+            long_ago,
+        )
+        .execute(&mut *db)
+        .await
+        .unwrap();
+        for at in [long_ago, Utc::now()] {
+            create_dashboard_login_attempt(&mut db, Some("1.1.1.1"), "admin", at, false)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(prune_login_data(&mut db).await.unwrap(), 3);
+        assert_eq!(prune_login_data(&mut db).await.unwrap(), 0);
+    }
+}
@@ -1,8 +1,23 @@
 pub mod argon2;
 pub mod cli;
+pub mod client_ip;
+pub mod csrf;
 pub mod dashboard_auth;
+pub mod geoip;
+pub mod link_cache;
+pub mod link_validation;
+pub mod login_throttle;
+pub mod login_token_pruner;
 pub mod platform_auth;
+pub mod rate_limit;
+pub mod referrer;
+pub mod totp;
+pub mod user_agent;
 pub mod validation;
+pub mod visit_privacy;
+pub mod visit_pruner;
+pub mod visit_recorder;
+pub mod visit_rollup;
 
 #[cfg(test)]
 pub mod testing;
@@ -5,7 +5,10 @@ use poem::{
 use rand::distr::{Alphanumeric, SampleString};
 
 use crate::{
-    common::argon2::{argon2_check_key_against_hash, argon2_hash_key, setup_strong_argon2},
+    common::{
+        argon2::{argon2_check_key_against_hash, argon2_hash_key, setup_strong_argon2},
+        rate_limit::rate_limit_platform_request,
+    },
     db::platforms::{Platform, get_platform},
 };
 
@@ -73,6 +76,10 @@ impl<'a> poem::FromRequest<'a> for AuthedPlatform {
             ));
         };
 
+        // Requests with the wrong API key count too, and throttled requests are rejected before the
+        // key is hashed, so the platform's rate limit also limits guessing and hashing its key
+        rate_limit_platform_request(req, &platform)?;
+
         if !check_platform_api_key(&platform, basic_auth.password()) {
             return Err(poem::Error::from_string(
                 "invalid credentials",
@@ -0,0 +1,178 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use poem::{
+    Endpoint, IntoResponse, Response,
+    http::{HeaderMap, StatusCode, header},
+};
+use uuid::Uuid;
+
+use crate::db::platforms::Platform;
+
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// State of a platform's token bucket after one of its requests
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitStatus {
+    /// Requests per minute, which is also how many requests can be made at once
+    pub limit: u32,
+    pub remaining: u32,
+    /// How long until the bucket is full again
+    pub reset_after: Duration,
+    /// How long until the next request is allowed, None if this request was allowed
+    pub retry_after: Option<Duration>,
+}
+
+/// Token buckets of each platform's API requests, which refill continuously at the platform's rate
+/// limit. Only limits the requests to the current process, so each instance has its own buckets
+#[derive(Clone, Default)]
+pub struct ApiRateLimiter {
+    buckets: Arc<Mutex<HashMap<Uuid, TokenBucket>>>,
+}
+
+impl ApiRateLimiter {
+    /// Takes a token from the platform's bucket if it has one
+    pub fn acquire(
+        &self,
+        platform_id: Uuid,
+        requests_per_minute: u32,
+        now: Instant,
+    ) -> RateLimitStatus {
+        let capacity = requests_per_minute as f64;
+        let tokens_per_second = capacity / 60.0;
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(platform_id).or_insert(TokenBucket {
+            tokens: capacity,
+            updated_at: now,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.updated_at);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * tokens_per_second).min(capacity);
+        bucket.updated_at = now;
+
+        let retry_after = if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / tokens_per_second,
+            ))
+        };
+
+        RateLimitStatus {
+            limit: requests_per_minute,
+            remaining: bucket.tokens as u32,
+            reset_after: Duration::from_secs_f64((capacity - bucket.tokens) / tokens_per_second),
+            retry_after,
+        }
+    }
+}
+
+/// Where the status of the request's platform is put for rate_limit_headers_middleware
+#[derive(Clone, Default)]
+struct RateLimitStatusSlot(Arc<Mutex<Option<RateLimitStatus>>>);
+
+/// Rate limits the platform's request, if the platform has a rate limit
+pub fn rate_limit_platform_request(req: &poem::Request, platform: &Platform) -> poem::Result<()> {
+    let (Some(requests_per_minute), Some(rate_limiter)) = (
+        platform.api_requests_per_minute,
+        req.data::<ApiRateLimiter>(),
+    ) else {
+        return Ok(());
+    };
+
+    let status = rate_limiter.acquire(platform.id, requests_per_minute as u32, Instant::now());
+
+    if let Some(slot) = req.extensions().get::<RateLimitStatusSlot>() {
+        *slot.0.lock().unwrap() = Some(status);
+    }
+
+    if status.retry_after.is_some() {
+        return Err(poem::Error::from_string(
+            "rate limit exceeded",
+            StatusCode::TOO_MANY_REQUESTS,
+        ));
+    }
+
+    Ok(())
+}
+
+/// Seconds rounded up, since clients shouldn't retry too early
+fn header_seconds(duration: Duration) -> u64 {
+    duration.as_secs_f64().ceil() as u64
+}
+
+fn insert_rate_limit_headers(headers: &mut HeaderMap, status: &RateLimitStatus) {
+    headers.insert("x-ratelimit-limit", status.limit.into());
+    headers.insert("x-ratelimit-remaining", status.remaining.into());
+    headers.insert(
+        "x-ratelimit-reset",
+        header_seconds(status.reset_after).into(),
+    );
+
+    if let Some(retry_after) = status.retry_after {
+        headers.insert(header::RETRY_AFTER, header_seconds(retry_after).into());
+    }
+}
+
+/// Adds the X-RateLimit-* headers (and Retry-After if the request was limited) to responses to
+/// requests which were rate limited by rate_limit_platform_request
+pub async fn rate_limit_headers_middleware<E: Endpoint>(
+    next: E,
+    mut req: poem::Request,
+) -> poem::Result<Response> {
+    let slot = RateLimitStatusSlot::default();
+    req.extensions_mut().insert(slot.clone());
+
+    let mut response = match next.call(req).await {
+        Ok(response) => response.into_response(),
+        Err(error) => error.into_response(),
+    };
+
+    if let Some(status) = *slot.0.lock().unwrap() {
+        insert_rate_limit_headers(response.headers_mut(), &status);
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_acquire() {
+        let rate_limiter = ApiRateLimiter::default();
+        let platform_id = Uuid::now_v7();
+        let start = Instant::now();
+
+        // The bucket starts full
+        for remaining in (0..3).rev() {
+            let status = rate_limiter.acquire(platform_id, 3, start);
+            assert_eq!(status.remaining, remaining);
+            assert_eq!(status.retry_after, None);
+        }
+
+        let status = rate_limiter.acquire(platform_id, 3, start);
+        assert_eq!(status.remaining, 0);
+        assert_eq!(status.retry_after, Some(Duration::from_secs(20)));
+        assert_eq!(status.reset_after, Duration::from_secs(60));
+
+        // A token is added every 20 seconds
+        let status = rate_limiter.acquire(platform_id, 3, start + Duration::from_secs(30));
+        assert_eq!(status.remaining, 0);
+        assert_eq!(status.retry_after, None);
+        assert_eq!(status.reset_after, Duration::from_secs(50));
+
+        // Other platforms have their own buckets
+        let status = rate_limiter.acquire(Uuid::now_v7(), 3, start);
+        assert_eq!(status.remaining, 2);
+    }
+}
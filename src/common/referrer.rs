@@ -0,0 +1,108 @@
+use url::Url;
+
+/// Where a visit came from according to its Referer header
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VisitReferrer {
+    /// The header's value as sent by the client
+    pub url: Option<String>,
+    /// See normalize_referrer_domain
+    pub domain: Option<String>,
+}
+
+/// Hosts of link shorteners, redirectors and mobile sites mapped to the domain of their network,
+/// so clicks from the same network are counted together
+const NETWORK_DOMAINS: [(&str, &str); 14] = [
+    ("t.co", "x.com"),
+    ("twitter.com", "x.com"),
+    ("mobile.twitter.com", "x.com"),
+    ("l.facebook.com", "facebook.com"),
+    ("lm.facebook.com", "facebook.com"),
+    ("m.facebook.com", "facebook.com"),
+    ("l.instagram.com", "instagram.com"),
+    ("l.messenger.com", "messenger.com"),
+    ("l.threads.net", "threads.net"),
+    ("out.reddit.com", "reddit.com"),
+    ("old.reddit.com", "reddit.com"),
+    ("lnkd.in", "linkedin.com"),
+    ("m.youtube.com", "youtube.com"),
+    ("away.vk.com", "vk.com"),
+];
+
+/// Lowercases the host, strips a trailing dot and leading `www.` and maps the hosts of known
+/// redirectors like t.co and l.facebook.com to the domain of their network
+pub fn normalize_referrer_domain(host: &str) -> String {
+    let host = host.trim_end_matches('.').to_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+
+    NETWORK_DOMAINS
+        .iter()
+        .find(|(redirector, _)| *redirector == host)
+        .map_or(host, |(_, network)| network)
+        .to_string()
+}
+
+/// Parses a Referer header, its domain is unknown if it isn't an absolute URL with a host
+pub fn parse_referrer(referer: &str) -> VisitReferrer {
+    let referer = referer.trim();
+
+    if referer.is_empty() {
+        return VisitReferrer::default();
+    }
+
+    VisitReferrer {
+        url: Some(referer.to_string()),
+        domain: Url::parse(referer)
+            .ok()
+            .and_then(|url| url.host_str().map(normalize_referrer_domain))
+            .filter(|domain| !domain.is_empty()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_referrer_domain() {
+        let cases = [
+            ("example.com", "example.com"),
+            ("WWW.Example.com.", "example.com"),
+            ("news.example.com", "news.example.com"),
+            ("t.co", "x.com"),
+            ("www.twitter.com", "x.com"),
+            ("l.facebook.com", "facebook.com"),
+            ("lm.facebook.com", "facebook.com"),
+            ("out.reddit.com", "reddit.com"),
+        ];
+
+        for (host, expected) in cases {
+            assert_eq!(normalize_referrer_domain(host), expected, "{host}");
+        }
+    }
+
+    #[test]
+    fn test_parse_referrer() {
+        assert_eq!(
+            parse_referrer(" https://www.Google.com/search?q=lonk "),
+            VisitReferrer {
+                url: Some("https://www.Google.com/search?q=lonk".to_string()),
+                domain: Some("google.com".to_string()),
+            }
+        );
+        assert_eq!(
+            parse_referrer("https://user@t.co:443/abc"),
+            VisitReferrer {
+                url: Some("https://user@t.co:443/abc".to_string()),
+                domain: Some("x.com".to_string()),
+            }
+        );
+        assert_eq!(
+            parse_referrer("not a url"),
+            VisitReferrer {
+                url: Some("not a url".to_string()),
+                domain: None,
+            }
+        );
+        assert_eq!(parse_referrer(""), VisitReferrer::default());
+    }
+}
@@ -1,29 +1,177 @@
-use std::sync::{Arc, OnceLock};
+use std::{
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
 
 use poem::{
     EndpointExt,
     endpoint::BoxEndpoint,
+    http::{StatusCode, header},
     middleware::{AddData, NormalizePath, TrailingSlash},
-    test::TestClient,
+    test::{TestClient, TestResponse},
     web::headers::{Authorization, authorization::Basic},
 };
 use uuid::Uuid;
 
-use crate::routes::routes;
+use crate::{
+    common::{
+        argon2::{argon2_hash_key, setup_weak_argon2},
+        geoip::GeoIp,
+        link_cache::LinkCache,
+        rate_limit::ApiRateLimiter,
+        visit_recorder::{VisitRecorder, VisitRecorderHandle},
+    },
+    config::CONFIG,
+    db::dashboard_users::{
+        DashboardUserRole, create_dashboard_user, grant_dashboard_user_platform,
+    },
+    routes::routes,
+};
 
 // Cache API test client to improve test execution speed
 static API_TEST_CLIENT: OnceLock<Arc<BoxEndpoint<'static>>> = OnceLock::new();
 
 pub fn api_test_client(db_pool: sqlx::PgPool) -> TestClient<BoxEndpoint<'static>> {
+    api_test_client_with_visit_recorder(db_pool).0
+}
+
+/// Like api_test_client, but also returns the handle for the client's visit recorder so that
+/// tests can flush recorded visits by shutting it down
+pub fn api_test_client_with_visit_recorder(
+    db_pool: sqlx::PgPool,
+) -> (TestClient<BoxEndpoint<'static>>, VisitRecorderHandle) {
     let cached_app = API_TEST_CLIENT.get_or_init(|| {
         let app = routes().with(NormalizePath::new(TrailingSlash::Always));
 
         Arc::new(app.boxed())
     });
 
-    TestClient::new(cached_app.clone().with(AddData::new(db_pool)).boxed())
+    let (visit_recorder, visit_recorder_handle) = VisitRecorder::start(
+        db_pool.clone(),
+        CONFIG.visit_queue_capacity,
+        CONFIG.visit_batch_size,
+        Duration::from_millis(CONFIG.visit_flush_interval_ms),
+    );
+
+    let client = TestClient::new(
+        cached_app
+            .clone()
+            .with(AddData::new(db_pool))
+            .with(AddData::new(visit_recorder))
+            .with(AddData::new(LinkCache::new(
+                CONFIG.link_cache_capacity,
+                Duration::from_secs(CONFIG.link_cache_ttl_seconds),
+                Duration::from_secs(CONFIG.link_cache_not_found_ttl_seconds),
+            )))
+            .with(AddData::new(
+                GeoIp::open(CONFIG.geoip_database_path.as_deref()).unwrap(),
+            ))
+            .with(AddData::new(ApiRateLimiter::default()))
+            .boxed(),
+    );
+
+    (client, visit_recorder_handle)
 }
 
 pub fn platform_auth_header(platform_id: &Uuid, api_key: &str) -> Authorization<Basic> {
     Authorization::basic(&platform_id.to_string(), api_key)
 }
+
+/// A browser's dashboard session, with the value of the Cookie header to send and the CSRF token
+/// which POST requests have to include
+pub struct DashboardTestSession {
+    pub cookie: String,
+    pub csrf_token: String,
+}
+
+/// The value of the Cookie header for requests after the response, with the cookies it set
+pub fn response_cookie(resp: &TestResponse) -> String {
+    resp.0
+        .headers()
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .filter_map(|cookie| cookie.to_str().ok()?.split(';').next())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// The CSRF token embedded in the forms of the page in the response
+async fn page_csrf_token(resp: TestResponse) -> String {
+    let body = resp.0.into_body().into_string().await.unwrap();
+
+    body.split(r#"name="csrf_token""#)
+        .nth(1)
+        .and_then(|rest| rest.split(r#"value=""#).nth(1))
+        .and_then(|rest| rest.split('"').next())
+        .unwrap()
+        .to_string()
+}
+
+/// Opens the login page like a browser would before logging in, starting a session with a CSRF
+/// token
+pub async fn dashboard_login_page_session(
+    cli: &TestClient<BoxEndpoint<'static>>,
+) -> DashboardTestSession {
+    let resp = cli.get("/admin/dashboard/login/").send().await;
+    resp.assert_status_is_ok();
+
+    let cookie = response_cookie(&resp);
+    let csrf_token = page_csrf_token(resp).await;
+
+    DashboardTestSession { cookie, csrf_token }
+}
+
+/// Creates a dashboard user with access to the platforms and logs them in through the login page,
+/// returning the session to make requests as that user with
+pub async fn dashboard_test_session(
+    cli: &TestClient<BoxEndpoint<'static>>,
+    db_pool: &sqlx::PgPool,
+    role: DashboardUserRole,
+    platform_ids: &[Uuid],
+) -> DashboardTestSession {
+    let mut db = db_pool.acquire().await.unwrap();
+
+    let username = format!("{}-{}", role.as_str(), Uuid::now_v7());
+    let user = create_dashboard_user(
+        &mut db,
+        &username,
+        &argon2_hash_key(&setup_weak_argon2(), "password"),
+        role,
+    )
+    .await
+    .unwrap();
+
+    for platform_id in platform_ids {
+        grant_dashboard_user_platform(&mut db, &user.id, platform_id)
+            .await
+            .unwrap();
+    }
+
+    drop(db);
+
+    let DashboardTestSession { cookie, csrf_token } = dashboard_login_page_session(cli).await;
+
+    let resp = cli
+        .post("/admin/dashboard/login/")
+        .header(header::COOKIE, cookie)
+        .form(&[
+            ("username", username.as_str()),
+            ("password", "password"),
+            ("csrf_token", csrf_token.as_str()),
+        ])
+        .send()
+        .await;
+    resp.assert_status(StatusCode::SEE_OTHER);
+    let cookie = response_cookie(&resp);
+
+    // Logging in gives the session a new token
+    let resp = cli
+        .get("/admin/dashboard/account/")
+        .header(header::COOKIE, &cookie)
+        .send()
+        .await;
+    resp.assert_status_is_ok();
+    let csrf_token = page_csrf_token(resp).await;
+
+    DashboardTestSession { cookie, csrf_token }
+}
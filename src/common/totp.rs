@@ -0,0 +1,231 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::{
+    RngCore,
+    distr::{Alphanumeric, SampleString},
+};
+use sha1::Sha1;
+
+/// Seconds each code is valid for, the default which authenticator apps assume
+const TOTP_PERIOD_SECONDS: i64 = 30;
+const TOTP_DIGITS: u32 = 6;
+/// Codes from this many periods before or after the current one are accepted, to allow for clock
+/// drift and slow typing
+const TOTP_ALLOWED_DRIFT: i64 = 1;
+
+const TOTP_ISSUER: &str = "LonkLink";
+
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+/// 160 bits, the size RFC 4226 recommends for HMAC-SHA1
+pub fn generate_totp_secret() -> Vec<u8> {
+    let mut secret = vec![0; 20];
+    rand::rng().fill_bytes(&mut secret);
+
+    secret
+}
+
+/// RFC 4648 base32 without padding, which is how authenticator apps expect secrets
+pub fn base32_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    let mut buffer = 0_u32;
+    let mut bits = 0;
+
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+
+    if bits > 0 {
+        encoded.push(ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+
+    encoded
+}
+
+/// The otpauth:// URI authenticator apps are provisioned with, usually by scanning it as a QR code
+pub fn totp_provisioning_uri(secret: &[u8], username: &str) -> String {
+    let label =
+        url::form_urlencoded::byte_serialize(format!("{TOTP_ISSUER}:{username}").as_bytes())
+            .collect::<String>()
+            .replace('+', "%20");
+
+    format!(
+        "otpauth://totp/{label}?secret={}&issuer={TOTP_ISSUER}&algorithm=SHA1&digits={TOTP_DIGITS}&period={TOTP_PERIOD_SECONDS}",
+        base32_encode(secret)
+    )
+}
+
+/// The RFC 6238 code for the time step, which is the number of periods since the Unix epoch
+fn totp_code(secret: &[u8], step: i64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).unwrap();
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // Dynamic truncation from RFC 4226
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let binary = u32::from_be_bytes(hash[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+
+    binary % 10_u32.pow(TOTP_DIGITS)
+}
+
+/// The code an authenticator app would currently show, for logging in as users with two-factor
+/// authentication in tests
+#[cfg(test)]
+pub fn current_totp_code(secret: &[u8]) -> String {
+    format!(
+        "{:06}",
+        totp_code(
+            secret,
+            Utc::now().timestamp().div_euclid(TOTP_PERIOD_SECONDS)
+        )
+    )
+}
+
+/// Checks the code against the time steps around now, returning the step it matched. Steps up to
+/// and including last_used_step are skipped so a code can't be used twice
+pub fn verify_totp_code(
+    secret: &[u8],
+    code: &str,
+    now: DateTime<Utc>,
+    last_used_step: Option<i64>,
+) -> Option<i64> {
+    let code = code.trim().replace(' ', "");
+
+    if code.len() != TOTP_DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let code = code.parse::<u32>().ok()?;
+    let current_step = now.timestamp().div_euclid(TOTP_PERIOD_SECONDS);
+
+    (current_step - TOTP_ALLOWED_DRIFT..=current_step + TOTP_ALLOWED_DRIFT)
+        .filter(|step| last_used_step.is_none_or(|last_used_step| *step > last_used_step))
+        .find(|step| totp_code(secret, *step) == code)
+}
+
+/// Single use codes for logging in without the authenticator app, formatted like `abcde-fghij`.
+/// They're random enough that hashing them with weak argon2 params is plenty
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code = Alphanumeric
+                .sample_string(&mut rand::rng(), 10)
+                .to_lowercase();
+
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+/// Recovery codes are compared case insensitively and with or without the dash
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.trim().to_lowercase().replace(['-', ' '], "")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    /// The SHA1 secret from the test vectors in RFC 6238 appendix B
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn test_totp_code() {
+        // The RFC's 8 digit codes, of which these are the last 6 digits
+        for (timestamp, code) in [
+            (59, 287082),
+            (1111111109, 81804),
+            (1111111111, 50471),
+            (1234567890, 5924),
+            (2000000000, 279037),
+        ] {
+            assert_eq!(
+                totp_code(RFC_SECRET, timestamp / TOTP_PERIOD_SECONDS),
+                code,
+                "{timestamp}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_verify_totp_code() {
+        let now = Utc.timestamp_opt(1111111111, 0).unwrap();
+        let step = 1111111111 / TOTP_PERIOD_SECONDS;
+
+        assert_eq!(
+            verify_totp_code(RFC_SECRET, "050471", now, None),
+            Some(step)
+        );
+        assert_eq!(
+            verify_totp_code(RFC_SECRET, " 050 471 ", now, None),
+            Some(step)
+        );
+
+        // The previous period's code is still accepted
+        let previous_code = format!("{:06}", totp_code(RFC_SECRET, step - 1));
+        assert_eq!(
+            verify_totp_code(RFC_SECRET, &previous_code, now, None),
+            Some(step - 1)
+        );
+
+        // But not once it or a later code has been used
+        assert_eq!(
+            verify_totp_code(RFC_SECRET, "050471", now, Some(step)),
+            None
+        );
+        assert_eq!(
+            verify_totp_code(RFC_SECRET, &previous_code, now, Some(step - 1)),
+            None
+        );
+
+        assert_eq!(verify_totp_code(RFC_SECRET, "050472", now, None), None);
+        assert_eq!(verify_totp_code(RFC_SECRET, "50471", now, None), None);
+        assert_eq!(verify_totp_code(RFC_SECRET, "abcdef", now, None), None);
+    }
+
+    #[test]
+    fn test_base32_encode() {
+        // Test vectors from RFC 4648, without padding
+        for (bytes, encoded) in [
+            ("", ""),
+            ("f", "MY"),
+            ("fo", "MZXQ"),
+            ("foo", "MZXW6"),
+            ("foob", "MZXW6YQ"),
+            ("fooba", "MZXW6YTB"),
+            ("foobar", "MZXW6YTBOI"),
+        ] {
+            assert_eq!(base32_encode(bytes.as_bytes()), encoded);
+        }
+    }
+
+    #[test]
+    fn test_totp_provisioning_uri() {
+        assert_eq!(
+            totp_provisioning_uri(b"foobar", "jane doe"),
+            "otpauth://totp/LonkLink%3Ajane%20doe?secret=MZXW6YTBOI&issuer=LonkLink&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let codes = generate_recovery_codes();
+
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert!(codes.iter().all(|code| code.len() == 11));
+        assert_eq!(
+            normalize_recovery_code(" ABCDE-fghij "),
+            normalize_recovery_code("abcdefghij")
+        );
+    }
+}
@@ -0,0 +1,265 @@
+/// The kind of device a visit came from, stored by as_str
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceType {
+    Desktop,
+    Mobile,
+    Tablet,
+}
+
+impl DeviceType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeviceType::Desktop => "desktop",
+            DeviceType::Mobile => "mobile",
+            DeviceType::Tablet => "tablet",
+        }
+    }
+}
+
+/// What's known about the client of a visit from its User-Agent header
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserAgentInfo {
+    /// Browser family without a version, or for known bots the bot's name
+    pub browser: Option<String>,
+    pub os: Option<String>,
+    pub device_type: Option<DeviceType>,
+    /// Crawlers, link unfurlers and HTTP libraries
+    pub is_bot: bool,
+}
+
+/// Lowercase substrings of known bots' user agents and their names, checked in order
+const KNOWN_BOTS: [(&str, &str); 26] = [
+    ("googlebot", "Googlebot"),
+    ("bingbot", "Bingbot"),
+    ("bingpreview", "Bing Preview"),
+    ("duckduckbot", "DuckDuckBot"),
+    ("yandexbot", "YandexBot"),
+    ("baiduspider", "Baiduspider"),
+    ("applebot", "Applebot"),
+    ("slackbot", "Slackbot"),
+    ("slack-imgproxy", "Slackbot"),
+    ("discordbot", "Discordbot"),
+    ("twitterbot", "Twitterbot"),
+    ("facebookexternalhit", "Facebook"),
+    ("facebookcatalog", "Facebook"),
+    ("linkedinbot", "LinkedInBot"),
+    ("telegrambot", "TelegramBot"),
+    ("whatsapp", "WhatsApp"),
+    ("skypeuripreview", "Skype"),
+    ("pinterestbot", "Pinterestbot"),
+    ("redditbot", "Redditbot"),
+    ("embedly", "Embedly"),
+    ("headlesschrome", "Headless Chrome"),
+    ("curl/", "curl"),
+    ("wget/", "Wget"),
+    ("python-requests", "Python Requests"),
+    ("go-http-client", "Go HTTP Client"),
+    ("okhttp", "OkHttp"),
+];
+
+/// Lowercase substrings which only appear in the user agents of automated clients
+const GENERIC_BOT_MARKERS: [&str; 6] = ["bot", "crawler", "spider", "scraper", "preview", "fetch"];
+
+/// Lowercase substrings of browsers' user agents and their names, checked in order since most
+/// browsers include the tokens of those they're based on
+const BROWSERS: [(&str, &str); 10] = [
+    ("edg/", "Edge"),
+    ("edga/", "Edge"),
+    ("edgios/", "Edge"),
+    ("opr/", "Opera"),
+    ("samsungbrowser/", "Samsung Internet"),
+    ("firefox/", "Firefox"),
+    ("fxios/", "Firefox"),
+    ("crios/", "Chrome"),
+    ("chrome/", "Chrome"),
+    ("safari/", "Safari"),
+];
+
+/// Lowercase substrings of operating systems' user agents and their names, checked in order
+const OPERATING_SYSTEMS: [(&str, &str); 8] = [
+    ("iphone", "iOS"),
+    ("ipad", "iOS"),
+    ("android", "Android"),
+    ("cros ", "ChromeOS"),
+    ("windows", "Windows"),
+    ("macintosh", "macOS"),
+    ("mac os x", "macOS"),
+    ("linux", "Linux"),
+];
+
+fn find_name(user_agent: &str, names: &[(&str, &'static str)]) -> Option<&'static str> {
+    names
+        .iter()
+        .find(|(token, _)| user_agent.contains(token))
+        .map(|(_, name)| *name)
+}
+
+/// Classifies a User-Agent header with simple substring heuristics, which is enough to tell the
+/// common browsers, operating systems and bots apart
+pub fn classify_user_agent(user_agent: &str) -> UserAgentInfo {
+    let user_agent = user_agent.to_lowercase();
+
+    if let Some(bot) = find_name(&user_agent, &KNOWN_BOTS) {
+        return UserAgentInfo {
+            browser: Some(bot.to_string()),
+            is_bot: true,
+            ..Default::default()
+        };
+    }
+
+    if GENERIC_BOT_MARKERS
+        .iter()
+        .any(|marker| user_agent.contains(marker))
+    {
+        return UserAgentInfo {
+            is_bot: true,
+            ..Default::default()
+        };
+    }
+
+    let os = find_name(&user_agent, &OPERATING_SYSTEMS);
+
+    let device_type = if user_agent.contains("ipad")
+        || user_agent.contains("tablet")
+        || (os == Some("Android") && !user_agent.contains("mobile"))
+    {
+        Some(DeviceType::Tablet)
+    } else if user_agent.contains("mobile") || os == Some("iOS") || os == Some("Android") {
+        Some(DeviceType::Mobile)
+    } else if os.is_some() {
+        Some(DeviceType::Desktop)
+    } else {
+        None
+    };
+
+    UserAgentInfo {
+        browser: find_name(&user_agent, &BROWSERS).map(String::from),
+        os: os.map(String::from),
+        device_type,
+        is_bot: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(
+        browser: Option<&str>,
+        os: Option<&str>,
+        device_type: Option<DeviceType>,
+        is_bot: bool,
+    ) -> UserAgentInfo {
+        UserAgentInfo {
+            browser: browser.map(String::from),
+            os: os.map(String::from),
+            device_type,
+            is_bot,
+        }
+    }
+
+    #[test]
+    fn test_classify_browsers() {
+        let cases = [
+            (
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0.0.0 Safari/537.36",
+                info(
+                    Some("Chrome"),
+                    Some("Windows"),
+                    Some(DeviceType::Desktop),
+                    false,
+                ),
+            ),
+            (
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0.0.0 Safari/537.36 Edg/126.0.0.0",
+                info(
+                    Some("Edge"),
+                    Some("Windows"),
+                    Some(DeviceType::Desktop),
+                    false,
+                ),
+            ),
+            (
+                "Mozilla/5.0 (Macintosh; Intel Mac OS X 14_5) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.5 Safari/605.1.15",
+                info(
+                    Some("Safari"),
+                    Some("macOS"),
+                    Some(DeviceType::Desktop),
+                    false,
+                ),
+            ),
+            (
+                "Mozilla/5.0 (X11; Linux x86_64; rv:127.0) Gecko/20100101 Firefox/127.0",
+                info(
+                    Some("Firefox"),
+                    Some("Linux"),
+                    Some(DeviceType::Desktop),
+                    false,
+                ),
+            ),
+            (
+                "Mozilla/5.0 (iPhone; CPU iPhone OS 17_5 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) CriOS/126.0.6478.54 Mobile/15E148 Safari/604.1",
+                info(Some("Chrome"), Some("iOS"), Some(DeviceType::Mobile), false),
+            ),
+            (
+                "Mozilla/5.0 (Linux; Android 14; SM-S918B) AppleWebKit/537.36 (KHTML, like Gecko) SamsungBrowser/25.0 Chrome/121.0.0.0 Mobile Safari/537.36",
+                info(
+                    Some("Samsung Internet"),
+                    Some("Android"),
+                    Some(DeviceType::Mobile),
+                    false,
+                ),
+            ),
+            (
+                "Mozilla/5.0 (Linux; Android 13; SM-X700) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0.0.0 Safari/537.36",
+                info(
+                    Some("Chrome"),
+                    Some("Android"),
+                    Some(DeviceType::Tablet),
+                    false,
+                ),
+            ),
+            (
+                "Mozilla/5.0 (iPad; CPU OS 17_5 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.5 Mobile/15E148 Safari/604.1",
+                info(Some("Safari"), Some("iOS"), Some(DeviceType::Tablet), false),
+            ),
+            ("something else", info(None, None, None, false)),
+        ];
+
+        for (user_agent, expected) in cases {
+            assert_eq!(classify_user_agent(user_agent), expected, "{user_agent}");
+        }
+    }
+
+    #[test]
+    fn test_classify_bots() {
+        let cases = [
+            (
+                "Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)",
+                Some("Googlebot"),
+            ),
+            (
+                "Slackbot-LinkExpanding 1.0 (+https://api.slack.com/robots)",
+                Some("Slackbot"),
+            ),
+            (
+                "Mozilla/5.0 (compatible; Discordbot/2.0; +https://discordapp.com)",
+                Some("Discordbot"),
+            ),
+            (
+                "facebookexternalhit/1.1 (+http://www.facebook.com/externalhit_uatext.php)",
+                Some("Facebook"),
+            ),
+            ("curl/8.0", Some("curl")),
+            ("Mozilla/5.0 (compatible; SomeNewCrawler/1.0)", None),
+        ];
+
+        for (user_agent, expected_name) in cases {
+            assert_eq!(
+                classify_user_agent(user_agent),
+                info(expected_name, None, None, true),
+                "{user_agent}"
+            );
+        }
+    }
+}
@@ -1,8 +1,18 @@
 use poem::{Body, http::StatusCode};
-use serde_valid::Validate;
+use serde_valid::{Validate, validation::Error};
 
 /// Validates a struct with the Validate trait, returning a properly formatted BAD_REQUEST response
-/// if validation fails.
+/// if validation fails. The response body is JSON of the following shape, where `errors` holds
+/// errors which apply to the whole struct and `properties` holds errors for each invalid field:
+///
+/// ```json
+/// {
+///     "errors": [],
+///     "properties": {
+///         "slug": { "errors": ["slug cannot be one of: admin, static"] }
+///     }
+/// }
+/// ```
 pub fn validate_to_poem_error<T: Validate>(value: T) -> poem::Result<T> {
     value.validate().map(|_| value).map_err(|e| {
         poem::Error::from_response(
@@ -13,3 +23,14 @@ pub fn validate_to_poem_error<T: Validate>(value: T) -> poem::Result<T> {
         )
     })
 }
+
+/// Runs a custom validator against an optional field, if a value is present
+pub fn validate_if_present<T: AsRef<str>>(
+    value: &Option<T>,
+    validator: impl Fn(&str) -> Result<(), Error>,
+) -> Result<(), Error> {
+    match value {
+        Some(value) => validator(value.as_ref()),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use poem::http::HeaderMap;
+use sha2::Sha256;
+
+/// Headers which are never stored unless VISIT_HEADER_DENYLIST is set to something else
+pub const DEFAULT_HEADER_DENYLIST: [&str; 3] = ["authorization", "cookie", "proxy-authorization"];
+
+/// Headers which proxies put client IP addresses in, these are only stored with the IP address mode
+/// Full since they'd otherwise store the address the mode hides
+pub const CLIENT_IP_HEADERS: [&str; 6] = [
+    "x-forwarded-for",
+    "x-real-ip",
+    "forwarded",
+    "cf-connecting-ip",
+    "true-client-ip",
+    "x-client-ip",
+];
+
+/// How visitor IP addresses are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpAddressMode {
+    Full,
+    /// Zeroes everything after the /24 prefix of IPv4 addresses and the /48 prefix of IPv6 addresses
+    Truncate,
+    /// Replaces the address with a keyed hash, so visits from the same address can still be
+    /// related without storing it
+    Hash,
+    Discard,
+}
+
+impl FromStr for IpAddressMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(IpAddressMode::Full),
+            "truncate" => Ok(IpAddressMode::Truncate),
+            "hash" => Ok(IpAddressMode::Hash),
+            "discard" => Ok(IpAddressMode::Discard),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VisitPrivacySettings {
+    /// If set, only these (lowercase) headers are stored
+    pub header_allowlist: Option<Vec<String>>,
+    /// These (lowercase) headers are never stored, even if allowlisted
+    pub header_denylist: Vec<String>,
+    pub ip_address_mode: IpAddressMode,
+    /// Required when ip_address_mode is Hash
+    pub ip_address_hash_key: Option<String>,
+    /// Visits from requests with `DNT: 1` or `Sec-GPC: 1` are stored without headers or IP address
+    pub honour_do_not_track: bool,
+}
+
+/// Whether the request asks not to be tracked via the DNT or Global Privacy Control headers
+pub fn requests_no_tracking(headers: &HeaderMap) -> bool {
+    ["dnt", "sec-gpc"].into_iter().any(|header_name| {
+        headers
+            .get(header_name)
+            .is_some_and(|value| value.as_bytes().trim_ascii() == b"1")
+    })
+}
+
+/// Converts the headers to a map of lowercase header names to values, leaving out the headers
+/// which shouldn't be stored and values which aren't valid strings
+pub fn filter_visit_headers(
+    headers: &HeaderMap,
+    settings: &VisitPrivacySettings,
+) -> HashMap<String, Vec<String>> {
+    let mut filtered_headers = HashMap::<String, Vec<String>>::with_capacity(headers.keys_len());
+
+    for (header_name, header_value) in headers {
+        // Header names from the http crate are already lowercase
+        let header_name = header_name.as_str();
+
+        let allowed = settings
+            .header_allowlist
+            .as_ref()
+            .is_none_or(|allowlist| allowlist.iter().any(|h| h == header_name))
+            && !settings.header_denylist.iter().any(|h| h == header_name)
+            && (settings.ip_address_mode == IpAddressMode::Full
+                || !CLIENT_IP_HEADERS.contains(&header_name));
+
+        if allowed && let Ok(header_value) = header_value.to_str() {
+            filtered_headers
+                .entry(header_name.to_string())
+                .or_default()
+                .push(header_value.to_string());
+        }
+    }
+
+    filtered_headers
+}
+
+fn truncate_ip_address(ip_address: IpAddr) -> IpAddr {
+    match ip_address {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            IpAddr::V4(Ipv4Addr::new(a, b, c, 0))
+        }
+        IpAddr::V6(ip) => {
+            let [a, b, c, ..] = ip.segments();
+            IpAddr::V6(Ipv6Addr::new(a, b, c, 0, 0, 0, 0, 0))
+        }
+    }
+}
+
+fn hash_ip_address(ip_address: IpAddr, key: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).unwrap();
+    mac.update(ip_address.to_string().as_bytes());
+
+    // Half of the hash is plenty to tell addresses apart
+    BASE64_URL_SAFE_NO_PAD.encode(&mac.finalize().into_bytes()[..16])
+}
+
+/// Returns the IP address in the form it should be stored, if at all
+pub fn anonymize_ip_address(ip_address: IpAddr, settings: &VisitPrivacySettings) -> Option<String> {
+    match settings.ip_address_mode {
+        IpAddressMode::Full => Some(ip_address.to_string()),
+        IpAddressMode::Truncate => Some(truncate_ip_address(ip_address).to_string()),
+        IpAddressMode::Hash => Some(hash_ip_address(
+            ip_address,
+            settings
+                .ip_address_hash_key
+                .as_deref()
+                .expect("an IP address hash key is required to hash IP addresses"),
+        )),
+        IpAddressMode::Discard => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use poem::http::HeaderValue;
+
+    use super::*;
+
+    fn settings() -> VisitPrivacySettings {
+        VisitPrivacySettings {
+            header_allowlist: None,
+            header_denylist: DEFAULT_HEADER_DENYLIST.map(String::from).to_vec(),
+            ip_address_mode: IpAddressMode::Full,
+            ip_address_hash_key: Some("secret".to_string()),
+            honour_do_not_track: true,
+        }
+    }
+
+    fn test_headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("cookie", HeaderValue::from_static("session=abc"));
+        headers.insert("user-agent", HeaderValue::from_static("curl/8.0"));
+        headers.insert("referer", HeaderValue::from_static("https://example.com/"));
+        headers.append("accept-language", HeaderValue::from_static("en"));
+        headers.append("accept-language", HeaderValue::from_static("fr"));
+        headers
+    }
+
+    #[test]
+    fn test_filter_visit_headers_denylist() {
+        let filtered_headers = filter_visit_headers(&test_headers(), &settings());
+
+        assert_eq!(
+            filtered_headers,
+            HashMap::from([
+                ("user-agent".to_string(), vec!["curl/8.0".to_string()]),
+                (
+                    "referer".to_string(),
+                    vec!["https://example.com/".to_string()]
+                ),
+                (
+                    "accept-language".to_string(),
+                    vec!["en".to_string(), "fr".to_string()]
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_filter_visit_headers_allowlist() {
+        let settings = VisitPrivacySettings {
+            header_allowlist: Some(vec!["user-agent".to_string(), "cookie".to_string()]),
+            ..settings()
+        };
+
+        let filtered_headers = filter_visit_headers(&test_headers(), &settings);
+
+        assert_eq!(
+            filtered_headers,
+            HashMap::from([("user-agent".to_string(), vec!["curl/8.0".to_string()])])
+        );
+    }
+
+    #[test]
+    fn test_filter_visit_headers_client_ip() {
+        let mut headers = test_headers();
+        headers.insert("x-forwarded-for", HeaderValue::from_static("203.0.113.195"));
+        headers.insert("x-real-ip", HeaderValue::from_static("203.0.113.195"));
+        headers.insert("forwarded", HeaderValue::from_static("for=203.0.113.195"));
+        headers.insert(
+            "cf-connecting-ip",
+            HeaderValue::from_static("203.0.113.195"),
+        );
+
+        for ip_address_mode in [
+            IpAddressMode::Truncate,
+            IpAddressMode::Hash,
+            IpAddressMode::Discard,
+        ] {
+            let settings = VisitPrivacySettings {
+                ip_address_mode,
+                ..settings()
+            };
+
+            let filtered_headers = filter_visit_headers(&headers, &settings);
+            assert!(
+                filtered_headers
+                    .values()
+                    .flatten()
+                    .all(|value| !value.contains("203.0.113.195"))
+            );
+            assert!(filtered_headers.contains_key("user-agent"));
+        }
+
+        let filtered_headers = filter_visit_headers(&headers, &settings());
+        assert_eq!(
+            filtered_headers["x-forwarded-for"],
+            vec!["203.0.113.195".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_anonymize_ip_address() {
+        let ipv4: IpAddr = "203.0.113.195".parse().unwrap();
+        let ipv6: IpAddr = "2001:db8:85a3:8d3:1319:8a2e:370:7348".parse().unwrap();
+
+        let with_mode = |ip_address_mode| VisitPrivacySettings {
+            ip_address_mode,
+            ..settings()
+        };
+
+        assert_eq!(
+            anonymize_ip_address(ipv4, &with_mode(IpAddressMode::Full)),
+            Some("203.0.113.195".to_string())
+        );
+        assert_eq!(
+            anonymize_ip_address(ipv4, &with_mode(IpAddressMode::Truncate)),
+            Some("203.0.113.0".to_string())
+        );
+        assert_eq!(
+            anonymize_ip_address(ipv6, &with_mode(IpAddressMode::Truncate)),
+            Some("2001:db8:85a3::".to_string())
+        );
+        assert_eq!(
+            anonymize_ip_address(ipv4, &with_mode(IpAddressMode::Discard)),
+            None
+        );
+
+        let hashed = anonymize_ip_address(ipv4, &with_mode(IpAddressMode::Hash)).unwrap();
+        assert_eq!(hashed.len(), 22);
+        assert!(!hashed.contains("203"));
+        assert_eq!(
+            anonymize_ip_address(ipv4, &with_mode(IpAddressMode::Hash)),
+            Some(hashed.clone())
+        );
+        assert_ne!(
+            anonymize_ip_address(ipv6, &with_mode(IpAddressMode::Hash)),
+            Some(hashed)
+        );
+    }
+
+    #[test]
+    fn test_requests_no_tracking() {
+        let mut headers = HeaderMap::new();
+        assert!(!requests_no_tracking(&headers));
+
+        headers.insert("dnt", HeaderValue::from_static("0"));
+        assert!(!requests_no_tracking(&headers));
+
+        headers.insert("sec-gpc", HeaderValue::from_static("1"));
+        assert!(requests_no_tracking(&headers));
+    }
+}
@@ -0,0 +1,147 @@
+use std::time::Duration;
+
+use chrono::{NaiveTime, TimeDelta, Utc};
+use sqlx::PgConnection;
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+
+use crate::{
+    common::visit_rollup::roll_up_closed_days,
+    db::{link_visit_rollups::get_rollup_watermark, link_visits::prune_link_visits},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct VisitRetentionSettings {
+    /// Visits older than this are pruned
+    pub retention: TimeDelta,
+    /// How many visits are deleted per statement, to keep transactions and locks short
+    pub batch_size: i64,
+    /// Whether only visits which have been rolled up into the daily stats are pruned, so long-range
+    /// stats still include them
+    pub rollup: bool,
+}
+
+/// Prunes every visit older than the retention window, one batch at a time. If rollup is set,
+/// closed days are rolled up first and days which still haven't been are kept. Returns the total
+/// number of visits pruned
+pub async fn prune_old_visits(
+    db: &mut PgConnection,
+    settings: &VisitRetentionSettings,
+) -> sqlx::Result<u64> {
+    let now = Utc::now();
+    let mut before = now - settings.retention;
+
+    if settings.rollup {
+        roll_up_closed_days(&mut *db, now).await?;
+
+        let rolled_up_until = get_rollup_watermark(&mut *db)
+            .await?
+            .map(|watermark| watermark.and_time(NaiveTime::MIN).and_utc());
+
+        match rolled_up_until {
+            Some(rolled_up_until) => before = before.min(rolled_up_until),
+            None => return Ok(0),
+        }
+    }
+
+    let mut total_pruned = 0;
+
+    loop {
+        let pruned = prune_link_visits(&mut *db, before, settings.batch_size).await?;
+        total_pruned += pruned;
+
+        if pruned < settings.batch_size as u64 {
+            return Ok(total_pruned);
+        }
+    }
+}
+
+/// Spawns a background task which prunes old visits every interval, starting immediately
+pub fn start_visit_pruner(
+    db_pool: sqlx::PgPool,
+    settings: VisitRetentionSettings,
+    interval: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            let result = match db_pool.acquire().await {
+                Ok(mut db) => prune_old_visits(&mut db, &settings).await,
+                Err(error) => Err(error),
+            };
+
+            match result {
+                Ok(0) => {}
+                Ok(pruned) => tracing::info!("Pruned {pruned} old link visits"),
+                Err(error) => tracing::error!("Failed to prune old link visits: {error}"),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        common::testing::db::PgPoolConn,
+        db::{
+            link_visits::{NewLinkVisit, create_link_visits, get_recent_link_visits},
+            links::{LinkSettings, create_link},
+            platforms::create_platform,
+        },
+    };
+
+    use super::*;
+
+    #[sqlx::test]
+    async fn test_prune_old_visits(mut db: PgPoolConn) {
+        let (_, platform) = create_platform(&mut db, "Pruner").await.unwrap();
+        let link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://example.com/".to_string(),
+            None,
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+
+        let now = Utc::now();
+        let visits = (0..7)
+            .map(|days_ago| NewLinkVisit {
+                link_slug: link.slug.clone(),
+                at: now - TimeDelta::days(days_ago * 10),
+                headers: HashMap::new(),
+                ip_address: None,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        create_link_visits(&mut db, &visits).await.unwrap();
+
+        let settings = VisitRetentionSettings {
+            retention: TimeDelta::days(25),
+            batch_size: 2,
+            rollup: true,
+        };
+
+        assert_eq!(prune_old_visits(&mut db, &settings).await.unwrap(), 4);
+
+        let remaining_visits = get_recent_link_visits(&mut db, &link.slug, 10)
+            .await
+            .unwrap();
+        assert_eq!(remaining_visits.len(), 3);
+
+        // Every pruned visit was rolled up first
+        let rolled_up_visits =
+            sqlx::query!(r#"SELECT SUM(visits)::BIGINT AS "visits!" FROM link_visit_daily"#)
+                .fetch_one(&mut *db)
+                .await
+                .unwrap();
+        assert_eq!(rolled_up_visits.visits, 6);
+    }
+}
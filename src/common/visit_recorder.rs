@@ -0,0 +1,305 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use tokio::{
+    sync::{
+        mpsc::{self, error::TrySendError},
+        oneshot,
+    },
+    task::JoinHandle,
+    time::MissedTickBehavior,
+};
+
+use crate::db::link_visits::{NewLinkVisit, create_link_visits};
+
+#[derive(Debug, Default)]
+struct VisitRecorderMetrics {
+    queued: AtomicU64,
+    dropped: AtomicU64,
+    flushed: AtomicU64,
+    failed: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct VisitRecorderMetricsSnapshot {
+    /// Visits successfully pushed onto the queue
+    queued: u64,
+    /// Visits which were discarded because the queue was full or closed
+    dropped: u64,
+    /// Visits written to the database
+    flushed: u64,
+    /// Visits which were lost because their batch failed to be written
+    failed: u64,
+}
+
+/// Queues link visits so they can be written to the database in batches by a background task,
+/// keeping the database out of the redirect hot path
+#[derive(Clone)]
+pub struct VisitRecorder {
+    sender: mpsc::Sender<NewLinkVisit>,
+    metrics: Arc<VisitRecorderMetrics>,
+}
+
+/// Used to stop the background task started by [`VisitRecorder::start`]
+pub struct VisitRecorderHandle {
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl VisitRecorder {
+    fn new(queue_capacity: usize) -> (Self, mpsc::Receiver<NewLinkVisit>) {
+        let (sender, receiver) = mpsc::channel(queue_capacity);
+
+        let visit_recorder = VisitRecorder {
+            sender,
+            metrics: Arc::new(VisitRecorderMetrics::default()),
+        };
+
+        (visit_recorder, receiver)
+    }
+
+    /// Spawns the background task which flushes queued visits whenever batch_size visits are
+    /// waiting or every flush_interval, whichever comes first
+    pub fn start(
+        db_pool: sqlx::PgPool,
+        queue_capacity: usize,
+        batch_size: usize,
+        flush_interval: Duration,
+    ) -> (Self, VisitRecorderHandle) {
+        let (visit_recorder, receiver) = VisitRecorder::new(queue_capacity);
+        let (shutdown_sender, shutdown_receiver) = oneshot::channel();
+
+        let task = tokio::spawn(run_flush_loop(
+            db_pool,
+            receiver,
+            shutdown_receiver,
+            visit_recorder.metrics.clone(),
+            batch_size,
+            flush_interval,
+        ));
+
+        let handle = VisitRecorderHandle {
+            shutdown: shutdown_sender,
+            task,
+        };
+
+        (visit_recorder, handle)
+    }
+
+    /// Queues the visit without waiting, if the queue is full the visit is dropped
+    pub fn record(&self, visit: NewLinkVisit) {
+        match self.sender.try_send(visit) {
+            Ok(()) => {
+                self.metrics.queued.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(TrySendError::Full(_) | TrySendError::Closed(_)) => {
+                self.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl VisitRecorderHandle {
+    /// Stops accepting new visits, then flushes everything that's still queued
+    pub async fn shutdown(self) {
+        let _ = self.shutdown.send(());
+        let _ = self.task.await;
+    }
+}
+
+fn snapshot_metrics(metrics: &VisitRecorderMetrics) -> VisitRecorderMetricsSnapshot {
+    VisitRecorderMetricsSnapshot {
+        queued: metrics.queued.load(Ordering::Relaxed),
+        dropped: metrics.dropped.load(Ordering::Relaxed),
+        flushed: metrics.flushed.load(Ordering::Relaxed),
+        failed: metrics.failed.load(Ordering::Relaxed),
+    }
+}
+
+async fn flush_batch(
+    db_pool: &sqlx::PgPool,
+    batch: &mut Vec<NewLinkVisit>,
+    metrics: &VisitRecorderMetrics,
+) {
+    if batch.is_empty() {
+        return;
+    }
+
+    let result = match db_pool.acquire().await {
+        Ok(mut db) => create_link_visits(&mut db, batch).await.map(|_| ()),
+        Err(error) => Err(error),
+    };
+
+    let batch_len = batch.len() as u64;
+    match result {
+        Ok(()) => {
+            metrics.flushed.fetch_add(batch_len, Ordering::Relaxed);
+        }
+        Err(error) => {
+            metrics.failed.fetch_add(batch_len, Ordering::Relaxed);
+            tracing::error!("Failed to record batch of {batch_len} link visits: {error}");
+        }
+    }
+
+    batch.clear();
+}
+
+async fn run_flush_loop(
+    db_pool: sqlx::PgPool,
+    mut receiver: mpsc::Receiver<NewLinkVisit>,
+    mut shutdown: oneshot::Receiver<()>,
+    metrics: Arc<VisitRecorderMetrics>,
+    batch_size: usize,
+    flush_interval: Duration,
+) {
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut reported_dropped = 0;
+    let mut shutdown_handle_dropped = false;
+
+    let mut interval = tokio::time::interval(flush_interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            visit = receiver.recv() => match visit {
+                Some(visit) => {
+                    batch.push(visit);
+
+                    if batch.len() >= batch_size {
+                        flush_batch(&db_pool, &mut batch, &metrics).await;
+                    }
+                }
+                // Every VisitRecorder was dropped
+                None => break,
+            },
+            _ = interval.tick() => {
+                flush_batch(&db_pool, &mut batch, &metrics).await;
+
+                let dropped = metrics.dropped.load(Ordering::Relaxed);
+                if dropped > reported_dropped {
+                    tracing::warn!(
+                        "Dropped {} link visits because the visit queue was full",
+                        dropped - reported_dropped
+                    );
+                    reported_dropped = dropped;
+                }
+            },
+            result = &mut shutdown, if !shutdown_handle_dropped => match result {
+                Ok(()) => {
+                    receiver.close();
+
+                    while let Some(visit) = receiver.recv().await {
+                        batch.push(visit);
+
+                        if batch.len() >= batch_size {
+                            flush_batch(&db_pool, &mut batch, &metrics).await;
+                        }
+                    }
+
+                    break;
+                }
+                Err(_) => shutdown_handle_dropped = true,
+            },
+        }
+    }
+
+    flush_batch(&db_pool, &mut batch, &metrics).await;
+
+    let VisitRecorderMetricsSnapshot {
+        queued,
+        dropped,
+        flushed,
+        failed,
+    } = snapshot_metrics(&metrics);
+    tracing::info!(
+        "Visit recorder stopped, visits queued: {queued}, dropped: {dropped}, flushed: {flushed}, failed: {failed}"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::Utc;
+    use sqlx::PgPool;
+
+    use crate::db::{
+        links::{LinkSettings, create_link},
+        platforms::create_platform,
+    };
+
+    use super::*;
+
+    fn new_visit(link_slug: &str) -> NewLinkVisit {
+        NewLinkVisit {
+            link_slug: link_slug.to_string(),
+            at: Utc::now(),
+            headers: HashMap::new(),
+            ip_address: None,
+            ..Default::default()
+        }
+    }
+
+    #[sqlx::test]
+    async fn test_visits_flushed_on_shutdown(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (_, platform) = create_platform(&mut db, "Recorder").await.unwrap();
+        let link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://example.com/".to_string(),
+            None,
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+
+        let (visit_recorder, handle) =
+            VisitRecorder::start(db_pool.clone(), 100, 2, Duration::from_secs(3600));
+
+        for _ in 0..5 {
+            visit_recorder.record(new_visit(&link.slug));
+        }
+
+        handle.shutdown().await;
+
+        // Queue is closed after shutdown
+        visit_recorder.record(new_visit(&link.slug));
+
+        let visit_count = sqlx::query!("SELECT COUNT(*) FROM link_visits")
+            .fetch_one(&mut *db)
+            .await
+            .unwrap();
+        assert_eq!(visit_count.count, Some(5));
+
+        assert_eq!(
+            snapshot_metrics(&visit_recorder.metrics),
+            VisitRecorderMetricsSnapshot {
+                queued: 5,
+                dropped: 1,
+                flushed: 5,
+                failed: 0,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_visits_dropped_when_queue_full() {
+        let (visit_recorder, _receiver) = VisitRecorder::new(2);
+
+        for _ in 0..5 {
+            visit_recorder.record(new_visit("full"));
+        }
+
+        let metrics = snapshot_metrics(&visit_recorder.metrics);
+        assert_eq!(metrics.queued, 2);
+        assert_eq!(metrics.dropped, 3);
+    }
+}
@@ -0,0 +1,172 @@
+use std::time::Duration;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use sqlx::PgConnection;
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+
+use crate::db::link_visit_rollups::{get_first_visit_day, get_rollup_watermark, roll_up_visit_day};
+
+/// How many of the most common values of each kind (referrer domains, browsers, etc.) are kept per
+/// link per day
+const ROLLUP_TOP_VALUES_LIMIT: i64 = 20;
+
+/// How long after midnight (UTC) a day is considered closed, so visits still waiting in the visit
+/// recorder's queue make it into the rollups
+const DAY_CLOSE_GRACE_PERIOD: TimeDelta = TimeDelta::minutes(5);
+
+/// Rolls up every closed day after the last rolled up day (or the oldest visit) into
+/// link_visit_daily, one day at a time. Days rolled up by another task or instance in the meantime
+/// are skipped. Returns the number of days rolled up
+pub async fn roll_up_closed_days(db: &mut PgConnection, now: DateTime<Utc>) -> sqlx::Result<u32> {
+    let until = (now - DAY_CLOSE_GRACE_PERIOD).date_naive();
+
+    let first_day = match get_rollup_watermark(&mut *db).await? {
+        Some(watermark) => watermark,
+        None => get_first_visit_day(&mut *db).await?.unwrap_or(until),
+    };
+
+    let mut rolled_up_days = 0;
+
+    for day in first_day.iter_days().take_while(|day| *day < until) {
+        if roll_up_visit_day(&mut *db, day, ROLLUP_TOP_VALUES_LIMIT).await? {
+            rolled_up_days += 1;
+        }
+    }
+
+    Ok(rolled_up_days)
+}
+
+/// Spawns a background task which rolls up closed days every interval, starting immediately
+pub fn start_visit_rollup(db_pool: sqlx::PgPool, interval: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+
+            let result = match db_pool.acquire().await {
+                Ok(mut db) => roll_up_closed_days(&mut db, Utc::now()).await,
+                Err(error) => Err(error),
+            };
+
+            match result {
+                Ok(0) => {}
+                Ok(days) => tracing::info!("Rolled up link visits for {days} days"),
+                Err(error) => tracing::error!("Failed to roll up link visits: {error}"),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::TimeZone;
+
+    use crate::{
+        common::testing::db::PgPoolConn,
+        db::{
+            link_visits::{NewLinkVisit, create_link_visits},
+            links::{LinkSettings, create_link},
+            platforms::create_platform,
+        },
+    };
+
+    use super::*;
+
+    #[sqlx::test]
+    async fn test_roll_up_closed_days(mut db: PgPoolConn) {
+        let (_, platform) = create_platform(&mut db, "Rollups").await.unwrap();
+        let link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://example.com/".to_string(),
+            None,
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+
+        let at = |d: u32, h: u32, m: u32| Utc.with_ymd_and_hms(2025, 1, d, h, m, 0).unwrap();
+        let visits = [at(1, 1, 0), at(3, 1, 0), at(3, 2, 0), at(5, 0, 0)]
+            .into_iter()
+            .map(|at| NewLinkVisit {
+                link_slug: link.slug.clone(),
+                at,
+                headers: HashMap::new(),
+                ip_address: None,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        create_link_visits(&mut db, &visits).await.unwrap();
+
+        // The 4th is still within the grace period, so it isn't closed yet
+        assert_eq!(roll_up_closed_days(&mut db, at(5, 0, 1)).await.unwrap(), 3);
+        assert_eq!(roll_up_closed_days(&mut db, at(5, 0, 1)).await.unwrap(), 0);
+        assert_eq!(roll_up_closed_days(&mut db, at(5, 0, 10)).await.unwrap(), 1);
+
+        let daily_visits = sqlx::query!("SELECT day, visits FROM link_visit_daily ORDER BY day")
+            .fetch_all(&mut *db)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| (row.day, row.visits))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            daily_visits,
+            vec![(at(1, 0, 0).date_naive(), 1), (at(3, 0, 0).date_naive(), 2)]
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_roll_up_closed_days_concurrently(db_pool: sqlx::PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+        let (_, platform) = create_platform(&mut db, "Rollups").await.unwrap();
+        let link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://example.com/".to_string(),
+            None,
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+
+        let at = |d: u32| Utc.with_ymd_and_hms(2025, 1, d, 12, 0, 0).unwrap();
+        let visits = (1..=5)
+            .map(|d| NewLinkVisit {
+                link_slug: link.slug.clone(),
+                at: at(d),
+                headers: HashMap::new(),
+                ip_address: None,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        create_link_visits(&mut db, &visits).await.unwrap();
+        drop(db);
+
+        let tasks = (0..5)
+            .map(|_| {
+                let db_pool = db_pool.clone();
+
+                tokio::spawn(async move {
+                    let mut db = db_pool.acquire().await.unwrap();
+                    roll_up_closed_days(&mut db, at(6)).await.unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut rolled_up_days = 0;
+        for task in tasks {
+            rolled_up_days += task.await.unwrap();
+        }
+
+        // Each day is only rolled up once, by whichever task gets to it first
+        assert_eq!(rolled_up_days, 5);
+    }
+}
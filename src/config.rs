@@ -1,11 +1,44 @@
-use std::{any::type_name, env, str::FromStr, sync::LazyLock};
+use std::{any::type_name, env, fmt::Display, str::FromStr, sync::LazyLock};
+
+use ipnetwork::IpNetwork;
+
+use crate::common::{
+    login_throttle::LoginThrottleSettings, visit_privacy::VisitPrivacySettings,
+    visit_pruner::VisitRetentionSettings,
+};
 
 pub struct Config {
     pub database_url: String,
     pub database_pool_size: u32,
     pub host_address: String,
-    pub admin_password_hash: String,
+    /// How long dashboard sessions last without being used
     pub admin_login_expires_after_seconds: u64,
+    /// How often expired dashboard login tokens are deleted
+    pub login_token_prune_interval_seconds: u64,
+    /// Lockouts after too many failed dashboard login attempts
+    pub dashboard_login_throttle: LoginThrottleSettings,
+    /// Where to redirect visitors of expired or used up links, if unset a 410 Gone is returned
+    pub link_unavailable_fallback_url: Option<String>,
+    /// How many link visits can be waiting to be written before new ones are dropped
+    pub visit_queue_capacity: usize,
+    pub visit_batch_size: usize,
+    pub visit_flush_interval_ms: u64,
+    /// How many links to keep cached for redirects, zero disables the cache
+    pub link_cache_capacity: u64,
+    pub link_cache_ttl_seconds: u64,
+    /// How long to remember that a slug has no link
+    pub link_cache_not_found_ttl_seconds: u64,
+    /// What's stored about each link visit
+    pub visit_privacy: VisitPrivacySettings,
+    /// If None, visits are kept forever
+    pub visit_retention: Option<VisitRetentionSettings>,
+    pub visit_prune_interval_seconds: u64,
+    /// How often closed days of visits are rolled up into daily stats
+    pub visit_rollup_interval_seconds: u64,
+    /// MaxMind City database used to resolve the locations of visits, if None they aren't resolved
+    pub geoip_database_path: Option<String>,
+    /// Proxies whose X-Forwarded-For and X-Real-IP headers are trusted to contain the client's IP
+    pub trusted_proxies: Vec<IpNetwork>,
 }
 
 fn get_env<T: FromStr>(key: &str) -> T {
@@ -23,55 +56,209 @@ fn get_env<T: FromStr>(key: &str) -> T {
     }
 }
 
+/// Like get_env, but returns None if the variable is unset or empty
+#[cfg_attr(test, allow(dead_code))]
+fn get_optional_env<T: FromStr>(key: &str) -> Option<T> {
+    match env::var(key) {
+        Ok(string) if !string.is_empty() => Some(get_env(key)),
+        _ => None,
+    }
+}
+
+/// Like get_optional_env, but panics if the value is less than min
+#[cfg_attr(test, allow(dead_code))]
+fn get_optional_env_at_least<T: FromStr + PartialOrd + Display>(key: &str, min: T) -> Option<T> {
+    let value = get_optional_env(key)?;
+
+    if value < min {
+        panic!("Expected {key} to be at least {min} in your env or .env file");
+    }
+
+    Some(value)
+}
+
+/// Parses a comma separated list of IP addresses and CIDR networks, returning an empty list if the
+/// variable is unset or empty
+#[cfg_attr(test, allow(dead_code))]
+fn get_network_list_env(key: &str) -> Vec<IpNetwork> {
+    get_optional_env::<String>(key)
+        .map(|list| {
+            list.split(',')
+                .map(str::trim)
+                .filter(|network| !network.is_empty())
+                .map(|network| {
+                    network.parse().unwrap_or_else(|_| {
+                        panic!("Expected {key} to only contain IP addresses and CIDR networks, but it contains {network}")
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Parses a comma separated list of header names, returning None if the variable is unset or empty
+#[cfg_attr(test, allow(dead_code))]
+fn get_header_list_env(key: &str) -> Option<Vec<String>> {
+    get_optional_env::<String>(key).map(|list| {
+        list.split(',')
+            .map(|header_name| header_name.trim().to_lowercase())
+            .filter(|header_name| !header_name.is_empty())
+            .collect()
+    })
+}
+
 #[cfg(not(test))]
 fn load() -> Config {
-    use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+    use crate::common::visit_privacy::{DEFAULT_HEADER_DENYLIST, IpAddressMode};
 
     let _ = dotenvy::dotenv();
 
     let database_url: String = get_env("DATABASE_URL");
     let database_pool_size: u32 = get_env("DATABASE_POOL_SIZE");
     let host_address: String = get_env("HOST_ADDRESS");
-    let admin_password_hash: String = String::from_utf8_lossy(
-        &BASE64_URL_SAFE_NO_PAD
-            .decode(get_env::<String>("ADMIN_PASSWORD_HASH"))
-            .expect("ADMIN_PASSWORD_HASH should be a base64 encoded argon2id password hash"),
-    )
-    .into();
     let admin_login_expires_after_seconds: u64 = get_env("ADMIN_LOGIN_EXPIRES_AFTER_SECONDS");
+    let login_token_prune_interval_seconds: u64 =
+        get_optional_env("LOGIN_TOKEN_PRUNE_INTERVAL_SECONDS").unwrap_or(3600);
+    let dashboard_login_throttle = LoginThrottleSettings {
+        free_attempts_per_ip: get_optional_env_at_least("DASHBOARD_LOGIN_FREE_ATTEMPTS", 1)
+            .unwrap_or(5),
+        base_lockout: chrono::TimeDelta::seconds(
+            get_optional_env("DASHBOARD_LOGIN_BASE_LOCKOUT_SECONDS").unwrap_or(30),
+        ),
+        max_lockout: chrono::TimeDelta::seconds(
+            get_optional_env("DASHBOARD_LOGIN_MAX_LOCKOUT_SECONDS").unwrap_or(3600),
+        ),
+        global_max_failures: get_optional_env_at_least("DASHBOARD_LOGIN_GLOBAL_MAX_FAILURES", 1)
+            .unwrap_or(50),
+        global_window: chrono::TimeDelta::seconds(
+            get_optional_env("DASHBOARD_LOGIN_GLOBAL_WINDOW_SECONDS").unwrap_or(900),
+        ),
+    };
+    let link_unavailable_fallback_url: Option<String> =
+        get_optional_env("LINK_UNAVAILABLE_FALLBACK_URL");
+    let visit_queue_capacity: usize = get_optional_env("VISIT_QUEUE_CAPACITY").unwrap_or(10_000);
+    let visit_batch_size: usize = get_optional_env("VISIT_BATCH_SIZE").unwrap_or(500);
+    let visit_flush_interval_ms: u64 = get_optional_env("VISIT_FLUSH_INTERVAL_MS").unwrap_or(1000);
+    let link_cache_capacity: u64 = get_optional_env("LINK_CACHE_CAPACITY").unwrap_or(10_000);
+    let link_cache_ttl_seconds: u64 = get_optional_env("LINK_CACHE_TTL_SECONDS").unwrap_or(60);
+    let link_cache_not_found_ttl_seconds: u64 =
+        get_optional_env("LINK_CACHE_NOT_FOUND_TTL_SECONDS").unwrap_or(10);
+    let visit_privacy = VisitPrivacySettings {
+        header_allowlist: get_header_list_env("VISIT_HEADER_ALLOWLIST"),
+        header_denylist: get_header_list_env("VISIT_HEADER_DENYLIST")
+            .unwrap_or_else(|| DEFAULT_HEADER_DENYLIST.map(String::from).to_vec()),
+        ip_address_mode: get_optional_env("VISIT_IP_ADDRESS_MODE").unwrap_or(IpAddressMode::Full),
+        ip_address_hash_key: get_optional_env("VISIT_IP_ADDRESS_HASH_KEY"),
+        honour_do_not_track: get_optional_env("VISIT_HONOUR_DO_NOT_TRACK").unwrap_or(false),
+    };
+    if visit_privacy.ip_address_mode == IpAddressMode::Hash
+        && visit_privacy.ip_address_hash_key.is_none()
+    {
+        panic!(
+            "Please set VISIT_IP_ADDRESS_HASH_KEY in your env or .env file to hash IP addresses"
+        );
+    }
+    let visit_retention: Option<VisitRetentionSettings> =
+        get_optional_env::<i64>("VISIT_RETENTION_DAYS").map(|retention_days| {
+            VisitRetentionSettings {
+                retention: chrono::TimeDelta::days(retention_days),
+                batch_size: get_optional_env("VISIT_PRUNE_BATCH_SIZE").unwrap_or(10_000),
+                rollup: get_optional_env("VISIT_ROLLUP_PRUNED").unwrap_or(true),
+            }
+        });
+    let visit_prune_interval_seconds: u64 =
+        get_optional_env("VISIT_PRUNE_INTERVAL_SECONDS").unwrap_or(3600);
+    let visit_rollup_interval_seconds: u64 =
+        get_optional_env("VISIT_ROLLUP_INTERVAL_SECONDS").unwrap_or(3600);
+    let geoip_database_path: Option<String> = get_optional_env("GEOIP_DATABASE_PATH");
+    let trusted_proxies: Vec<IpNetwork> = get_network_list_env("TRUSTED_PROXIES");
 
     Config {
         database_url,
         database_pool_size,
         host_address,
-        admin_password_hash,
         admin_login_expires_after_seconds,
+        login_token_prune_interval_seconds,
+        dashboard_login_throttle,
+        link_unavailable_fallback_url,
+        visit_queue_capacity,
+        visit_batch_size,
+        visit_flush_interval_ms,
+        link_cache_capacity,
+        link_cache_ttl_seconds,
+        link_cache_not_found_ttl_seconds,
+        visit_privacy,
+        visit_retention,
+        visit_prune_interval_seconds,
+        visit_rollup_interval_seconds,
+        geoip_database_path,
+        trusted_proxies,
     }
 }
 
 #[cfg(test)]
 fn load() -> Config {
-    use crate::common::argon2::setup_strong_argon2;
-    use argon2::{
-        PasswordHasher,
-        password_hash::{SaltString, rand_core::OsRng},
-    };
+    use crate::common::visit_privacy::{DEFAULT_HEADER_DENYLIST, IpAddressMode};
 
     let database_url: String = get_env("DATABASE_URL");
     let database_pool_size: u32 = 1;
     let host_address: String = "localhost:8000".to_string();
-    let admin_password_hash: String = setup_strong_argon2()
-        .hash_password("password".as_bytes(), &SaltString::generate(OsRng))
-        .unwrap()
-        .to_string();
     let admin_login_expires_after_seconds: u64 = 3600;
+    let login_token_prune_interval_seconds: u64 = 3600;
+    let dashboard_login_throttle = LoginThrottleSettings {
+        free_attempts_per_ip: 3,
+        base_lockout: chrono::TimeDelta::seconds(30),
+        max_lockout: chrono::TimeDelta::seconds(3600),
+        global_max_failures: 20,
+        global_window: chrono::TimeDelta::seconds(900),
+    };
+    let link_unavailable_fallback_url: Option<String> = None;
+    let visit_queue_capacity: usize = 100;
+    let visit_batch_size: usize = 10;
+    let visit_flush_interval_ms: u64 = 100;
+    let link_cache_capacity: u64 = 100;
+    let link_cache_ttl_seconds: u64 = 60;
+    let link_cache_not_found_ttl_seconds: u64 = 10;
+    let visit_privacy = VisitPrivacySettings {
+        header_allowlist: None,
+        header_denylist: DEFAULT_HEADER_DENYLIST.map(String::from).to_vec(),
+        ip_address_mode: IpAddressMode::Full,
+        ip_address_hash_key: None,
+        honour_do_not_track: true,
+    };
+    let visit_retention: Option<VisitRetentionSettings> = None;
+    let visit_prune_interval_seconds: u64 = 3600;
+    let visit_rollup_interval_seconds: u64 = 3600;
+    let geoip_database_path: Option<String> = Some(
+        concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/src/common/testing/fixtures/GeoIP2-City-Test.mmdb"
+        )
+        .to_string(),
+    );
+    let trusted_proxies: Vec<IpNetwork> =
+        vec!["127.0.0.0/8".parse().unwrap(), "::1".parse().unwrap()];
 
     Config {
         database_url,
         database_pool_size,
         host_address,
-        admin_password_hash,
         admin_login_expires_after_seconds,
+        login_token_prune_interval_seconds,
+        dashboard_login_throttle,
+        link_unavailable_fallback_url,
+        visit_queue_capacity,
+        visit_batch_size,
+        visit_flush_interval_ms,
+        link_cache_capacity,
+        link_cache_ttl_seconds,
+        link_cache_not_found_ttl_seconds,
+        visit_privacy,
+        visit_retention,
+        visit_prune_interval_seconds,
+        visit_rollup_interval_seconds,
+        geoip_database_path,
+        trusted_proxies,
     }
 }
 
@@ -0,0 +1,215 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoginFailures {
+    pub count: i64,
+    pub last_at: Option<DateTime<Utc>>,
+}
+
+/// Locks the attempts until the end of the transaction, so checking whether an IP address is locked
+/// out and creating an attempt for it can't be interleaved with another request doing the same
+pub async fn lock_dashboard_login_attempts(db: &mut PgConnection) -> sqlx::Result<()> {
+    sqlx::query!("LOCK TABLE dashboard_login_attempts IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut *db)
+        .await?;
+
+    Ok(())
+}
+
+/// Returns the id of the created attempt
+pub async fn create_dashboard_login_attempt(
+    db: &mut PgConnection,
+    ip_address: Option<&str>,
+    username: &str,
+    at: DateTime<Utc>,
+    succeeded: bool,
+) -> sqlx::Result<Uuid> {
+    let id = Uuid::now_v7();
+
+    sqlx::query!(
+        r#"
+            INSERT INTO dashboard_login_attempts (id, at, ip_address, username, succeeded)
+            VALUES ($1, $2, $3, $4, $5)
+        "#,
+        id,
+        at,
+        ip_address,
+        username,
+        succeeded,
+    )
+    .execute(&mut *db)
+    .await?;
+
+    Ok(id)
+}
+
+pub async fn set_dashboard_login_attempt_succeeded(
+    db: &mut PgConnection,
+    id: &Uuid,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        "UPDATE dashboard_login_attempts SET succeeded = TRUE WHERE id = $1",
+        id,
+    )
+    .execute(&mut *db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn delete_dashboard_login_attempt(db: &mut PgConnection, id: &Uuid) -> sqlx::Result<()> {
+    sqlx::query!("DELETE FROM dashboard_login_attempts WHERE id = $1", id)
+        .execute(&mut *db)
+        .await?;
+
+    Ok(())
+}
+
+/// Failed attempts from the IP address after the specified time, except those followed by a
+/// successful attempt from it for the same username, so logging in to one account doesn't reset
+/// the failures for others
+pub async fn get_ip_login_failures(
+    db: &mut PgConnection,
+    ip_address: Option<&str>,
+    since: DateTime<Utc>,
+) -> sqlx::Result<LoginFailures> {
+    sqlx::query_as!(
+        LoginFailures,
+        r#"
+            SELECT COUNT(*) AS "count!", MAX(at) AS last_at
+            FROM dashboard_login_attempts AS failure
+            WHERE ip_address IS NOT DISTINCT FROM $1
+                AND NOT succeeded
+                AND at > $2
+                AND NOT EXISTS (
+                    SELECT 1 FROM dashboard_login_attempts AS success
+                    WHERE success.ip_address IS NOT DISTINCT FROM $1
+                        AND success.succeeded
+                        AND LOWER(success.username) = LOWER(failure.username)
+                        AND success.at > failure.at
+                )
+        "#,
+        ip_address,
+        since,
+    )
+    .fetch_one(&mut *db)
+    .await
+}
+
+/// When the nth most recent failed attempt from any IP address after the specified time was, None
+/// if there haven't been that many
+pub async fn get_nth_latest_login_failure_at(
+    db: &mut PgConnection,
+    since: DateTime<Utc>,
+    n: i64,
+) -> sqlx::Result<Option<DateTime<Utc>>> {
+    sqlx::query_scalar!(
+        r#"
+            SELECT at FROM dashboard_login_attempts
+            WHERE NOT succeeded AND at > $1
+            ORDER BY at DESC
+            OFFSET $2::BIGINT - 1
+            LIMIT 1
+        "#,
+        since,
+        n,
+    )
+    .fetch_optional(&mut *db)
+    .await
+}
+
+/// Deletes every attempt from before the specified time. Returns the number of attempts deleted
+pub async fn prune_dashboard_login_attempts(
+    db: &mut PgConnection,
+    before: DateTime<Utc>,
+) -> sqlx::Result<u64> {
+    sqlx::query!("DELETE FROM dashboard_login_attempts WHERE at < $1", before)
+        .execute(&mut *db)
+        .await
+        .map(|result| result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeDelta, TimeZone};
+
+    use crate::common::testing::db::PgPoolConn;
+
+    use super::*;
+
+    #[sqlx::test]
+    async fn test_login_failures(mut db: PgPoolConn) {
+        let at =
+            |m: i64| Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap() + TimeDelta::minutes(m);
+
+        for (ip_address, username, minute, succeeded) in [
+            (Some("1.1.1.1"), "admin", 0, false),
+            (Some("1.1.1.1"), "other", 0, false),
+            (Some("1.1.1.1"), "Admin", 1, true),
+            (Some("1.1.1.1"), "admin", 2, false),
+            (Some("1.1.1.1"), "admin", 3, false),
+            (Some("2.2.2.2"), "admin", 4, false),
+            (None, "admin", 5, false),
+        ] {
+            create_dashboard_login_attempt(&mut db, ip_address, username, at(minute), succeeded)
+                .await
+                .unwrap();
+        }
+
+        // Failures before a success for the same username don't count
+        assert_eq!(
+            get_ip_login_failures(&mut db, Some("1.1.1.1"), at(-60))
+                .await
+                .unwrap(),
+            LoginFailures {
+                count: 3,
+                last_at: Some(at(3)),
+            }
+        );
+        assert_eq!(
+            get_ip_login_failures(&mut db, Some("1.1.1.1"), at(2))
+                .await
+                .unwrap()
+                .count,
+            1
+        );
+        assert_eq!(
+            get_ip_login_failures(&mut db, None, at(-60))
+                .await
+                .unwrap()
+                .count,
+            1
+        );
+        assert_eq!(
+            get_ip_login_failures(&mut db, Some("3.3.3.3"), at(-60))
+                .await
+                .unwrap(),
+            LoginFailures {
+                count: 0,
+                last_at: None,
+            }
+        );
+
+        assert_eq!(
+            get_nth_latest_login_failure_at(&mut db, at(-60), 2)
+                .await
+                .unwrap(),
+            Some(at(4))
+        );
+        assert_eq!(
+            get_nth_latest_login_failure_at(&mut db, at(-60), 7)
+                .await
+                .unwrap(),
+            None
+        );
+
+        assert_eq!(
+            prune_dashboard_login_attempts(&mut db, at(3))
+                .await
+                .unwrap(),
+            4
+        );
+    }
+}
@@ -0,0 +1,83 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+/// A login which got the password right and still needs a second factor
+pub struct DashboardLoginChallenge {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub token_hash: String,
+    #[allow(dead_code)]
+    pub created_at: DateTime<Utc>,
+}
+
+pub async fn create_dashboard_login_challenge(
+    db: &mut PgConnection,
+    user_id: &Uuid,
+    token_hash: &str,
+) -> sqlx::Result<DashboardLoginChallenge> {
+    sqlx::query_as!(
+        DashboardLoginChallenge,
+        r#"
+            INSERT INTO dashboard_login_challenges (id, user_id, token_hash, created_at)
+            VALUES ($1, $2, $3, NOW())
+            RETURNING id, user_id, token_hash, created_at;
+        "#,
+        Uuid::now_v7(),
+        user_id,
+        token_hash,
+    )
+    .fetch_one(&mut *db)
+    .await
+}
+
+/// Fetch a challenge if it was created after the specified time
+pub async fn get_dashboard_login_challenge(
+    db: &mut PgConnection,
+    id: &Uuid,
+    created_after: DateTime<Utc>,
+) -> sqlx::Result<Option<DashboardLoginChallenge>> {
+    sqlx::query_as!(
+        DashboardLoginChallenge,
+        r#"
+            SELECT id, user_id, token_hash, created_at FROM dashboard_login_challenges
+            WHERE id = $1 AND created_at > $2;
+        "#,
+        id,
+        created_after,
+    )
+    .fetch_optional(&mut *db)
+    .await
+}
+
+/// Deletes the challenge along with any others which were created before the specified time
+pub async fn delete_dashboard_login_challenge(
+    db: &mut PgConnection,
+    id: &Uuid,
+    expired_before: DateTime<Utc>,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        "DELETE FROM dashboard_login_challenges WHERE id = $1 OR created_at <= $2",
+        id,
+        expired_before,
+    )
+    .execute(&mut *db)
+    .await?;
+
+    Ok(())
+}
+
+/// Deletes every challenge created before the specified time, returning how many were deleted
+pub async fn delete_expired_dashboard_login_challenges(
+    db: &mut PgConnection,
+    expired_before: DateTime<Utc>,
+) -> sqlx::Result<u64> {
+    let result = sqlx::query!(
+        "DELETE FROM dashboard_login_challenges WHERE created_at <= $1",
+        expired_before,
+    )
+    .execute(&mut *db)
+    .await?;
+
+    Ok(result.rows_affected())
+}
@@ -7,8 +7,17 @@ use crate::config::CONFIG;
 pub struct DashboardLoginToken {
     pub id: Uuid,
     pub token_hash: String,
-    #[allow(dead_code)]
+    pub user_id: Uuid,
     pub created_at: DateTime<Utc>,
+    /// Refreshed as the token is used, it expires once it hasn't been used for a while
+    pub last_used_at: DateTime<Utc>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// Tokens last used before this have expired
+fn expiration_barrier() -> DateTime<Utc> {
+    Utc::now() - TimeDelta::seconds(CONFIG.admin_login_expires_after_seconds as i64)
 }
 
 /// Creates a dashboard login token in the database, returning the unhashed token
@@ -16,16 +25,23 @@ pub struct DashboardLoginToken {
 pub async fn create_dashboard_login_token(
     db: &mut PgConnection,
     token_hash: &str,
+    user_id: &Uuid,
+    ip_address: Option<&str>,
+    user_agent: Option<&str>,
 ) -> sqlx::Result<DashboardLoginToken> {
     let token_row = sqlx::query_as!(
         DashboardLoginToken,
         r#"
-            INSERT INTO dashboard_login_tokens (id, token_hash, created_at)
-            VALUES ($1, $2, NOW())
-            RETURNING id, token_hash, created_at;
+            INSERT INTO dashboard_login_tokens
+                (id, token_hash, user_id, created_at, last_used_at, ip_address, user_agent)
+            VALUES ($1, $2, $3, NOW(), NOW(), $4, $5)
+            RETURNING id, token_hash, user_id, created_at, last_used_at, ip_address, user_agent;
         "#,
         Uuid::now_v7(),
         token_hash,
+        user_id,
+        ip_address,
+        user_agent,
     )
     .fetch_one(&mut *db)
     .await?;
@@ -37,13 +53,229 @@ pub async fn get_dashboard_login_token(
     db: &mut PgConnection,
     id: &Uuid,
 ) -> sqlx::Result<Option<DashboardLoginToken>> {
-    let expiration_barrier =
-        Utc::now() - TimeDelta::seconds(CONFIG.admin_login_expires_after_seconds as i64);
+    sqlx::query_as!(
+        DashboardLoginToken,
+        r#"
+            SELECT id, token_hash, user_id, created_at, last_used_at, ip_address, user_agent
+            FROM dashboard_login_tokens WHERE id = $1 AND last_used_at > $2;
+        "#,
+        id,
+        expiration_barrier(),
+    )
+    .fetch_optional(&mut *db)
+    .await
+}
 
+/// The user's unexpired tokens, newest first
+pub async fn get_user_dashboard_login_tokens(
+    db: &mut PgConnection,
+    user_id: &Uuid,
+) -> sqlx::Result<Vec<DashboardLoginToken>> {
     sqlx::query_as!(
         DashboardLoginToken,
-        "SELECT id, token_hash, created_at FROM dashboard_login_tokens WHERE id = $1 AND created_at > $2",
+        r#"
+            SELECT id, token_hash, user_id, created_at, last_used_at, ip_address, user_agent
+            FROM dashboard_login_tokens WHERE user_id = $1 AND last_used_at > $2
+            ORDER BY created_at DESC;
+        "#,
+        user_id,
+        expiration_barrier(),
+    )
+    .fetch_all(&mut *db)
+    .await
+}
+
+/// Marks the token as just used, so it doesn't expire until it has gone unused for a while again
+pub async fn touch_dashboard_login_token(db: &mut PgConnection, id: &Uuid) -> sqlx::Result<()> {
+    sqlx::query!(
+        "UPDATE dashboard_login_tokens SET last_used_at = NOW() WHERE id = $1",
+        id,
+    )
+    .execute(&mut *db)
+    .await?;
+
+    Ok(())
+}
+
+/// Deletes one of the user's tokens, returning false if they don't have a token with the id
+pub async fn delete_dashboard_login_token(
+    db: &mut PgConnection,
+    id: &Uuid,
+    user_id: &Uuid,
+) -> sqlx::Result<bool> {
+    let result = sqlx::query!(
+        "DELETE FROM dashboard_login_tokens WHERE id = $1 AND user_id = $2",
         id,
-        expiration_barrier,
-    ).fetch_optional(&mut *db).await
+        user_id,
+    )
+    .execute(&mut *db)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Deletes all of the user's tokens, logging them out everywhere
+pub async fn delete_user_dashboard_login_tokens(
+    db: &mut PgConnection,
+    user_id: &Uuid,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        "DELETE FROM dashboard_login_tokens WHERE user_id = $1",
+        user_id,
+    )
+    .execute(&mut *db)
+    .await?;
+
+    Ok(())
+}
+
+/// Deletes every expired token, returning how many were deleted
+pub async fn delete_expired_dashboard_login_tokens(db: &mut PgConnection) -> sqlx::Result<u64> {
+    let result = sqlx::query!(
+        "DELETE FROM dashboard_login_tokens WHERE last_used_at <= $1",
+        expiration_barrier(),
+    )
+    .execute(&mut *db)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        common::testing::db::PgPoolConn,
+        db::dashboard_users::{DashboardUserRole, create_dashboard_user},
+    };
+
+    use super::*;
+
+    #[sqlx::test]
+    async fn test_user_dashboard_login_tokens(mut db: PgPoolConn) {
+        let mut users = vec![];
+        for username in ["alice", "bob"] {
+            users.push(
+                create_dashboard_user(&mut db, username, "hash", DashboardUserRole::Owner)
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        let first = create_dashboard_login_token(
+            &mut db,
+            "hash1",
+            &users[0].id,
+            Some("1.1.1.1"),
+            Some("curl/8.0"),
+        )
+        .await
+        .unwrap();
+        let second = create_dashboard_login_token(&mut db, "hash2", &users[0].id, None, None)
+            .await
+            .unwrap();
+        let other = create_dashboard_login_token(&mut db, "hash3", &users[1].id, None, None)
+            .await
+            .unwrap();
+
+        let tokens = get_user_dashboard_login_tokens(&mut db, &users[0].id)
+            .await
+            .unwrap();
+        assert_eq!(
+            tokens.iter().map(|token| token.id).collect::<Vec<_>>(),
+            vec![second.id, first.id]
+        );
+        assert_eq!(tokens[1].ip_address.as_deref(), Some("1.1.1.1"));
+        assert_eq!(tokens[1].user_agent.as_deref(), Some("curl/8.0"));
+
+        // Users can only delete their own tokens
+        assert!(
+            !delete_dashboard_login_token(&mut db, &other.id, &users[0].id)
+                .await
+                .unwrap()
+        );
+        assert!(
+            delete_dashboard_login_token(&mut db, &first.id, &users[0].id)
+                .await
+                .unwrap()
+        );
+        assert!(
+            get_dashboard_login_token(&mut db, &first.id)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        delete_user_dashboard_login_tokens(&mut db, &users[0].id)
+            .await
+            .unwrap();
+        assert!(
+            get_user_dashboard_login_tokens(&mut db, &users[0].id)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert!(
+            get_dashboard_login_token(&mut db, &other.id)
+                .await
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_dashboard_login_token_expiry(mut db: PgPoolConn) {
+        let user = create_dashboard_user(&mut db, "alice", "hash", DashboardUserRole::Owner)
+            .await
+            .unwrap();
+
+        let mut tokens = vec![];
+        for token_hash in ["hash1", "hash2"] {
+            tokens.push(
+                create_dashboard_login_token(&mut db, token_hash, &user.id, None, None)
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        // Both were created too long ago, but the second has been used recently
+        let long_ago =
+            Utc::now() - TimeDelta::seconds(CONFIG.admin_login_expires_after_seconds as i64 + 60);
+        sqlx::query!(
+            "UPDATE dashboard_login_tokens SET created_at = $1, last_used_at = $1",
+            long_ago,
+        )
+        .execute(&mut *db)
+        .await
+        .unwrap();
+        touch_dashboard_login_token(&mut db, &tokens[1].id)
+            .await
+            .unwrap();
+
+        assert!(
+            get_dashboard_login_token(&mut db, &tokens[0].id)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            get_dashboard_login_token(&mut db, &tokens[1].id)
+                .await
+                .unwrap()
+                .is_some()
+        );
+
+        assert_eq!(
+            delete_expired_dashboard_login_tokens(&mut db)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM dashboard_login_tokens"#)
+                .fetch_one(&mut *db)
+                .await
+                .unwrap(),
+            1
+        );
+    }
 }
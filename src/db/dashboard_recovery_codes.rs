@@ -0,0 +1,121 @@
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+pub struct DashboardRecoveryCode {
+    pub id: Uuid,
+    pub code_hash: String,
+}
+
+/// Replaces all of the user's recovery codes, used or not, with new ones
+pub async fn replace_dashboard_recovery_codes(
+    db: &mut PgConnection,
+    user_id: &Uuid,
+    code_hashes: &[String],
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        "DELETE FROM dashboard_recovery_codes WHERE user_id = $1",
+        user_id
+    )
+    .execute(&mut *db)
+    .await?;
+
+    let ids = code_hashes
+        .iter()
+        .map(|_| Uuid::now_v7())
+        .collect::<Vec<_>>();
+
+    sqlx::query!(
+        r#"
+            INSERT INTO dashboard_recovery_codes (id, user_id, code_hash)
+            SELECT id, $2, code_hash FROM UNNEST($1::UUID[], $3::VARCHAR[]) AS codes(id, code_hash);
+        "#,
+        &ids,
+        user_id,
+        code_hashes,
+    )
+    .execute(&mut *db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_unused_dashboard_recovery_codes(
+    db: &mut PgConnection,
+    user_id: &Uuid,
+) -> sqlx::Result<Vec<DashboardRecoveryCode>> {
+    sqlx::query_as!(
+        DashboardRecoveryCode,
+        "SELECT id, code_hash FROM dashboard_recovery_codes WHERE user_id = $1 AND used_at IS NULL",
+        user_id,
+    )
+    .fetch_all(&mut *db)
+    .await
+}
+
+/// Marks the recovery code as used, returning false if it already was
+pub async fn use_dashboard_recovery_code(db: &mut PgConnection, id: &Uuid) -> sqlx::Result<bool> {
+    sqlx::query!(
+        "UPDATE dashboard_recovery_codes SET used_at = NOW() WHERE id = $1 AND used_at IS NULL",
+        id,
+    )
+    .execute(&mut *db)
+    .await
+    .map(|result| result.rows_affected() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        common::testing::db::PgPoolConn,
+        db::dashboard_users::{DashboardUserRole, create_dashboard_user},
+    };
+
+    use super::*;
+
+    #[sqlx::test]
+    async fn test_dashboard_recovery_codes(mut db: PgPoolConn) {
+        let user = create_dashboard_user(&mut db, "user", "hash", DashboardUserRole::Owner)
+            .await
+            .unwrap();
+
+        replace_dashboard_recovery_codes(&mut db, &user.id, &["a".to_string(), "b".to_string()])
+            .await
+            .unwrap();
+
+        let codes = get_unused_dashboard_recovery_codes(&mut db, &user.id)
+            .await
+            .unwrap();
+        assert_eq!(codes.len(), 2);
+
+        assert!(
+            use_dashboard_recovery_code(&mut db, &codes[0].id)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !use_dashboard_recovery_code(&mut db, &codes[0].id)
+                .await
+                .unwrap()
+        );
+
+        let unused_codes = get_unused_dashboard_recovery_codes(&mut db, &user.id)
+            .await
+            .unwrap();
+        assert_eq!(unused_codes.len(), 1);
+        assert_eq!(unused_codes[0].id, codes[1].id);
+
+        replace_dashboard_recovery_codes(&mut db, &user.id, &["c".to_string()])
+            .await
+            .unwrap();
+        let codes = get_unused_dashboard_recovery_codes(&mut db, &user.id)
+            .await
+            .unwrap();
+        assert_eq!(
+            codes
+                .iter()
+                .map(|c| c.code_hash.as_str())
+                .collect::<Vec<_>>(),
+            vec!["c"]
+        );
+    }
+}
@@ -0,0 +1,430 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+/// What a dashboard user is allowed to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "dashboard_user_role", rename_all = "snake_case")]
+pub enum DashboardUserRole {
+    /// Can do anything, including managing platforms and users
+    Owner,
+    /// Can manage the links of their platforms and reset their API keys
+    Editor,
+    /// Can only view their platforms, links and stats
+    Viewer,
+}
+
+impl DashboardUserRole {
+    pub const ALL: [DashboardUserRole; 3] = [
+        DashboardUserRole::Owner,
+        DashboardUserRole::Editor,
+        DashboardUserRole::Viewer,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DashboardUserRole::Owner => "owner",
+            DashboardUserRole::Editor => "editor",
+            DashboardUserRole::Viewer => "viewer",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            DashboardUserRole::Owner => "Owner",
+            DashboardUserRole::Editor => "Editor",
+            DashboardUserRole::Viewer => "Viewer",
+        }
+    }
+}
+
+impl FromStr for DashboardUserRole {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DashboardUserRole::ALL
+            .into_iter()
+            .find(|role| role.as_str() == s)
+            .ok_or(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DashboardUser {
+    pub id: Uuid,
+    pub username: String,
+    pub password_hash: String,
+    pub role: DashboardUserRole,
+    #[allow(dead_code)]
+    pub created_at: DateTime<Utc>,
+    /// Set while enrolling in two-factor authentication and once enabled
+    pub totp_secret: Option<Vec<u8>>,
+    /// Whether logging in requires a TOTP or recovery code after the password
+    pub totp_enabled: bool,
+    /// The time step of the last TOTP code used, so that codes can't be reused
+    pub totp_last_used_step: Option<i64>,
+    /// Platforms the user has been given access to, owners can access every platform regardless
+    pub platform_ids: Vec<Uuid>,
+}
+
+impl DashboardUser {
+    pub fn is_owner(&self) -> bool {
+        self.role == DashboardUserRole::Owner
+    }
+
+    pub fn can_view_platform(&self, platform_id: &Uuid) -> bool {
+        self.is_owner() || self.platform_ids.contains(platform_id)
+    }
+
+    /// Whether the user can manage the platform's links and reset its API key
+    pub fn can_edit_platform(&self, platform_id: &Uuid) -> bool {
+        self.role != DashboardUserRole::Viewer && self.can_view_platform(platform_id)
+    }
+}
+
+pub async fn create_dashboard_user(
+    db: &mut PgConnection,
+    username: &str,
+    password_hash: &str,
+    role: DashboardUserRole,
+) -> sqlx::Result<DashboardUser> {
+    sqlx::query_as!(
+        DashboardUser,
+        r#"
+            INSERT INTO dashboard_users (id, username, password_hash, role, created_at)
+            VALUES ($1, $2, $3, $4, NOW())
+            RETURNING
+                id, username, password_hash, role AS "role: DashboardUserRole", created_at,
+                totp_secret, totp_enabled, totp_last_used_step,
+                ARRAY[]::UUID[] AS "platform_ids!";
+        "#,
+        Uuid::now_v7(),
+        username,
+        password_hash,
+        role as DashboardUserRole,
+    )
+    .fetch_one(&mut *db)
+    .await
+}
+
+pub async fn get_dashboard_user(
+    db: &mut PgConnection,
+    id: &Uuid,
+) -> sqlx::Result<Option<DashboardUser>> {
+    sqlx::query_as!(
+        DashboardUser,
+        r#"
+            SELECT
+                id, username, password_hash, role AS "role: DashboardUserRole", created_at,
+                totp_secret, totp_enabled, totp_last_used_step,
+                ARRAY(
+                    SELECT platform_id FROM dashboard_user_platforms WHERE user_id = dashboard_users.id
+                ) AS "platform_ids!"
+            FROM dashboard_users
+            WHERE id = $1;
+        "#,
+        id,
+    )
+    .fetch_optional(&mut *db)
+    .await
+}
+
+/// Retrieve a dashboard user by their username, case insensitively
+pub async fn get_dashboard_user_by_username(
+    db: &mut PgConnection,
+    username: &str,
+) -> sqlx::Result<Option<DashboardUser>> {
+    sqlx::query_as!(
+        DashboardUser,
+        r#"
+            SELECT
+                id, username, password_hash, role AS "role: DashboardUserRole", created_at,
+                totp_secret, totp_enabled, totp_last_used_step,
+                ARRAY(
+                    SELECT platform_id FROM dashboard_user_platforms WHERE user_id = dashboard_users.id
+                ) AS "platform_ids!"
+            FROM dashboard_users
+            WHERE LOWER(username) = LOWER($1);
+        "#,
+        username,
+    )
+    .fetch_optional(&mut *db)
+    .await
+}
+
+pub async fn get_dashboard_users(db: &mut PgConnection) -> sqlx::Result<Vec<DashboardUser>> {
+    sqlx::query_as!(
+        DashboardUser,
+        r#"
+            SELECT
+                id, username, password_hash, role AS "role: DashboardUserRole", created_at,
+                totp_secret, totp_enabled, totp_last_used_step,
+                ARRAY(
+                    SELECT platform_id FROM dashboard_user_platforms WHERE user_id = dashboard_users.id
+                ) AS "platform_ids!"
+            FROM dashboard_users
+            ORDER BY LOWER(username);
+        "#,
+    )
+    .fetch_all(&mut *db)
+    .await
+}
+
+#[derive(Debug, Default)]
+pub struct UpdateDashboardUserData {
+    pub password_hash: Option<String>,
+    pub role: Option<DashboardUserRole>,
+}
+
+/// Updates the user's fields which are Some, returning None if the user doesn't exist
+pub async fn update_dashboard_user(
+    db: &mut PgConnection,
+    id: &Uuid,
+    data: &UpdateDashboardUserData,
+) -> sqlx::Result<Option<DashboardUser>> {
+    sqlx::query_as!(
+        DashboardUser,
+        r#"
+            UPDATE dashboard_users
+            SET
+                password_hash = COALESCE($2, password_hash),
+                role = COALESCE($3, role)
+            WHERE id = $1
+            RETURNING
+                id, username, password_hash, role AS "role: DashboardUserRole", created_at,
+                totp_secret, totp_enabled, totp_last_used_step,
+                ARRAY(
+                    SELECT platform_id FROM dashboard_user_platforms WHERE user_id = dashboard_users.id
+                ) AS "platform_ids!";
+        "#,
+        id,
+        data.password_hash,
+        data.role as Option<DashboardUserRole>,
+    )
+    .fetch_optional(&mut *db)
+    .await
+}
+
+/// Deletes the user along with their platform access and logins, returning None if they didn't exist
+pub async fn delete_dashboard_user(db: &mut PgConnection, id: &Uuid) -> sqlx::Result<Option<Uuid>> {
+    sqlx::query_scalar!("DELETE FROM dashboard_users WHERE id = $1 RETURNING id", id)
+        .fetch_optional(&mut *db)
+        .await
+}
+
+/// Sets the secret of a user who's starting to enroll in two-factor authentication, which isn't
+/// enabled until they confirm a code. None disables two-factor authentication
+pub async fn set_dashboard_user_totp_secret(
+    db: &mut PgConnection,
+    id: &Uuid,
+    totp_secret: Option<&[u8]>,
+) -> sqlx::Result<()> {
+    sqlx::query!(
+        r#"
+            UPDATE dashboard_users
+            SET totp_secret = $2, totp_enabled = FALSE, totp_last_used_step = NULL
+            WHERE id = $1;
+        "#,
+        id,
+        totp_secret,
+    )
+    .execute(&mut *db)
+    .await?;
+
+    Ok(())
+}
+
+/// Enables two-factor authentication for a user with a secret, returning false if they don't have one
+pub async fn enable_dashboard_user_totp(db: &mut PgConnection, id: &Uuid) -> sqlx::Result<bool> {
+    sqlx::query!(
+        "UPDATE dashboard_users SET totp_enabled = TRUE WHERE id = $1 AND totp_secret IS NOT NULL",
+        id,
+    )
+    .execute(&mut *db)
+    .await
+    .map(|result| result.rows_affected() > 0)
+}
+
+/// Records that the user's TOTP code for the time step was used, returning false if a code for it
+/// or a later step was already used, e.g. by a concurrent login with the same code
+pub async fn use_dashboard_user_totp_step(
+    db: &mut PgConnection,
+    id: &Uuid,
+    step: i64,
+) -> sqlx::Result<bool> {
+    sqlx::query!(
+        r#"
+            UPDATE dashboard_users
+            SET totp_last_used_step = $2
+            WHERE id = $1 AND (totp_last_used_step IS NULL OR totp_last_used_step < $2);
+        "#,
+        id,
+        step,
+    )
+    .execute(&mut *db)
+    .await
+    .map(|result| result.rows_affected() > 0)
+}
+
+/// Gives the user access to the platform, returning false if they already had access
+pub async fn grant_dashboard_user_platform(
+    db: &mut PgConnection,
+    user_id: &Uuid,
+    platform_id: &Uuid,
+) -> sqlx::Result<bool> {
+    sqlx::query!(
+        r#"
+            INSERT INTO dashboard_user_platforms (user_id, platform_id)
+            VALUES ($1, $2)
+            ON CONFLICT DO NOTHING;
+        "#,
+        user_id,
+        platform_id,
+    )
+    .execute(&mut *db)
+    .await
+    .map(|result| result.rows_affected() > 0)
+}
+
+/// Takes away the user's access to the platform, returning false if they didn't have access
+pub async fn revoke_dashboard_user_platform(
+    db: &mut PgConnection,
+    user_id: &Uuid,
+    platform_id: &Uuid,
+) -> sqlx::Result<bool> {
+    sqlx::query!(
+        "DELETE FROM dashboard_user_platforms WHERE user_id = $1 AND platform_id = $2",
+        user_id,
+        platform_id,
+    )
+    .execute(&mut *db)
+    .await
+    .map(|result| result.rows_affected() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{common::testing::db::PgPoolConn, db::platforms::create_platform};
+
+    use super::*;
+
+    #[sqlx::test]
+    async fn test_dashboard_user_platforms(mut db: PgPoolConn) {
+        let (_, platform) = create_platform(&mut db, "Platform").await.unwrap();
+        let (_, other_platform) = create_platform(&mut db, "Other Platform").await.unwrap();
+
+        let user = create_dashboard_user(&mut db, "Editor", "hash", DashboardUserRole::Editor)
+            .await
+            .unwrap();
+        assert!(user.platform_ids.is_empty());
+
+        assert!(
+            grant_dashboard_user_platform(&mut db, &user.id, &platform.id)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !grant_dashboard_user_platform(&mut db, &user.id, &platform.id)
+                .await
+                .unwrap()
+        );
+
+        // Usernames are looked up case insensitively
+        let user = get_dashboard_user_by_username(&mut db, "editor")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.platform_ids, vec![platform.id]);
+        assert!(user.can_edit_platform(&platform.id));
+        assert!(!user.can_view_platform(&other_platform.id));
+
+        let user = update_dashboard_user(
+            &mut db,
+            &user.id,
+            &UpdateDashboardUserData {
+                role: Some(DashboardUserRole::Viewer),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(user.password_hash, "hash");
+        assert!(user.can_view_platform(&platform.id));
+        assert!(!user.can_edit_platform(&platform.id));
+
+        assert!(
+            revoke_dashboard_user_platform(&mut db, &user.id, &platform.id)
+                .await
+                .unwrap()
+        );
+        assert!(
+            get_dashboard_user(&mut db, &user.id)
+                .await
+                .unwrap()
+                .unwrap()
+                .platform_ids
+                .is_empty()
+        );
+
+        // Owners can access every platform without being given access
+        let owner = create_dashboard_user(&mut db, "Owner", "hash", DashboardUserRole::Owner)
+            .await
+            .unwrap();
+        assert!(owner.can_edit_platform(&other_platform.id));
+
+        assert_eq!(
+            delete_dashboard_user(&mut db, &owner.id).await.unwrap(),
+            Some(owner.id)
+        );
+        assert_eq!(get_dashboard_users(&mut db).await.unwrap().len(), 1);
+    }
+
+    #[sqlx::test]
+    async fn test_dashboard_user_totp(mut db: PgPoolConn) {
+        let user = create_dashboard_user(&mut db, "user", "hash", DashboardUserRole::Owner)
+            .await
+            .unwrap();
+        assert!(!enable_dashboard_user_totp(&mut db, &user.id).await.unwrap());
+
+        set_dashboard_user_totp_secret(&mut db, &user.id, Some(b"secret"))
+            .await
+            .unwrap();
+        assert!(enable_dashboard_user_totp(&mut db, &user.id).await.unwrap());
+
+        assert!(
+            use_dashboard_user_totp_step(&mut db, &user.id, 10)
+                .await
+                .unwrap()
+        );
+        for step in [9, 10] {
+            assert!(
+                !use_dashboard_user_totp_step(&mut db, &user.id, step)
+                    .await
+                    .unwrap()
+            );
+        }
+
+        let user = get_dashboard_user(&mut db, &user.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.totp_secret.as_deref(), Some(b"secret".as_slice()));
+        assert!(user.totp_enabled);
+        assert_eq!(user.totp_last_used_step, Some(10));
+
+        set_dashboard_user_totp_secret(&mut db, &user.id, None)
+            .await
+            .unwrap();
+        let user = get_dashboard_user(&mut db, &user.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.totp_secret, None);
+        assert!(!user.totp_enabled);
+    }
+}
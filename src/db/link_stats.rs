@@ -0,0 +1,551 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StatsBucket {
+    Hour,
+    #[default]
+    Day,
+    Week,
+}
+
+impl StatsBucket {
+    /// Also a valid date_trunc field and interval unit in Postgres
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StatsBucket::Hour => "hour",
+            StatsBucket::Day => "day",
+            StatsBucket::Week => "week",
+        }
+    }
+
+    pub fn duration(&self) -> TimeDelta {
+        match self {
+            StatsBucket::Hour => TimeDelta::hours(1),
+            StatsBucket::Day => TimeDelta::days(1),
+            StatsBucket::Week => TimeDelta::weeks(1),
+        }
+    }
+}
+
+/// Which visits stats are calculated over, always restricted to a single platform's links
+#[derive(Debug, Clone)]
+pub struct VisitsScope {
+    pub platform_id: Uuid,
+    /// If None, visits to all of the platform's links are included
+    pub link_slug: Option<String>,
+    pub from: DateTime<Utc>,
+    /// Exclusive
+    pub to: DateTime<Utc>,
+    /// Whether visits from crawlers, link unfurlers, etc. are included
+    pub include_bots: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VisitTotals {
+    pub visits: i64,
+    /// Visits from distinct visitors, see create_link_visits
+    pub unique_visits: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VisitsBucket {
+    pub start: DateTime<Utc>,
+    pub visits: i64,
+    pub unique_visits: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VisitsByValue {
+    pub value: String,
+    pub visits: i64,
+}
+
+/// Visits before the rollup watermark are counted from their daily rollups in link_visit_daily, and
+/// later ones from link_visits. Rolled up days are whole, so those overlapping scope.from are
+/// counted in full, and unique visits are summed across them
+pub async fn get_visit_totals(
+    db: &mut PgConnection,
+    scope: &VisitsScope,
+) -> sqlx::Result<VisitTotals> {
+    sqlx::query_as!(
+        VisitTotals,
+        r#"
+        WITH watermark AS (
+            SELECT COALESCE(
+                (SELECT rolled_up_until FROM link_visit_rollup_state)::TIMESTAMP AT TIME ZONE 'UTC',
+                '-infinity'
+            ) AS at
+        ), rolled_up AS (
+            SELECT
+                COALESCE(SUM(link_visit_daily.visits), 0) AS visits,
+                COALESCE(SUM(link_visit_daily.unique_visitors), 0) AS unique_visits
+            FROM link_visit_daily
+            JOIN links ON links.slug = link_visit_daily.link_slug
+            CROSS JOIN watermark
+            WHERE links.platform_id = $1
+                AND ($2::VARCHAR IS NULL OR link_visit_daily.link_slug = $2)
+                AND ($5 OR NOT link_visit_daily.is_bot)
+                AND link_visit_daily.day >= ($3 AT TIME ZONE 'UTC')::DATE
+                AND link_visit_daily.day::TIMESTAMP AT TIME ZONE 'UTC' < LEAST($4, watermark.at)
+        ), raw AS (
+            SELECT COUNT(*) AS visits, COUNT(DISTINCT link_visits.visitor_hash) AS unique_visits
+            FROM link_visits
+            JOIN links ON links.slug = link_visits.link_slug
+            CROSS JOIN watermark
+            WHERE links.platform_id = $1
+                AND ($2::VARCHAR IS NULL OR link_visits.link_slug = $2)
+                AND ($5 OR NOT link_visits.is_bot)
+                AND link_visits.at >= GREATEST($3, watermark.at)
+                AND link_visits.at < $4
+        )
+        SELECT
+            (rolled_up.visits + raw.visits)::BIGINT AS "visits!",
+            (rolled_up.unique_visits + raw.unique_visits)::BIGINT AS "unique_visits!"
+        FROM rolled_up, raw
+        "#,
+        scope.platform_id,
+        scope.link_slug,
+        scope.from,
+        scope.to,
+        scope.include_bots,
+    )
+    .fetch_one(&mut *db)
+    .await
+}
+
+/// Returns the all-time number of visits, excluding bots, for each of the platform's links which
+/// have been visited
+pub async fn get_visit_counts_by_link(
+    db: &mut PgConnection,
+    platform_id: &Uuid,
+) -> sqlx::Result<HashMap<String, i64>> {
+    let rows = sqlx::query!(
+        r#"
+        WITH watermark AS (
+            SELECT COALESCE(
+                (SELECT rolled_up_until FROM link_visit_rollup_state)::TIMESTAMP AT TIME ZONE 'UTC',
+                '-infinity'
+            ) AS at
+        ), counts AS (
+            SELECT link_visit_daily.link_slug, SUM(link_visit_daily.visits) AS visits
+            FROM link_visit_daily
+            JOIN links ON links.slug = link_visit_daily.link_slug
+            CROSS JOIN watermark
+            WHERE links.platform_id = $1
+                AND NOT link_visit_daily.is_bot
+                AND link_visit_daily.day::TIMESTAMP AT TIME ZONE 'UTC' < watermark.at
+            GROUP BY link_visit_daily.link_slug
+            UNION ALL
+            SELECT link_visits.link_slug, COUNT(*)
+            FROM link_visits
+            JOIN links ON links.slug = link_visits.link_slug
+            CROSS JOIN watermark
+            WHERE links.platform_id = $1 AND NOT link_visits.is_bot AND link_visits.at >= watermark.at
+            GROUP BY link_visits.link_slug
+        )
+        SELECT link_slug AS "link_slug!", SUM(visits)::BIGINT AS "visits!"
+        FROM counts
+        GROUP BY link_slug
+        "#,
+        platform_id,
+    )
+    .fetch_all(&mut *db)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.link_slug, row.visits))
+        .collect())
+}
+
+/// Returns every bucket between scope.from and scope.to in UTC, including empty ones. Day and week
+/// buckets use the daily rollups like get_visit_totals, but hour buckets only count the visits
+/// which haven't been pruned
+pub async fn get_visits_time_series(
+    db: &mut PgConnection,
+    scope: &VisitsScope,
+    bucket: StatsBucket,
+) -> sqlx::Result<Vec<VisitsBucket>> {
+    sqlx::query_as!(
+        VisitsBucket,
+        r#"
+        WITH watermark AS (
+            SELECT CASE
+                WHEN $5 = 'hour' THEN '-infinity'
+                ELSE COALESCE(
+                    (SELECT rolled_up_until FROM link_visit_rollup_state)::TIMESTAMP AT TIME ZONE 'UTC',
+                    '-infinity'
+                )
+            END AS at
+        ), counts AS (
+            SELECT
+                DATE_TRUNC($5, link_visit_daily.day::TIMESTAMP) AS start,
+                SUM(link_visit_daily.visits) AS visits,
+                SUM(link_visit_daily.unique_visitors) AS unique_visits
+            FROM link_visit_daily
+            JOIN links ON links.slug = link_visit_daily.link_slug
+            CROSS JOIN watermark
+            WHERE links.platform_id = $1
+                AND ($2::VARCHAR IS NULL OR link_visit_daily.link_slug = $2)
+                AND ($6 OR NOT link_visit_daily.is_bot)
+                AND link_visit_daily.day >= ($3 AT TIME ZONE 'UTC')::DATE
+                AND link_visit_daily.day::TIMESTAMP AT TIME ZONE 'UTC' < LEAST($4, watermark.at)
+            GROUP BY 1
+            UNION ALL
+            SELECT
+                DATE_TRUNC($5, link_visits.at AT TIME ZONE 'UTC'),
+                COUNT(*),
+                COUNT(DISTINCT link_visits.visitor_hash)
+            FROM link_visits
+            JOIN links ON links.slug = link_visits.link_slug
+            CROSS JOIN watermark
+            WHERE links.platform_id = $1
+                AND ($2::VARCHAR IS NULL OR link_visits.link_slug = $2)
+                AND ($6 OR NOT link_visits.is_bot)
+                AND link_visits.at >= GREATEST($3, watermark.at)
+                AND link_visits.at < $4
+            GROUP BY 1
+        )
+        SELECT
+            buckets.start AT TIME ZONE 'UTC' AS "start!",
+            COALESCE(SUM(counts.visits), 0)::BIGINT AS "visits!",
+            COALESCE(SUM(counts.unique_visits), 0)::BIGINT AS "unique_visits!"
+        FROM GENERATE_SERIES(
+            DATE_TRUNC($5, $3 AT TIME ZONE 'UTC'),
+            $4 AT TIME ZONE 'UTC',
+            ('1 ' || $5)::INTERVAL
+        ) AS buckets(start)
+        LEFT JOIN counts ON counts.start = buckets.start
+        WHERE buckets.start < $4 AT TIME ZONE 'UTC'
+        GROUP BY buckets.start
+        ORDER BY buckets.start
+        "#,
+        scope.platform_id,
+        scope.link_slug,
+        scope.from,
+        scope.to,
+        bucket.as_str(),
+        scope.include_bots,
+    )
+    .fetch_all(&mut *db)
+    .await
+}
+
+/// A value which the most common ones can be calculated for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TopValuesKind {
+    /// The normalized host of the Referer header, see normalize_referrer_domain
+    ReferrerDomain,
+    UserAgent,
+    Browser,
+    Os,
+    DeviceType,
+    /// ISO 3166-1 alpha-2 code
+    Country,
+    Region,
+    City,
+}
+
+impl TopValuesKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TopValuesKind::ReferrerDomain => "referrer_domain",
+            TopValuesKind::UserAgent => "user_agent",
+            TopValuesKind::Browser => "browser",
+            TopValuesKind::Os => "os",
+            TopValuesKind::DeviceType => "device_type",
+            TopValuesKind::Country => "country",
+            TopValuesKind::Region => "region",
+            TopValuesKind::City => "city",
+        }
+    }
+}
+
+/// Most common values of the kind, visits without one are excluded. Rolled up days only keep each
+/// link's most common values, so the counts of rarer values may be lower than their actual visits
+pub async fn get_top_values(
+    db: &mut PgConnection,
+    scope: &VisitsScope,
+    kind: TopValuesKind,
+    limit: i64,
+) -> sqlx::Result<Vec<VisitsByValue>> {
+    sqlx::query_as!(
+        VisitsByValue,
+        r#"
+        WITH watermark AS (
+            SELECT COALESCE(
+                (SELECT rolled_up_until FROM link_visit_rollup_state)::TIMESTAMP AT TIME ZONE 'UTC',
+                '-infinity'
+            ) AS at
+        ), counts AS (
+            SELECT entry.key AS value, SUM(entry.value::BIGINT) AS visits
+            FROM link_visit_daily
+            JOIN links ON links.slug = link_visit_daily.link_slug
+            CROSS JOIN watermark
+            CROSS JOIN JSONB_EACH_TEXT(
+                CASE $5
+                    WHEN 'referrer_domain' THEN link_visit_daily.referrer_domains
+                    WHEN 'user_agent' THEN link_visit_daily.user_agents
+                    WHEN 'browser' THEN link_visit_daily.browsers
+                    WHEN 'os' THEN link_visit_daily.operating_systems
+                    WHEN 'device_type' THEN link_visit_daily.device_types
+                    WHEN 'country' THEN link_visit_daily.countries
+                    WHEN 'region' THEN link_visit_daily.regions
+                    ELSE link_visit_daily.cities
+                END
+            ) AS entry
+            WHERE links.platform_id = $1
+                AND ($2::VARCHAR IS NULL OR link_visit_daily.link_slug = $2)
+                AND ($7 OR NOT link_visit_daily.is_bot)
+                AND link_visit_daily.day >= ($3 AT TIME ZONE 'UTC')::DATE
+                AND link_visit_daily.day::TIMESTAMP AT TIME ZONE 'UTC' < LEAST($4, watermark.at)
+            GROUP BY 1
+            UNION ALL
+            SELECT raw.value, COUNT(*)
+            FROM (
+                SELECT CASE $5
+                    WHEN 'referrer_domain' THEN link_visits.referrer_domain
+                    WHEN 'user_agent' THEN link_visits.headers->'user-agent'->>0
+                    WHEN 'browser' THEN link_visits.browser
+                    WHEN 'os' THEN link_visits.os
+                    WHEN 'device_type' THEN link_visits.device_type
+                    WHEN 'country' THEN link_visits.country
+                    WHEN 'region' THEN link_visits.region
+                    ELSE link_visits.city
+                END AS value
+                FROM link_visits
+                JOIN links ON links.slug = link_visits.link_slug
+                CROSS JOIN watermark
+                WHERE links.platform_id = $1
+                    AND ($2::VARCHAR IS NULL OR link_visits.link_slug = $2)
+                    AND ($7 OR NOT link_visits.is_bot)
+                    AND link_visits.at >= GREATEST($3, watermark.at)
+                    AND link_visits.at < $4
+            ) AS raw
+            WHERE raw.value IS NOT NULL
+            GROUP BY 1
+        )
+        SELECT value AS "value!", SUM(visits)::BIGINT AS "visits!"
+        FROM counts
+        GROUP BY 1
+        ORDER BY 2 DESC, 1
+        LIMIT $6
+        "#,
+        scope.platform_id,
+        scope.link_slug,
+        scope.from,
+        scope.to,
+        kind.as_str(),
+        limit,
+        scope.include_bots,
+    )
+    .fetch_all(&mut *db)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use crate::{
+        common::{referrer::parse_referrer, testing::db::PgPoolConn, user_agent::UserAgentInfo},
+        db::{
+            link_visit_rollups::roll_up_visit_day,
+            link_visits::{NewLinkVisit, create_link_visits, prune_link_visits},
+            links::{LinkSettings, create_link},
+            platforms::create_platform,
+        },
+    };
+
+    use super::*;
+
+    fn visit(link_slug: &str, at: DateTime<Utc>, ip_address: &str, referer: &str) -> NewLinkVisit {
+        NewLinkVisit {
+            link_slug: link_slug.to_string(),
+            at,
+            headers: HashMap::from([("referer".to_string(), vec![referer.to_string()])]),
+            ip_address: Some(ip_address.to_string()),
+            referrer: parse_referrer(referer),
+            visitor: Some(ip_address.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn bot_visit(link_slug: &str, at: DateTime<Utc>) -> NewLinkVisit {
+        NewLinkVisit {
+            user_agent: UserAgentInfo {
+                browser: Some("Googlebot".to_string()),
+                is_bot: true,
+                ..Default::default()
+            },
+            ..visit(link_slug, at, "9.9.9.9", "https://c.com/")
+        }
+    }
+
+    #[sqlx::test]
+    async fn test_visit_stats(mut db: PgPoolConn) {
+        let (_, platform) = create_platform(&mut db, "Stats").await.unwrap();
+        let (_, other_platform) = create_platform(&mut db, "Other").await.unwrap();
+
+        let mut slugs = vec![];
+        for platform_id in [&platform.id, &platform.id, &other_platform.id] {
+            let link = create_link(
+                &mut db,
+                platform_id,
+                None,
+                "https://example.com/".to_string(),
+                None,
+                &LinkSettings::default(),
+            )
+            .await
+            .unwrap();
+            slugs.push(link.slug);
+        }
+
+        let day = |d: u32, h: u32| Utc.with_ymd_and_hms(2025, 1, d, h, 0, 0).unwrap();
+
+        create_link_visits(
+            &mut db,
+            &[
+                visit(&slugs[0], day(1, 1), "1.1.1.1", "https://a.com/"),
+                visit(&slugs[0], day(1, 2), "1.1.1.1", "https://a.com/"),
+                visit(&slugs[0], day(3, 5), "2.2.2.2", "https://b.com/"),
+                visit(&slugs[1], day(3, 6), "3.3.3.3", "https://a.com/"),
+                // Outside of the range
+                visit(&slugs[0], day(9, 0), "1.1.1.1", "https://a.com/"),
+                // Other platform
+                visit(&slugs[2], day(1, 1), "1.1.1.1", "https://a.com/"),
+                // Bots
+                bot_visit(&slugs[1], day(2, 1)),
+                bot_visit(&slugs[1], day(3, 1)),
+            ],
+        )
+        .await
+        .unwrap();
+
+        let platform_scope = VisitsScope {
+            platform_id: platform.id,
+            link_slug: None,
+            from: day(1, 0),
+            to: day(4, 0),
+            include_bots: false,
+        };
+        let link_scope = VisitsScope {
+            link_slug: Some(slugs[0].clone()),
+            ..platform_scope.clone()
+        };
+        let with_bots_scope = VisitsScope {
+            include_bots: true,
+            ..platform_scope.clone()
+        };
+
+        // Stats should be the same once the first two days are rolled up and their visits pruned,
+        // except for hour buckets which only count visits that haven't been pruned
+        for rolled_up in [false, true] {
+            if rolled_up {
+                for d in [1, 2] {
+                    roll_up_visit_day(&mut db, day(d, 0).date_naive(), 10)
+                        .await
+                        .unwrap();
+                }
+                prune_link_visits(&mut db, day(3, 0), 100).await.unwrap();
+            }
+
+            assert_eq!(
+                get_visit_totals(&mut db, &platform_scope).await.unwrap(),
+                VisitTotals {
+                    visits: 4,
+                    unique_visits: 3,
+                }
+            );
+            assert_eq!(
+                get_visit_totals(&mut db, &link_scope).await.unwrap(),
+                VisitTotals {
+                    visits: 3,
+                    unique_visits: 2,
+                }
+            );
+
+            let time_series = get_visits_time_series(&mut db, &link_scope, StatsBucket::Day)
+                .await
+                .unwrap();
+            assert_eq!(
+                time_series
+                    .iter()
+                    .map(|bucket| (bucket.start, bucket.visits, bucket.unique_visits))
+                    .collect::<Vec<_>>(),
+                vec![(day(1, 0), 2, 1), (day(2, 0), 0, 0), (day(3, 0), 1, 1)]
+            );
+
+            let time_series = get_visits_time_series(&mut db, &platform_scope, StatsBucket::Hour)
+                .await
+                .unwrap();
+            assert_eq!(time_series.len(), 72);
+            assert_eq!(
+                time_series.iter().map(|b| b.visits).sum::<i64>(),
+                if rolled_up { 2 } else { 4 }
+            );
+
+            let top_referrer_domains =
+                get_top_values(&mut db, &platform_scope, TopValuesKind::ReferrerDomain, 10)
+                    .await
+                    .unwrap();
+            assert_eq!(
+                top_referrer_domains,
+                vec![
+                    VisitsByValue {
+                        value: "a.com".to_string(),
+                        visits: 3,
+                    },
+                    VisitsByValue {
+                        value: "b.com".to_string(),
+                        visits: 1,
+                    },
+                ]
+            );
+
+            let top_user_agents =
+                get_top_values(&mut db, &platform_scope, TopValuesKind::UserAgent, 10)
+                    .await
+                    .unwrap();
+            assert!(top_user_agents.is_empty());
+
+            assert_eq!(
+                get_visit_totals(&mut db, &with_bots_scope)
+                    .await
+                    .unwrap()
+                    .visits,
+                6
+            );
+            assert_eq!(
+                get_top_values(&mut db, &with_bots_scope, TopValuesKind::Browser, 10)
+                    .await
+                    .unwrap(),
+                vec![VisitsByValue {
+                    value: "Googlebot".to_string(),
+                    visits: 2,
+                }]
+            );
+            assert!(
+                get_top_values(&mut db, &platform_scope, TopValuesKind::Browser, 10)
+                    .await
+                    .unwrap()
+                    .is_empty()
+            );
+
+            let visit_counts = get_visit_counts_by_link(&mut db, &platform.id)
+                .await
+                .unwrap();
+            assert_eq!(
+                visit_counts,
+                HashMap::from([(slugs[0].clone(), 4), (slugs[1].clone(), 1)])
+            );
+        }
+    }
+}
@@ -0,0 +1,357 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use sqlx::{Connection, PgConnection};
+
+/// Every day before the returned one has been rolled up into link_visit_daily, None if no day has
+/// been rolled up yet
+pub async fn get_rollup_watermark(db: &mut PgConnection) -> sqlx::Result<Option<NaiveDate>> {
+    sqlx::query_scalar!("SELECT rolled_up_until FROM link_visit_rollup_state")
+        .fetch_optional(&mut *db)
+        .await
+}
+
+/// The UTC day of the oldest stored visit, if there are any
+pub async fn get_first_visit_day(db: &mut PgConnection) -> sqlx::Result<Option<NaiveDate>> {
+    let first_visit_at: Option<DateTime<Utc>> =
+        sqlx::query_scalar!("SELECT MIN(at) FROM link_visits")
+            .fetch_one(&mut *db)
+            .await?;
+
+    Ok(first_visit_at.map(|at| at.date_naive()))
+}
+
+/// Replaces the daily rollups of the (UTC) day with ones calculated from its visits, separately for
+/// bots and everyone else, keeping the top_values_limit most common values of each kind (referrer
+/// domains, browsers, countries, etc.) for each link, and moves the watermark past the day. Returns
+/// false without changing anything if the day is already before the watermark, since its visits
+/// may have been pruned since it was rolled up
+pub async fn roll_up_visit_day(
+    db: &mut PgConnection,
+    day: NaiveDate,
+    top_values_limit: i64,
+) -> sqlx::Result<bool> {
+    let mut tx = db.begin().await?;
+
+    // Rollups from other tasks and instances wait for this one, so the watermark can't be stale
+    sqlx::query!("LOCK TABLE link_visit_rollup_state IN EXCLUSIVE MODE")
+        .execute(&mut *tx)
+        .await?;
+
+    if get_rollup_watermark(&mut tx)
+        .await?
+        .is_some_and(|watermark| day < watermark)
+    {
+        return Ok(false);
+    }
+
+    sqlx::query!("DELETE FROM link_visit_daily WHERE day = $1", day)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query!(
+        r#"
+        WITH day_visits AS (
+            SELECT
+                link_slug,
+                is_bot,
+                visitor_hash,
+                referrer_domain,
+                headers->'user-agent'->>0 AS user_agent,
+                browser,
+                os,
+                device_type,
+                country,
+                region,
+                city
+            FROM link_visits
+            WHERE at >= $1::DATE::TIMESTAMP AT TIME ZONE 'UTC'
+                AND at < ($1::DATE + 1)::TIMESTAMP AT TIME ZONE 'UTC'
+        ), value_counts AS (
+            SELECT
+                day_visits.link_slug,
+                day_visits.is_bot,
+                v.kind,
+                v.value,
+                COUNT(*) AS visits,
+                ROW_NUMBER() OVER (
+                    PARTITION BY day_visits.link_slug, day_visits.is_bot, v.kind
+                    ORDER BY COUNT(*) DESC, v.value
+                ) AS rank
+            FROM day_visits
+            CROSS JOIN LATERAL (
+                VALUES
+                    ('referrer_domain', day_visits.referrer_domain),
+                    ('user_agent', day_visits.user_agent),
+                    ('browser', day_visits.browser),
+                    ('os', day_visits.os),
+                    ('device_type', day_visits.device_type),
+                    ('country', day_visits.country),
+                    ('region', day_visits.region),
+                    ('city', day_visits.city)
+            ) AS v(kind, value)
+            WHERE v.value IS NOT NULL
+            GROUP BY day_visits.link_slug, day_visits.is_bot, v.kind, v.value
+        ), top_values AS (
+            SELECT link_slug, is_bot, kind, JSONB_OBJECT_AGG(value, visits) AS counts
+            FROM value_counts
+            WHERE rank <= $2
+            GROUP BY link_slug, is_bot, kind
+        )
+        INSERT INTO link_visit_daily (
+            link_slug,
+            day,
+            is_bot,
+            visits,
+            unique_visitors,
+            referrer_domains,
+            user_agents,
+            browsers,
+            operating_systems,
+            device_types,
+            countries,
+            regions,
+            cities
+        )
+        SELECT
+            day_visits.link_slug,
+            $1,
+            day_visits.is_bot,
+            COUNT(*),
+            COUNT(DISTINCT day_visits.visitor_hash),
+            COALESCE(
+                (
+                    SELECT t.counts FROM top_values t
+                    WHERE t.link_slug = day_visits.link_slug
+                        AND t.is_bot = day_visits.is_bot
+                        AND t.kind = 'referrer_domain'
+                ),
+                '{}'
+            ),
+            COALESCE(
+                (
+                    SELECT t.counts FROM top_values t
+                    WHERE t.link_slug = day_visits.link_slug
+                        AND t.is_bot = day_visits.is_bot
+                        AND t.kind = 'user_agent'
+                ),
+                '{}'
+            ),
+            COALESCE(
+                (
+                    SELECT t.counts FROM top_values t
+                    WHERE t.link_slug = day_visits.link_slug
+                        AND t.is_bot = day_visits.is_bot
+                        AND t.kind = 'browser'
+                ),
+                '{}'
+            ),
+            COALESCE(
+                (
+                    SELECT t.counts FROM top_values t
+                    WHERE t.link_slug = day_visits.link_slug
+                        AND t.is_bot = day_visits.is_bot
+                        AND t.kind = 'os'
+                ),
+                '{}'
+            ),
+            COALESCE(
+                (
+                    SELECT t.counts FROM top_values t
+                    WHERE t.link_slug = day_visits.link_slug
+                        AND t.is_bot = day_visits.is_bot
+                        AND t.kind = 'device_type'
+                ),
+                '{}'
+            ),
+            COALESCE(
+                (
+                    SELECT t.counts FROM top_values t
+                    WHERE t.link_slug = day_visits.link_slug
+                        AND t.is_bot = day_visits.is_bot
+                        AND t.kind = 'country'
+                ),
+                '{}'
+            ),
+            COALESCE(
+                (
+                    SELECT t.counts FROM top_values t
+                    WHERE t.link_slug = day_visits.link_slug
+                        AND t.is_bot = day_visits.is_bot
+                        AND t.kind = 'region'
+                ),
+                '{}'
+            ),
+            COALESCE(
+                (
+                    SELECT t.counts FROM top_values t
+                    WHERE t.link_slug = day_visits.link_slug
+                        AND t.is_bot = day_visits.is_bot
+                        AND t.kind = 'city'
+                ),
+                '{}'
+            )
+        FROM day_visits
+        GROUP BY day_visits.link_slug, day_visits.is_bot
+        "#,
+        day,
+        top_values_limit,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO link_visit_rollup_state (rolled_up_until) VALUES ($1::DATE + 1)
+        ON CONFLICT (id) DO UPDATE SET rolled_up_until = EXCLUDED.rolled_up_until
+        "#,
+        day,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::TimeZone;
+
+    use crate::{
+        common::{
+            geoip::VisitLocation, referrer::parse_referrer, testing::db::PgPoolConn,
+            user_agent::UserAgentInfo,
+        },
+        db::{
+            link_visits::{NewLinkVisit, create_link_visits},
+            links::{LinkSettings, create_link},
+            platforms::create_platform,
+        },
+    };
+
+    use super::*;
+
+    #[sqlx::test]
+    async fn test_roll_up_visit_day(mut db: PgPoolConn) {
+        let (_, platform) = create_platform(&mut db, "Rollups").await.unwrap();
+        let link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://example.com/".to_string(),
+            None,
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+
+        let at = |d: u32, h: u32| Utc.with_ymd_and_hms(2025, 1, d, h, 0, 0).unwrap();
+        let visit = |at, ip_address: &str, referer: &str| NewLinkVisit {
+            link_slug: link.slug.clone(),
+            at,
+            headers: HashMap::from([
+                ("referer".to_string(), vec![referer.to_string()]),
+                ("user-agent".to_string(), vec!["Firefox".to_string()]),
+            ]),
+            ip_address: Some(ip_address.to_string()),
+            user_agent: UserAgentInfo {
+                browser: Some("Firefox".to_string()),
+                ..Default::default()
+            },
+            location: VisitLocation {
+                country: Some("GB".to_string()),
+                ..Default::default()
+            },
+            referrer: parse_referrer(referer),
+            visitor: Some(ip_address.to_string()),
+        };
+
+        create_link_visits(
+            &mut db,
+            &[
+                visit(at(1, 0), "1.1.1.1", "https://a.com/x"),
+                visit(at(1, 12), "1.1.1.1", "https://A.com/y"),
+                visit(at(1, 23), "2.2.2.2", "https://b.com/"),
+                visit(at(1, 23), "2.2.2.2", "not a url"),
+                visit(at(2, 0), "3.3.3.3", "https://c.com/"),
+                NewLinkVisit {
+                    user_agent: UserAgentInfo {
+                        is_bot: true,
+                        ..Default::default()
+                    },
+                    ..visit(at(1, 6), "4.4.4.4", "https://d.com/")
+                },
+            ],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(get_rollup_watermark(&mut db).await.unwrap(), None);
+        assert_eq!(
+            get_first_visit_day(&mut db).await.unwrap(),
+            Some(at(1, 0).date_naive())
+        );
+
+        assert!(
+            roll_up_visit_day(&mut db, at(1, 0).date_naive(), 1)
+                .await
+                .unwrap()
+        );
+
+        // Rolling up a day again once it's been rolled up, like a task which read the watermark
+        // before it moved would, doesn't replace its rollups with ones from what's left of its visits
+        sqlx::query!("DELETE FROM link_visits")
+            .execute(&mut *db)
+            .await
+            .unwrap();
+        assert!(
+            !roll_up_visit_day(&mut db, at(1, 0).date_naive(), 1)
+                .await
+                .unwrap()
+        );
+
+        assert_eq!(
+            get_rollup_watermark(&mut db).await.unwrap(),
+            Some(at(2, 0).date_naive())
+        );
+
+        let rollups = sqlx::query!(
+            r#"
+            SELECT
+                day,
+                is_bot,
+                visits,
+                unique_visitors,
+                referrer_domains,
+                user_agents,
+                browsers,
+                countries,
+                cities
+            FROM link_visit_daily
+            ORDER BY is_bot
+            "#
+        )
+        .fetch_all(&mut *db)
+        .await
+        .unwrap();
+
+        assert_eq!(rollups.len(), 2);
+        assert_eq!(rollups[0].day, at(1, 0).date_naive());
+        assert!(!rollups[0].is_bot);
+        assert_eq!(rollups[0].visits, 4);
+        assert_eq!(rollups[0].unique_visitors, 2);
+        assert_eq!(rollups[0].referrer_domains, serde_json::json!({"a.com": 2}));
+        assert_eq!(rollups[0].user_agents, serde_json::json!({"Firefox": 4}));
+        assert_eq!(rollups[0].browsers, serde_json::json!({"Firefox": 4}));
+        assert_eq!(rollups[0].countries, serde_json::json!({"GB": 4}));
+        assert_eq!(rollups[0].cities, serde_json::json!({}));
+
+        // Bots are rolled up separately
+        assert!(rollups[1].is_bot);
+        assert_eq!(rollups[1].visits, 1);
+        assert_eq!(rollups[1].referrer_domains, serde_json::json!({"d.com": 1}));
+        assert_eq!(rollups[1].browsers, serde_json::json!({}));
+    }
+}
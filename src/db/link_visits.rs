@@ -1,35 +1,238 @@
 use std::collections::HashMap;
 
+use chrono::{DateTime, NaiveDate, Utc};
 use sqlx::PgConnection;
 
-pub async fn create_link_visit(
+use crate::common::{geoip::VisitLocation, referrer::VisitReferrer, user_agent::UserAgentInfo};
+
+#[derive(Debug, Clone, Default)]
+pub struct NewLinkVisit {
+    pub link_slug: String,
+    pub at: DateTime<Utc>,
+    pub headers: HashMap<String, Vec<String>>,
+    pub ip_address: Option<String>,
+    /// Classified from the User-Agent header before it's filtered by the privacy settings
+    pub user_agent: UserAgentInfo,
+    /// Resolved from the IP address before it's anonymized
+    pub location: VisitLocation,
+    pub referrer: VisitReferrer,
+    /// Identifies the visitor, like their IP address and user agent. Only a hash of it salted with
+    /// the salt of the visit's day is stored, see create_link_visits
+    pub visitor: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LinkVisit {
+    pub at: DateTime<Utc>,
+    /// Header names mapped to each of their values
+    pub headers: serde_json::Value,
+    pub ip_address: Option<String>,
+    pub referrer: Option<String>,
+}
+
+/// Makes sure each of the (UTC) days has a salt and deletes the salts of days before yesterday,
+/// which are no longer needed since visits are recorded shortly after they happen
+async fn rotate_visitor_salts(db: &mut PgConnection, days: &[NaiveDate]) -> sqlx::Result<()> {
+    sqlx::query!("DELETE FROM visitor_salts WHERE day < (NOW() AT TIME ZONE 'UTC')::DATE - 1")
+        .execute(&mut *db)
+        .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO visitor_salts (day, salt)
+        SELECT day, UUID_SEND(GEN_RANDOM_UUID()) || UUID_SEND(GEN_RANDOM_UUID())
+        FROM UNNEST($1::DATE[]) AS day
+        ON CONFLICT (day) DO NOTHING
+        "#,
+        days,
+    )
+    .execute(&mut *db)
+    .await?;
+
+    Ok(())
+}
+
+/// Inserts the visits in a single statement, skipping any for links which no longer exist.
+/// Visitors are stored as a hash salted with a random salt of the visit's day, so visits from the
+/// same visitor can be related within a day without storing who they are. Returns the number of
+/// visits inserted
+pub async fn create_link_visits(
     db: &mut PgConnection,
-    slug: &str,
-    headers: HashMap<String, Vec<String>>,
-    ip_address: Option<&str>,
-) -> sqlx::Result<()> {
+    visits: &[NewLinkVisit],
+) -> sqlx::Result<u64> {
+    let mut link_slugs = Vec::with_capacity(visits.len());
+    let mut ats = Vec::with_capacity(visits.len());
+    let mut headers = Vec::with_capacity(visits.len());
+    let mut ip_addresses = Vec::with_capacity(visits.len());
+    let mut browsers = Vec::with_capacity(visits.len());
+    let mut operating_systems = Vec::with_capacity(visits.len());
+    let mut device_types = Vec::with_capacity(visits.len());
+    let mut is_bots = Vec::with_capacity(visits.len());
+    let mut countries = Vec::with_capacity(visits.len());
+    let mut regions = Vec::with_capacity(visits.len());
+    let mut cities = Vec::with_capacity(visits.len());
+    let mut referrers = Vec::with_capacity(visits.len());
+    let mut referrer_domains = Vec::with_capacity(visits.len());
+    let mut visitors = Vec::with_capacity(visits.len());
+
+    for visit in visits {
+        link_slugs.push(visit.link_slug.clone());
+        ats.push(visit.at);
+        headers.push(serde_json::to_value(&visit.headers).unwrap());
+        ip_addresses.push(visit.ip_address.clone());
+        browsers.push(visit.user_agent.browser.clone());
+        operating_systems.push(visit.user_agent.os.clone());
+        device_types.push(visit.user_agent.device_type.map(|d| d.as_str().to_string()));
+        is_bots.push(visit.user_agent.is_bot);
+        countries.push(visit.location.country.clone());
+        regions.push(visit.location.region.clone());
+        cities.push(visit.location.city.clone());
+        referrers.push(visit.referrer.url.clone());
+        referrer_domains.push(visit.referrer.domain.clone());
+        visitors.push(visit.visitor.clone());
+    }
+
+    let mut days = ats.iter().map(|at| at.date_naive()).collect::<Vec<_>>();
+    days.sort_unstable();
+    days.dedup();
+
+    rotate_visitor_salts(&mut *db, &days).await?;
+
     sqlx::query!(
-        "INSERT INTO link_visits (link_slug, at, headers, ip_address) VALUES ($1, NOW(), $2, $3)",
+        r#"
+        INSERT INTO link_visits (
+            link_slug,
+            at,
+            headers,
+            ip_address,
+            browser,
+            os,
+            device_type,
+            is_bot,
+            country,
+            region,
+            city,
+            referrer,
+            referrer_domain,
+            visitor_hash
+        )
+        SELECT
+            v.link_slug,
+            v.at,
+            v.headers,
+            v.ip_address,
+            v.browser,
+            v.os,
+            v.device_type,
+            v.is_bot,
+            v.country,
+            v.region,
+            v.city,
+            v.referrer,
+            v.referrer_domain,
+            ENCODE(SUBSTRING(SHA256(visitor_salts.salt || CONVERT_TO(v.visitor, 'UTF8')) FOR 16), 'hex')
+        FROM UNNEST(
+            $1::VARCHAR[],
+            $2::TIMESTAMPTZ[],
+            $3::JSONB[],
+            $4::VARCHAR[],
+            $5::VARCHAR[],
+            $6::VARCHAR[],
+            $7::VARCHAR[],
+            $8::BOOLEAN[],
+            $9::VARCHAR[],
+            $10::VARCHAR[],
+            $11::VARCHAR[],
+            $12::VARCHAR[],
+            $13::VARCHAR[],
+            $14::VARCHAR[]
+        ) AS v(
+            link_slug,
+            at,
+            headers,
+            ip_address,
+            browser,
+            os,
+            device_type,
+            is_bot,
+            country,
+            region,
+            city,
+            referrer,
+            referrer_domain,
+            visitor
+        )
+        JOIN links ON links.slug = v.link_slug
+        LEFT JOIN visitor_salts ON visitor_salts.day = (v.at AT TIME ZONE 'UTC')::DATE
+        "#,
+        &link_slugs,
+        &ats,
+        &headers,
+        &ip_addresses as &[Option<String>],
+        &browsers as &[Option<String>],
+        &operating_systems as &[Option<String>],
+        &device_types as &[Option<String>],
+        &is_bots,
+        &countries as &[Option<String>],
+        &regions as &[Option<String>],
+        &cities as &[Option<String>],
+        &referrers as &[Option<String>],
+        &referrer_domains as &[Option<String>],
+        &visitors as &[Option<String>],
+    )
+    .execute(&mut *db)
+    .await
+    .map(|result| result.rows_affected())
+}
+
+/// Retrieves the link's most recent visits, newest first
+pub async fn get_recent_link_visits(
+    db: &mut PgConnection,
+    slug: &str,
+    limit: i64,
+) -> sqlx::Result<Vec<LinkVisit>> {
+    sqlx::query_as!(
+        LinkVisit,
+        "SELECT at, headers, ip_address, referrer FROM link_visits WHERE link_slug = $1 ORDER BY at DESC LIMIT $2",
         slug,
-        serde_json::to_value(headers).unwrap(),
-        ip_address,
+        limit,
+    )
+    .fetch_all(&mut *db)
+    .await
+}
+
+/// Deletes up to limit visits from before the specified time. Returns the number of visits deleted
+pub async fn prune_link_visits(
+    db: &mut PgConnection,
+    before: DateTime<Utc>,
+    limit: i64,
+) -> sqlx::Result<u64> {
+    sqlx::query!(
+        "DELETE FROM link_visits WHERE ctid IN (SELECT ctid FROM link_visits WHERE at < $1 LIMIT $2)",
+        before,
+        limit,
     )
     .execute(&mut *db)
     .await
-    .map(|_| ())
+    .map(|result| result.rows_affected())
 }
 
 #[cfg(test)]
 pub mod tests {
+    use chrono::TimeZone;
+
     use super::*;
 
     use crate::{
         common::testing::db::PgPoolConn,
-        db::{links::create_link, platforms::create_platform},
+        db::{
+            links::{LinkSettings, create_link},
+            platforms::create_platform,
+        },
     };
 
     #[sqlx::test]
-    async fn test_create_link_visit(mut db: PgPoolConn) {
+    async fn test_create_link_visits(mut db: PgPoolConn) {
         let (_, platform) = create_platform(&mut db, "Guacamole").await.unwrap();
 
         let link = create_link(
@@ -38,16 +241,28 @@ pub mod tests {
             None,
             "https://iapetus11.me/fractals".to_string(),
             None,
+            &LinkSettings::default(),
         )
         .await
         .unwrap();
 
-        create_link_visit(&mut db, &link.slug, HashMap::new(), Some("0.0.0.0"))
-            .await
-            .unwrap();
-        create_link_visit(&mut db, &link.slug, HashMap::new(), Some("0.0.0.0"))
+        let visit = NewLinkVisit {
+            link_slug: link.slug.clone(),
+            at: Utc::now(),
+            headers: HashMap::new(),
+            ip_address: Some("0.0.0.0".to_string()),
+            ..Default::default()
+        };
+        let deleted_link_visit = NewLinkVisit {
+            link_slug: "deleted".to_string(),
+            ip_address: None,
+            ..visit.clone()
+        };
+
+        let inserted = create_link_visits(&mut db, &[visit.clone(), deleted_link_visit, visit])
             .await
             .unwrap();
+        assert_eq!(inserted, 2);
 
         let visit_count = sqlx::query!(
             "SELECT COUNT(*) FROM link_visits WHERE link_slug = $1",
@@ -58,4 +273,146 @@ pub mod tests {
         .unwrap();
         assert_eq!(visit_count.count, Some(2));
     }
+
+    #[sqlx::test]
+    async fn test_create_link_visits_hashes_visitors(mut db: PgPoolConn) {
+        let (_, platform) = create_platform(&mut db, "Guacamole").await.unwrap();
+
+        let link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://iapetus11.me/fractals".to_string(),
+            None,
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+
+        let now = Utc::now();
+        let visit = |minutes_ago: i64, visitor: Option<&str>| NewLinkVisit {
+            link_slug: link.slug.clone(),
+            at: now - chrono::TimeDelta::minutes(minutes_ago),
+            visitor: visitor.map(String::from),
+            ..Default::default()
+        };
+
+        create_link_visits(
+            &mut db,
+            &[
+                visit(0, Some("1.1.1.1\nFirefox")),
+                visit(1, Some("1.1.1.1\nFirefox")),
+                visit(2, Some("1.1.1.1\nChrome")),
+                visit(3, None),
+                visit(24 * 60, Some("1.1.1.1\nFirefox")),
+                visit(7 * 24 * 60, Some("1.1.1.1\nFirefox")),
+            ],
+        )
+        .await
+        .unwrap();
+
+        let hashes = sqlx::query_scalar!("SELECT visitor_hash FROM link_visits ORDER BY at DESC")
+            .fetch_all(&mut *db)
+            .await
+            .unwrap();
+
+        // The same visitor has the same hash within a day, but not across days
+        assert_eq!(hashes[0], hashes[1]);
+        assert_ne!(hashes[0], hashes[2]);
+        assert_eq!(hashes[3], None);
+        assert_ne!(hashes[0], hashes[4]);
+        assert!(hashes.iter().flatten().all(|hash| hash.len() == 32));
+
+        // Only the salts of today and yesterday are kept
+        create_link_visits(&mut db, &[]).await.unwrap();
+
+        let salt_days = sqlx::query_scalar!("SELECT day FROM visitor_salts ORDER BY day")
+            .fetch_all(&mut *db)
+            .await
+            .unwrap();
+        assert_eq!(
+            salt_days,
+            vec![
+                (now - chrono::TimeDelta::days(1)).date_naive(),
+                now.date_naive()
+            ]
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_get_recent_link_visits(mut db: PgPoolConn) {
+        let (_, platform) = create_platform(&mut db, "Guacamole").await.unwrap();
+
+        let link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://iapetus11.me/fractals".to_string(),
+            None,
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+
+        let now = Utc::now();
+        let visits = (0..3)
+            .map(|i| NewLinkVisit {
+                link_slug: link.slug.clone(),
+                at: now - chrono::TimeDelta::minutes(i),
+                headers: HashMap::from([("x-visit".to_string(), vec![i.to_string()])]),
+                ip_address: None,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        create_link_visits(&mut db, &visits).await.unwrap();
+
+        let recent_visits = get_recent_link_visits(&mut db, &link.slug, 2)
+            .await
+            .unwrap();
+        assert_eq!(recent_visits.len(), 2);
+        assert_eq!(recent_visits[0].headers["x-visit"][0], "0");
+        assert_eq!(recent_visits[1].headers["x-visit"][0], "1");
+    }
+
+    #[sqlx::test]
+    async fn test_prune_link_visits(mut db: PgPoolConn) {
+        let (_, platform) = create_platform(&mut db, "Guacamole").await.unwrap();
+
+        let link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://iapetus11.me/fractals".to_string(),
+            None,
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+
+        let day = |d: u32, h: u32| Utc.with_ymd_and_hms(2025, 1, d, h, 0, 0).unwrap();
+        let visits = [day(1, 1), day(1, 2), day(1, 3), day(2, 1), day(5, 1)]
+            .into_iter()
+            .map(|at| NewLinkVisit {
+                link_slug: link.slug.clone(),
+                at,
+                headers: HashMap::new(),
+                ip_address: None,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        create_link_visits(&mut db, &visits).await.unwrap();
+
+        for expected_pruned in [2, 2, 0] {
+            assert_eq!(
+                prune_link_visits(&mut db, day(3, 0), 2).await.unwrap(),
+                expected_pruned
+            );
+        }
+
+        let remaining_visits = get_recent_link_visits(&mut db, &link.slug, 10)
+            .await
+            .unwrap();
+        assert_eq!(remaining_visits.len(), 1);
+        assert_eq!(remaining_visits[0].at, day(5, 1));
+    }
 }
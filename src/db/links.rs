@@ -1,8 +1,64 @@
+use std::str::FromStr;
+
 use chrono::{DateTime, Utc};
 use rand::distr::{Alphanumeric, SampleString};
-use sqlx::PgConnection;
+use serde::{Deserialize, Serialize};
+use sqlx::{Connection, PgConnection};
 use uuid::Uuid;
 
+/// Maps to the HTTP status code a link redirects with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "redirect_type", rename_all = "snake_case")]
+pub enum RedirectType {
+    /// 301
+    MovedPermanently,
+    /// 302
+    Found,
+    /// 307
+    TemporaryRedirect,
+    /// 308
+    PermanentRedirect,
+}
+
+impl RedirectType {
+    pub const ALL: [RedirectType; 4] = [
+        RedirectType::MovedPermanently,
+        RedirectType::Found,
+        RedirectType::TemporaryRedirect,
+        RedirectType::PermanentRedirect,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RedirectType::MovedPermanently => "moved_permanently",
+            RedirectType::Found => "found",
+            RedirectType::TemporaryRedirect => "temporary_redirect",
+            RedirectType::PermanentRedirect => "permanent_redirect",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            RedirectType::MovedPermanently => "301 Moved Permanently",
+            RedirectType::Found => "302 Found",
+            RedirectType::TemporaryRedirect => "307 Temporary Redirect",
+            RedirectType::PermanentRedirect => "308 Permanent Redirect",
+        }
+    }
+}
+
+impl FromStr for RedirectType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        RedirectType::ALL
+            .into_iter()
+            .find(|redirect_type| redirect_type.as_str() == s)
+            .ok_or(())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Link {
     pub slug: String,
@@ -10,6 +66,36 @@ pub struct Link {
     pub url: String,
     pub metadata: Option<serde_json::Value>,
     pub created_at: DateTime<Utc>,
+    /// After this point in time the link no longer redirects
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Once visit_count reaches this the link no longer redirects
+    pub max_visits: Option<i64>,
+    /// Only counted for links with max_visits set, see claim_link_visit
+    pub visit_count: i64,
+    /// If None, the platform's default redirect type is used
+    pub redirect_type: Option<RedirectType>,
+    /// Disabled links don't redirect, but unlike deleted links they can be re-enabled
+    pub disabled: bool,
+}
+
+impl Link {
+    pub fn remaining_visits(&self) -> Option<i64> {
+        self.max_visits
+            .map(|max_visits| (max_visits - self.visit_count).max(0))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now())
+    }
+}
+
+/// Optional settings for a link which can be specified on creation
+#[derive(Debug, Default, Clone)]
+pub struct LinkSettings {
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_visits: Option<i64>,
+    pub redirect_type: Option<RedirectType>,
 }
 
 /// Create a link in the database, a slug will be automatically generated if not provided
@@ -19,6 +105,7 @@ pub async fn create_link(
     mut slug: Option<String>,
     url: String,
     metadata: Option<serde_json::Value>,
+    settings: &LinkSettings,
 ) -> sqlx::Result<Link> {
     let mut result: Option<sqlx::Result<Link>> = None;
 
@@ -40,22 +127,39 @@ pub async fn create_link(
             )
         }
 
-        result = Some(
-            sqlx::query_as!(
-                Link,
-                r#"
-                    INSERT INTO links (slug, platform_id, url, metadata, created_at)
-                    VALUES ($1, $2, $3, $4, NOW())
-                    RETURNING slug, platform_id, url, metadata, created_at;
-                "#,
-                slug,
-                platform_id,
-                url,
-                metadata,
-            )
-            .fetch_one(&mut *db)
-            .await,
-        );
+        // Each attempt runs in its own transaction, or a savepoint if the caller is already in a
+        // transaction, so a unique violation doesn't abort the caller's transaction
+        let mut attempt = db.begin().await?;
+
+        let attempt_result = sqlx::query_as!(
+            Link,
+            r#"
+                INSERT INTO links (
+                    slug, platform_id, url, metadata, created_at, expires_at, max_visits,
+                    redirect_type
+                )
+                VALUES ($1, $2, $3, $4, NOW(), $5, $6, $7)
+                RETURNING
+                    slug, platform_id, url, metadata, created_at, expires_at, max_visits,
+                    visit_count, redirect_type AS "redirect_type: RedirectType", disabled;
+            "#,
+            slug,
+            platform_id,
+            url,
+            metadata,
+            settings.expires_at,
+            settings.max_visits,
+            settings.redirect_type as Option<RedirectType>,
+        )
+        .fetch_one(&mut *attempt)
+        .await;
+
+        match attempt_result {
+            Ok(_) => attempt.commit().await?,
+            Err(_) => attempt.rollback().await?,
+        }
+
+        result = Some(attempt_result);
     }
 
     result.unwrap()
@@ -66,7 +170,11 @@ pub async fn get_link(db: &mut PgConnection, slug: &str) -> sqlx::Result<Option<
     sqlx::query_as!(
         Link,
         r#"
-            SELECT slug, platform_id, url, metadata, created_at FROM links WHERE slug = $1
+            SELECT
+                slug, platform_id, url, metadata, created_at, expires_at, max_visits, visit_count,
+                redirect_type AS "redirect_type: RedirectType", disabled
+            FROM links
+            WHERE slug = $1
         "#,
         slug,
     )
@@ -79,7 +187,8 @@ pub async fn get_links(db: &mut PgConnection, platform_id: &Uuid) -> sqlx::Resul
         Link,
         r#"
             SELECT
-                slug, platform_id, url, metadata, created_at
+                slug, platform_id, url, metadata, created_at, expires_at, max_visits, visit_count,
+                redirect_type AS "redirect_type: RedirectType", disabled
             FROM links
             WHERE platform_id = $1
             ORDER BY created_at DESC;
@@ -90,13 +199,183 @@ pub async fn get_links(db: &mut PgConnection, platform_id: &Uuid) -> sqlx::Resul
     .await
 }
 
-/// Attempts to delete a link from the database, returning the deleted link or None if no link
-/// for the specified slug exists
-pub async fn delete_link(db: &mut PgConnection, link_slug: &str) -> sqlx::Result<Option<Link>> {
+#[derive(Debug, Default)]
+pub struct LinksFilter {
+    /// Case insensitive substring of the link's URL
+    pub url_contains: Option<String>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub created_after: Option<DateTime<Utc>>,
+    /// JSON which the link's metadata must contain (see the JSONB `@>` operator)
+    pub metadata_contains: Option<serde_json::Value>,
+}
+
+/// Position in a list of links ordered by newest first, the slug is used to break ties between
+/// links created at the same time
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LinksPageCursor {
+    pub created_at: DateTime<Utc>,
+    pub slug: String,
+}
+
+impl From<&Link> for LinksPageCursor {
+    fn from(value: &Link) -> Self {
+        LinksPageCursor {
+            created_at: value.created_at,
+            slug: value.slug.clone(),
+        }
+    }
+}
+
+/// Retrieve up to `limit` of a platform's links matching the filter, newest first, starting after
+/// the cursor if one is provided
+pub async fn get_links_page(
+    db: &mut PgConnection,
+    platform_id: &Uuid,
+    filter: &LinksFilter,
+    after: Option<&LinksPageCursor>,
+    limit: i64,
+) -> sqlx::Result<Vec<Link>> {
+    sqlx::query_as!(
+        Link,
+        r#"
+            SELECT
+                slug, platform_id, url, metadata, created_at, expires_at, max_visits, visit_count,
+                redirect_type AS "redirect_type: RedirectType", disabled
+            FROM links
+            WHERE
+                platform_id = $1
+                AND ($2::VARCHAR IS NULL OR STRPOS(LOWER(url), LOWER($2)) > 0)
+                AND ($3::TIMESTAMPTZ IS NULL OR created_at < $3)
+                AND ($4::TIMESTAMPTZ IS NULL OR created_at > $4)
+                AND ($5::JSONB IS NULL OR metadata @> $5)
+                AND ($6::TIMESTAMPTZ IS NULL OR (created_at, slug) < ($6, $7::VARCHAR))
+            ORDER BY created_at DESC, slug DESC
+            LIMIT $8;
+        "#,
+        platform_id,
+        filter.url_contains,
+        filter.created_before,
+        filter.created_after,
+        filter.metadata_contains,
+        after.map(|c| c.created_at),
+        after.map(|c| c.slug.as_str()),
+        limit,
+    )
+    .fetch_all(&mut *db)
+    .await
+}
+
+#[derive(Serialize, Default)]
+pub struct UpdateLinkData {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Set to `Some(serde_json::Value::Null)` to clear the link's metadata
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+    /// Set to `Some(None)` to remove the link's expiration
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<Option<DateTime<Utc>>>,
+    /// Set to `Some(None)` to remove the link's visit limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_visits: Option<Option<i64>>,
+    /// Set to `Some(None)` to use the platform's default redirect type
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirect_type: Option<Option<RedirectType>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disabled: Option<bool>,
+}
+
+/// Updates a link with the provided values, if fields are set as None then they are not updated.
+/// Returns the updated Link, or if the platform has no link with the specified slug, None.
+pub async fn update_link(
+    db: &mut PgConnection,
+    platform_id: &Uuid,
+    slug: &str,
+    update_data: &UpdateLinkData,
+) -> sqlx::Result<Option<Link>> {
+    sqlx::query_as!(
+        Link,
+        r#"
+            UPDATE links
+            SET
+                url = CASE
+                    WHEN $2 ? 'url'
+                    THEN ($2->>'url')::VARCHAR
+                    ELSE url END,
+                metadata = CASE
+                    WHEN $2 ? 'metadata'
+                    THEN NULLIF($2->'metadata', 'null'::JSONB)
+                    ELSE metadata END,
+                expires_at = CASE
+                    WHEN $2 ? 'expires_at'
+                    THEN ($2->>'expires_at')::TIMESTAMPTZ
+                    ELSE expires_at END,
+                max_visits = CASE
+                    WHEN $2 ? 'max_visits'
+                    THEN ($2->>'max_visits')::BIGINT
+                    ELSE max_visits END,
+                redirect_type = CASE
+                    WHEN $2 ? 'redirect_type'
+                    THEN ($2->>'redirect_type')::redirect_type
+                    ELSE redirect_type END,
+                disabled = CASE
+                    WHEN $2 ? 'disabled'
+                    THEN ($2->>'disabled')::BOOLEAN
+                    ELSE disabled END
+            WHERE slug = $1 AND platform_id = $3
+            RETURNING
+                slug, platform_id, url, metadata, created_at, expires_at, max_visits, visit_count,
+                redirect_type AS "redirect_type: RedirectType", disabled
+        "#,
+        slug,
+        serde_json::to_value(update_data).unwrap(),
+        platform_id,
+    )
+    .fetch_optional(&mut *db)
+    .await
+}
+
+/// Atomically counts a visit towards a link's visit limit, returning false if the link has no
+/// visits remaining (or has expired, is disabled, or has no limit) in which case the visit should be refused
+pub async fn claim_link_visit(db: &mut PgConnection, slug: &str) -> sqlx::Result<bool> {
+    let claimed = sqlx::query!(
+        r#"
+            UPDATE links
+            SET visit_count = visit_count + 1
+            WHERE
+                slug = $1
+                AND max_visits IS NOT NULL
+                AND visit_count < max_visits
+                AND (expires_at IS NULL OR expires_at > NOW())
+                AND NOT disabled
+            RETURNING slug;
+        "#,
+        slug,
+    )
+    .fetch_optional(&mut *db)
+    .await?;
+
+    Ok(claimed.is_some())
+}
+
+/// Attempts to delete a link from the database, returning the deleted link or None if the platform
+/// has no link for the specified slug
+pub async fn delete_link(
+    db: &mut PgConnection,
+    platform_id: &Uuid,
+    link_slug: &str,
+) -> sqlx::Result<Option<Link>> {
     sqlx::query_as!(
         Link,
-        "DELETE FROM links WHERE slug = $1 RETURNING slug, platform_id, url, metadata, created_at;",
-        link_slug
+        r#"
+            DELETE FROM links
+            WHERE slug = $1 AND platform_id = $2
+            RETURNING
+                slug, platform_id, url, metadata, created_at, expires_at, max_visits, visit_count,
+                redirect_type AS "redirect_type: RedirectType", disabled;
+        "#,
+        link_slug,
+        platform_id,
     )
     .fetch_optional(&mut *db)
     .await
@@ -121,6 +400,7 @@ mod tests {
             Some(json!({
                 "w": 123,
             })),
+            &LinkSettings::default(),
         )
         .await
         .unwrap();
@@ -151,6 +431,7 @@ mod tests {
             Some(json!({
                 "w": 123,
             })),
+            &LinkSettings::default(),
         )
         .await
         .unwrap();
@@ -158,6 +439,30 @@ mod tests {
         assert_eq!(link.slug.len(), 7);
     }
 
+    #[sqlx::test]
+    async fn test_create_link_with_used_slug_in_transaction(mut db: PgPoolConn) {
+        let (_, platform) = create_platform(&mut db, "Test").await.unwrap();
+
+        let mut tx = db.begin().await.unwrap();
+
+        for expected_created in [true, false] {
+            let result = create_link(
+                &mut tx,
+                &platform.id,
+                Some("custom_slug".to_string()),
+                "https://iapetus11.me".to_string(),
+                None,
+                &LinkSettings::default(),
+            )
+            .await;
+            assert_eq!(result.is_ok(), expected_created);
+        }
+
+        // The unique violation doesn't abort the surrounding transaction
+        assert!(get_link(&mut tx, "custom_slug").await.unwrap().is_some());
+        tx.commit().await.unwrap();
+    }
+
     #[sqlx::test]
     async fn test_get_link(mut db: PgPoolConn) {
         let (_, platform) = create_platform(&mut db, "wowza").await.unwrap();
@@ -168,6 +473,7 @@ mod tests {
             None,
             "https://minecraft.global".to_string(),
             None,
+            &LinkSettings::default(),
         )
         .await
         .unwrap();
@@ -194,6 +500,7 @@ mod tests {
             Some("link_a".to_string()),
             "https://www.kevinjosethomas.com/".to_string(),
             None,
+            &LinkSettings::default(),
         )
         .await
         .unwrap();
@@ -204,6 +511,7 @@ mod tests {
             Some("link_b".to_string()),
             "https://iapetus11.me/".to_string(),
             None,
+            &LinkSettings::default(),
         )
         .await
         .unwrap();
@@ -214,4 +522,365 @@ mod tests {
         assert_eq!(links[0].slug, link_b.slug);
         assert_eq!(links[1].slug, link_a.slug);
     }
+
+    #[sqlx::test]
+    async fn test_get_links_page(mut db: PgPoolConn) {
+        let (_, platform) = create_platform(&mut db, "wowza").await.unwrap();
+        let (_, other_platform) = create_platform(&mut db, "other").await.unwrap();
+
+        let mut links = vec![];
+        for i in 0..5 {
+            links.push(
+                create_link(
+                    &mut db,
+                    &platform.id,
+                    Some(format!("link_{i}")),
+                    format!("https://example.com/{i}"),
+                    Some(json!({"index": i, "even": i % 2 == 0})),
+                    &LinkSettings::default(),
+                )
+                .await
+                .unwrap(),
+            );
+        }
+        create_link(
+            &mut db,
+            &other_platform.id,
+            None,
+            "https://example.com/other".to_string(),
+            Some(json!({"even": true})),
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+
+        let first_page = get_links_page(&mut db, &platform.id, &LinksFilter::default(), None, 3)
+            .await
+            .unwrap();
+        assert_eq!(
+            first_page.iter().map(|l| &l.slug).collect::<Vec<_>>(),
+            vec!["link_4", "link_3", "link_2"]
+        );
+
+        let second_page = get_links_page(
+            &mut db,
+            &platform.id,
+            &LinksFilter::default(),
+            Some(&LinksPageCursor::from(first_page.last().unwrap())),
+            3,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            second_page.iter().map(|l| &l.slug).collect::<Vec<_>>(),
+            vec!["link_1", "link_0"]
+        );
+
+        let even_links = get_links_page(
+            &mut db,
+            &platform.id,
+            &LinksFilter {
+                metadata_contains: Some(json!({"even": true})),
+                ..Default::default()
+            },
+            None,
+            10,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            even_links.iter().map(|l| &l.slug).collect::<Vec<_>>(),
+            vec!["link_4", "link_2", "link_0"]
+        );
+
+        let url_links = get_links_page(
+            &mut db,
+            &platform.id,
+            &LinksFilter {
+                url_contains: Some("EXAMPLE.com/3".to_string()),
+                ..Default::default()
+            },
+            None,
+            10,
+        )
+        .await
+        .unwrap();
+        assert_eq!(url_links.len(), 1);
+        assert_eq!(url_links[0].slug, "link_3");
+
+        let ranged_links = get_links_page(
+            &mut db,
+            &platform.id,
+            &LinksFilter {
+                created_after: Some(links[0].created_at),
+                created_before: Some(links[4].created_at),
+                ..Default::default()
+            },
+            None,
+            10,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            ranged_links.iter().map(|l| &l.slug).collect::<Vec<_>>(),
+            vec!["link_3", "link_2", "link_1"]
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_update_link(mut db: PgPoolConn) {
+        let (_, platform) = create_platform(&mut db, "wowza").await.unwrap();
+
+        let link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://iapetus11.me/".to_string(),
+            Some(json!({"a": 1})),
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+
+        let updated_link = update_link(
+            &mut db,
+            &link.platform_id,
+            &link.slug,
+            &UpdateLinkData {
+                url: Some("https://minecraft.global/".to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(updated_link.slug, link.slug);
+        assert_eq!(updated_link.url, "https://minecraft.global/");
+        assert_eq!(updated_link.metadata, link.metadata);
+        assert_eq!(updated_link.created_at, link.created_at);
+
+        let updated_link = update_link(
+            &mut db,
+            &link.platform_id,
+            &link.slug,
+            &UpdateLinkData {
+                metadata: Some(serde_json::Value::Null),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(updated_link.url, "https://minecraft.global/");
+        assert_eq!(updated_link.metadata, None);
+    }
+
+    #[sqlx::test]
+    async fn test_update_link_settings(mut db: PgPoolConn) {
+        let (_, platform) = create_platform(&mut db, "wowza").await.unwrap();
+
+        let expires_at = Utc::now() + chrono::TimeDelta::days(1);
+        let link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://iapetus11.me/".to_string(),
+            None,
+            &LinkSettings {
+                expires_at: Some(expires_at),
+                max_visits: Some(10),
+                redirect_type: Some(RedirectType::PermanentRedirect),
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            link.expires_at.unwrap().timestamp_micros(),
+            expires_at.timestamp_micros()
+        );
+        assert_eq!(link.max_visits, Some(10));
+        assert_eq!(link.redirect_type, Some(RedirectType::PermanentRedirect));
+
+        let updated_link = update_link(
+            &mut db,
+            &link.platform_id,
+            &link.slug,
+            &UpdateLinkData {
+                expires_at: Some(None),
+                max_visits: Some(Some(3)),
+                redirect_type: Some(None),
+                disabled: Some(true),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(updated_link.expires_at, None);
+        assert_eq!(updated_link.max_visits, Some(3));
+        assert_eq!(updated_link.redirect_type, None);
+        assert!(updated_link.disabled);
+        assert_eq!(updated_link.url, link.url);
+    }
+
+    #[sqlx::test]
+    async fn test_claim_link_visit(mut db: PgPoolConn) {
+        let (_, platform) = create_platform(&mut db, "wowza").await.unwrap();
+
+        let unlimited_link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://iapetus11.me/".to_string(),
+            None,
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+        assert!(
+            !claim_link_visit(&mut db, &unlimited_link.slug)
+                .await
+                .unwrap()
+        );
+
+        let expired_link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://iapetus11.me/".to_string(),
+            None,
+            &LinkSettings {
+                expires_at: Some(Utc::now() - chrono::TimeDelta::seconds(1)),
+                max_visits: Some(5),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert!(!claim_link_visit(&mut db, &expired_link.slug).await.unwrap());
+
+        let limited_link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://iapetus11.me/".to_string(),
+            None,
+            &LinkSettings {
+                max_visits: Some(2),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert!(claim_link_visit(&mut db, &limited_link.slug).await.unwrap());
+        assert!(claim_link_visit(&mut db, &limited_link.slug).await.unwrap());
+        assert!(!claim_link_visit(&mut db, &limited_link.slug).await.unwrap());
+
+        let limited_link = get_link(&mut db, &limited_link.slug)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(limited_link.visit_count, 2);
+        assert_eq!(limited_link.remaining_visits(), Some(0));
+    }
+
+    #[sqlx::test]
+    async fn test_claim_link_visit_concurrently(db_pool: sqlx::PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (_, platform) = create_platform(&mut db, "wowza").await.unwrap();
+        let link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://iapetus11.me/".to_string(),
+            None,
+            &LinkSettings {
+                max_visits: Some(5),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let tasks = (0..20)
+            .map(|_| {
+                let db_pool = db_pool.clone();
+                let slug = link.slug.clone();
+
+                tokio::spawn(async move {
+                    let mut db = db_pool.acquire().await.unwrap();
+                    claim_link_visit(&mut db, &slug).await.unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut claimed_count = 0;
+        for task in tasks {
+            if task.await.unwrap() {
+                claimed_count += 1;
+            }
+        }
+
+        assert_eq!(claimed_count, 5);
+    }
+
+    #[sqlx::test]
+    async fn test_update_and_delete_link_of_other_platform(mut db: PgPoolConn) {
+        let (_, platform) = create_platform(&mut db, "Test").await.unwrap();
+        let (_, other_platform) = create_platform(&mut db, "Other").await.unwrap();
+
+        let link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://iapetus11.me".to_string(),
+            None,
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+
+        let updated_link = update_link(
+            &mut db,
+            &other_platform.id,
+            &link.slug,
+            &UpdateLinkData {
+                url: Some("https://example.com/".to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert!(updated_link.is_none());
+
+        let deleted_link = delete_link(&mut db, &other_platform.id, &link.slug)
+            .await
+            .unwrap();
+        assert!(deleted_link.is_none());
+
+        let link = get_link(&mut db, &link.slug).await.unwrap().unwrap();
+        assert_eq!(link.url, "https://iapetus11.me");
+    }
+
+    #[sqlx::test]
+    async fn test_update_nonexistent_link(mut db: PgPoolConn) {
+        let updated_link = update_link(
+            &mut db,
+            &Uuid::now_v7(),
+            "nope",
+            &UpdateLinkData {
+                url: Some("https://example.com/".to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        assert!(updated_link.is_none());
+    }
 }
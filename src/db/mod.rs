@@ -1,4 +1,10 @@
+pub mod dashboard_login_attempts;
+pub mod dashboard_login_challenges;
 pub mod dashboard_login_token;
+pub mod dashboard_recovery_codes;
+pub mod dashboard_users;
+pub mod link_stats;
+pub mod link_visit_rollups;
 pub mod link_visits;
 pub mod links;
 pub mod platforms;
@@ -1,14 +1,28 @@
+use chrono::NaiveDate;
 use serde::{Deserialize, Serialize};
 use sqlx::PgConnection;
 use uuid::Uuid;
 
-use crate::common::platform_auth::{PlatformApiKeyAndHash, generate_platform_api_key};
+use crate::{
+    common::platform_auth::{PlatformApiKeyAndHash, generate_platform_api_key},
+    db::links::RedirectType,
+};
 
 #[derive(Debug, Clone)]
 pub struct Platform {
     pub id: Uuid,
     pub name: String,
     pub api_key_hash: String,
+    /// Used for the platform's links which don't specify their own redirect type
+    pub default_redirect_type: RedirectType,
+    /// Where to redirect visitors of the platform's expired or disabled links
+    pub unavailable_fallback_url: Option<String>,
+    /// Rendered for visitors of the platform's expired or disabled links, if there's no fallback URL
+    pub unavailable_fallback_html: Option<String>,
+    /// Rate limit of the platform's API requests, None if unlimited
+    pub api_requests_per_minute: Option<i32>,
+    /// How many links the platform may create through the API each (UTC) month, None if unlimited
+    pub monthly_link_quota: Option<i64>,
 }
 
 /// Creates a Platform, returning the unhashed API key and an object holding the Platform's data
@@ -23,7 +37,14 @@ pub async fn create_platform(
 
     let platform = sqlx::query_as!(
         Platform,
-        "INSERT INTO platforms (id, name, api_key_hash) VALUES ($1, $2, $3) RETURNING id, name, api_key_hash;",
+        r#"
+            INSERT INTO platforms (id, name, api_key_hash)
+            VALUES ($1, $2, $3)
+            RETURNING
+                id, name, api_key_hash, default_redirect_type AS "default_redirect_type: RedirectType",
+                unavailable_fallback_url, unavailable_fallback_html, api_requests_per_minute,
+                monthly_link_quota;
+        "#,
         uuid::Uuid::now_v7(),
         name,
         api_key_hash,
@@ -37,7 +58,12 @@ pub async fn get_platform(db: &mut PgConnection, id: &Uuid) -> sqlx::Result<Opti
     sqlx::query_as!(
         Platform,
         r#"
-            SELECT id, name, api_key_hash FROM platforms WHERE id = $1;
+            SELECT
+                id, name, api_key_hash, default_redirect_type AS "default_redirect_type: RedirectType",
+                unavailable_fallback_url, unavailable_fallback_html, api_requests_per_minute,
+                monthly_link_quota
+            FROM platforms
+            WHERE id = $1;
         "#,
         id,
     )
@@ -53,7 +79,12 @@ pub async fn get_platform_by_name(
     sqlx::query_as!(
         Platform,
         r#"
-            SELECT id, name, api_key_hash FROM platforms WHERE UPPER(name) = UPPER($1)
+            SELECT
+                id, name, api_key_hash, default_redirect_type AS "default_redirect_type: RedirectType",
+                unavailable_fallback_url, unavailable_fallback_html, api_requests_per_minute,
+                monthly_link_quota
+            FROM platforms
+            WHERE UPPER(name) = UPPER($1)
         "#,
         name,
     )
@@ -66,7 +97,12 @@ pub async fn get_platforms(db: &mut PgConnection) -> sqlx::Result<Vec<Platform>>
     sqlx::query_as!(
         Platform,
         r#"
-            SELECT id, name, api_key_hash FROM platforms ORDER BY name;
+            SELECT
+                id, name, api_key_hash, default_redirect_type AS "default_redirect_type: RedirectType",
+                unavailable_fallback_url, unavailable_fallback_html, api_requests_per_minute,
+                monthly_link_quota
+            FROM platforms
+            ORDER BY name;
         "#,
     )
     .fetch_all(&mut *db)
@@ -79,6 +115,20 @@ pub struct UpdatePlatformData {
     pub name: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub api_key_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_redirect_type: Option<RedirectType>,
+    /// Set to `Some(None)` to remove the platform's fallback URL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unavailable_fallback_url: Option<Option<String>>,
+    /// Set to `Some(None)` to remove the platform's fallback HTML
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unavailable_fallback_html: Option<Option<String>>,
+    /// Set to `Some(None)` to remove the platform's rate limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_requests_per_minute: Option<Option<i32>>,
+    /// Set to `Some(None)` to remove the platform's quota
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monthly_link_quota: Option<Option<i64>>,
 }
 
 /// Updates a platform with the provided values, if fields are set as None then they are not updated.
@@ -100,9 +150,32 @@ pub async fn update_platform(
                 api_key_hash = CASE
                     WHEN $2 ? 'api_key_hash'
                     THEN ($2->>'api_key_hash')::VARCHAR
-                    ELSE api_key_hash END
+                    ELSE api_key_hash END,
+                default_redirect_type = CASE
+                    WHEN $2 ? 'default_redirect_type'
+                    THEN ($2->>'default_redirect_type')::redirect_type
+                    ELSE default_redirect_type END,
+                unavailable_fallback_url = CASE
+                    WHEN $2 ? 'unavailable_fallback_url'
+                    THEN ($2->>'unavailable_fallback_url')::VARCHAR
+                    ELSE unavailable_fallback_url END,
+                unavailable_fallback_html = CASE
+                    WHEN $2 ? 'unavailable_fallback_html'
+                    THEN ($2->>'unavailable_fallback_html')::VARCHAR
+                    ELSE unavailable_fallback_html END,
+                api_requests_per_minute = CASE
+                    WHEN $2 ? 'api_requests_per_minute'
+                    THEN ($2->>'api_requests_per_minute')::INTEGER
+                    ELSE api_requests_per_minute END,
+                monthly_link_quota = CASE
+                    WHEN $2 ? 'monthly_link_quota'
+                    THEN ($2->>'monthly_link_quota')::BIGINT
+                    ELSE monthly_link_quota END
             WHERE id = $1
-            RETURNING id, name, api_key_hash
+            RETURNING
+                id, name, api_key_hash, default_redirect_type AS "default_redirect_type: RedirectType",
+                unavailable_fallback_url, unavailable_fallback_html, api_requests_per_minute,
+                monthly_link_quota
         "#,
         id,
         serde_json::to_value(update_data).unwrap(),
@@ -116,7 +189,12 @@ pub async fn delete_platform(db: &mut PgConnection, id: &Uuid) -> sqlx::Result<O
     sqlx::query_as!(
         Platform,
         r#"
-            DELETE FROM platforms WHERE id = $1 RETURNING id, name, api_key_hash;
+            DELETE FROM platforms
+            WHERE id = $1
+            RETURNING
+                id, name, api_key_hash, default_redirect_type AS "default_redirect_type: RedirectType",
+                unavailable_fallback_url, unavailable_fallback_html, api_requests_per_minute,
+                monthly_link_quota;
         "#,
         id,
     )
@@ -124,6 +202,51 @@ pub async fn delete_platform(db: &mut PgConnection, id: &Uuid) -> sqlx::Result<O
     .await
 }
 
+/// Counts a link created by the platform in the (UTC) month starting on the specified day, unless
+/// the quota has already been reached. Returns whether the link may be created
+pub async fn claim_monthly_link_quota(
+    db: &mut PgConnection,
+    platform_id: &Uuid,
+    month: NaiveDate,
+    quota: Option<i64>,
+) -> sqlx::Result<bool> {
+    let links_created = sqlx::query_scalar!(
+        r#"
+            INSERT INTO platform_monthly_usage (platform_id, month, links_created)
+            VALUES ($1, $2, 1)
+            ON CONFLICT (platform_id, month) DO UPDATE
+            SET links_created = platform_monthly_usage.links_created + 1
+            WHERE $3::BIGINT IS NULL OR platform_monthly_usage.links_created < $3
+            RETURNING links_created
+        "#,
+        platform_id,
+        month,
+        quota,
+    )
+    .fetch_optional(&mut *db)
+    .await?;
+
+    Ok(links_created.is_some())
+}
+
+/// How many links the platform has created through the API in the (UTC) month starting on the
+/// specified day
+pub async fn get_monthly_links_created(
+    db: &mut PgConnection,
+    platform_id: &Uuid,
+    month: NaiveDate,
+) -> sqlx::Result<i64> {
+    let links_created = sqlx::query_scalar!(
+        "SELECT links_created FROM platform_monthly_usage WHERE platform_id = $1 AND month = $2",
+        platform_id,
+        month,
+    )
+    .fetch_optional(&mut *db)
+    .await?;
+
+    Ok(links_created.unwrap_or(0))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -135,6 +258,10 @@ mod tests {
 
         assert!(api_key.len() == 69);
         assert!(platform.name == "Some Platform");
+        assert_eq!(
+            platform.default_redirect_type,
+            RedirectType::TemporaryRedirect
+        );
 
         let platform = get_platform(&mut db, &platform.id).await.unwrap().unwrap();
         assert_eq!(platform.name, "Some Platform");
@@ -181,6 +308,11 @@ mod tests {
             &UpdatePlatformData {
                 name: Some("New Name".to_string()),
                 api_key_hash: Some("Not a real hash but new".to_string()),
+                default_redirect_type: Some(RedirectType::PermanentRedirect),
+                unavailable_fallback_url: Some(Some("https://example.com/".to_string())),
+                unavailable_fallback_html: Some(Some("<h1>Gone</h1>".to_string())),
+                api_requests_per_minute: Some(None),
+                monthly_link_quota: Some(Some(100)),
             },
         )
         .await
@@ -194,6 +326,28 @@ mod tests {
 
         assert_eq!(updated_platform.api_key_hash, "Not a real hash but new");
         assert_ne!(updated_platform.api_key_hash, platform.api_key_hash);
+
+        assert_eq!(
+            updated_platform.default_redirect_type,
+            RedirectType::PermanentRedirect
+        );
+        assert_ne!(
+            updated_platform.default_redirect_type,
+            platform.default_redirect_type
+        );
+
+        assert_eq!(
+            updated_platform.unavailable_fallback_url.as_deref(),
+            Some("https://example.com/")
+        );
+        assert_eq!(
+            updated_platform.unavailable_fallback_html.as_deref(),
+            Some("<h1>Gone</h1>")
+        );
+
+        assert_eq!(platform.api_requests_per_minute, Some(600));
+        assert_eq!(updated_platform.api_requests_per_minute, None);
+        assert_eq!(updated_platform.monthly_link_quota, Some(100));
     }
 
     #[sqlx::test]
@@ -204,6 +358,11 @@ mod tests {
             &UpdatePlatformData {
                 name: Some("New Name".to_string()),
                 api_key_hash: Some("BLAH".to_string()),
+                default_redirect_type: None,
+                unavailable_fallback_url: None,
+                unavailable_fallback_html: None,
+                api_requests_per_minute: None,
+                monthly_link_quota: None,
             },
         )
         .await
@@ -222,6 +381,11 @@ mod tests {
             &UpdatePlatformData {
                 name: None,
                 api_key_hash: None,
+                default_redirect_type: None,
+                unavailable_fallback_url: None,
+                unavailable_fallback_html: None,
+                api_requests_per_minute: None,
+                monthly_link_quota: None,
             },
         )
         .await
@@ -231,6 +395,56 @@ mod tests {
         assert_eq!(updated_platform.id, platform.id);
         assert_eq!(updated_platform.name, platform.name);
         assert_eq!(updated_platform.api_key_hash, platform.api_key_hash);
+        assert_eq!(
+            updated_platform.default_redirect_type,
+            platform.default_redirect_type
+        );
+        assert_eq!(
+            updated_platform.api_requests_per_minute,
+            platform.api_requests_per_minute
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_claim_monthly_link_quota(mut db: PgPoolConn) {
+        let (_, platform) = create_platform(&mut db, "Quota").await.unwrap();
+
+        let january = NaiveDate::from_ymd_opt(2025, 1, 1).unwrap();
+        let february = NaiveDate::from_ymd_opt(2025, 2, 1).unwrap();
+
+        for expected_claimed in [true, true, false] {
+            assert_eq!(
+                claim_monthly_link_quota(&mut db, &platform.id, january, Some(2))
+                    .await
+                    .unwrap(),
+                expected_claimed
+            );
+        }
+
+        // Each month has its own quota, and links can always be created without one
+        assert!(
+            claim_monthly_link_quota(&mut db, &platform.id, february, Some(2))
+                .await
+                .unwrap()
+        );
+        assert!(
+            claim_monthly_link_quota(&mut db, &platform.id, january, None)
+                .await
+                .unwrap()
+        );
+
+        assert_eq!(
+            get_monthly_links_created(&mut db, &platform.id, january)
+                .await
+                .unwrap(),
+            3
+        );
+        assert_eq!(
+            get_monthly_links_created(&mut db, &platform.id, february)
+                .await
+                .unwrap(),
+            1
+        );
     }
 
     #[sqlx::test]
@@ -1,4 +1,3 @@
-use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
 use poem::{
     EndpointExt, Server,
     listener::TcpListener,
@@ -10,11 +9,24 @@ use crate::{
     common::{
         argon2::{argon2_hash_key, setup_strong_argon2},
         cli::take_input,
+        dashboard_auth::{validate_dashboard_password, validate_dashboard_username},
+        geoip::GeoIp,
+        link_cache::LinkCache,
+        login_token_pruner::{prune_login_data, start_login_token_pruner},
+        rate_limit::ApiRateLimiter,
+        visit_pruner::{VisitRetentionSettings, prune_old_visits, start_visit_pruner},
+        visit_recorder::VisitRecorder,
+        visit_rollup::start_visit_rollup,
     },
     config::CONFIG,
-    db::platforms::create_platform,
+    db::{
+        dashboard_users::{
+            DashboardUserRole, create_dashboard_user, get_dashboard_user_by_username,
+        },
+        platforms::create_platform,
+    },
 };
-use std::{env, error::Error as StdError};
+use std::{env, error::Error as StdError, time::Duration};
 
 mod common;
 mod config;
@@ -23,21 +35,88 @@ mod routes;
 
 static DB_MIGRATOR: Migrator = sqlx::migrate!();
 
+/// Resolves once the process receives SIGINT or SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
 async fn run_app() -> Result<(), Box<dyn StdError>> {
     let db_pool = sqlx::postgres::PgPoolOptions::new()
         .max_connections(CONFIG.database_pool_size)
         .connect(&CONFIG.database_url)
         .await?;
 
+    let (visit_recorder, visit_recorder_handle) = VisitRecorder::start(
+        db_pool.clone(),
+        CONFIG.visit_queue_capacity,
+        CONFIG.visit_batch_size,
+        Duration::from_millis(CONFIG.visit_flush_interval_ms),
+    );
+
+    let link_cache = LinkCache::new(
+        CONFIG.link_cache_capacity,
+        Duration::from_secs(CONFIG.link_cache_ttl_seconds),
+        Duration::from_secs(CONFIG.link_cache_not_found_ttl_seconds),
+    );
+
+    let geoip = GeoIp::open(CONFIG.geoip_database_path.as_deref())?;
+
+    start_visit_rollup(
+        db_pool.clone(),
+        Duration::from_secs(CONFIG.visit_rollup_interval_seconds),
+    );
+
+    start_login_token_pruner(
+        db_pool.clone(),
+        Duration::from_secs(CONFIG.login_token_prune_interval_seconds),
+    );
+
+    if let Some(visit_retention) = CONFIG.visit_retention {
+        start_visit_pruner(
+            db_pool.clone(),
+            visit_retention,
+            Duration::from_secs(CONFIG.visit_prune_interval_seconds),
+        );
+    }
+
     let app = routes::routes()
         .with(Tracing)
         .with(NormalizePath::new(TrailingSlash::Always))
         .with(AddData::new(db_pool))
+        .with(AddData::new(visit_recorder))
+        .with(AddData::new(link_cache))
+        .with(AddData::new(geoip))
+        .with(AddData::new(ApiRateLimiter::default()))
         .with(CatchPanic::new());
 
-    Server::new(TcpListener::bind(CONFIG.host_address.clone()))
-        .run(app)
-        .await?;
+    let server_result = Server::new(TcpListener::bind(CONFIG.host_address.clone()))
+        .run_with_graceful_shutdown(app, shutdown_signal(), Some(Duration::from_secs(10)))
+        .await;
+
+    // Flush queued visits even if the server stopped because of an error
+    visit_recorder_handle.shutdown().await;
+
+    server_result?;
 
     Ok(())
 }
@@ -69,13 +148,77 @@ async fn run_create_platform() -> Result<(), Box<dyn StdError>> {
     Ok(())
 }
 
-fn run_hash_admin_password() -> Result<(), Box<dyn StdError>> {
+/// Prunes visits older than the configured retention window, or the specified number of days
+async fn run_prune_visits(retention_days: Option<String>) -> Result<(), Box<dyn StdError>> {
+    let settings = match retention_days {
+        Some(retention_days) => VisitRetentionSettings {
+            retention: chrono::TimeDelta::days(retention_days.parse()?),
+            ..CONFIG.visit_retention.unwrap_or(VisitRetentionSettings {
+                retention: chrono::TimeDelta::zero(),
+                batch_size: 10_000,
+                rollup: true,
+            })
+        },
+        None => CONFIG.visit_retention.ok_or(
+            "Set VISIT_RETENTION_DAYS in your env or .env file, or pass the number of days to keep",
+        )?,
+    };
+
+    let mut db = sqlx::postgres::PgConnection::connect(&CONFIG.database_url).await?;
+
+    let pruned = prune_old_visits(&mut db, &settings).await?;
+
+    println!("Pruned {pruned} link visits");
+
+    Ok(())
+}
+
+async fn run_prune_login_tokens() -> Result<(), Box<dyn StdError>> {
+    let mut db = sqlx::postgres::PgConnection::connect(&CONFIG.database_url).await?;
+
+    let pruned = prune_login_data(&mut db).await?;
+
+    println!("Pruned {pruned} expired dashboard login tokens, challenges and attempts");
+
+    Ok(())
+}
+
+async fn run_create_dashboard_user() -> Result<(), Box<dyn StdError>> {
+    println!("Enter dashboard user details, press enter to advance:");
+    let username = take_input("Username: ")?.trim().to_string();
     let password = take_input("Password: ")?;
+    let role = take_input("Role (owner, editor, viewer) [owner]: ")?;
+
+    validate_dashboard_username(&username).map_err(|e| e.to_string())?;
+    validate_dashboard_password(&password).map_err(|e| e.to_string())?;
+
+    let role = match role.trim() {
+        "" => DashboardUserRole::Owner,
+        role => role
+            .parse::<DashboardUserRole>()
+            .map_err(|_| format!("Unknown role {role}"))?,
+    };
+
+    let mut db = sqlx::postgres::PgConnection::connect(&CONFIG.database_url).await?;
+
+    if get_dashboard_user_by_username(&mut db, &username)
+        .await?
+        .is_some()
+    {
+        return Err(format!("A dashboard user named {username} already exists").into());
+    }
 
     let argon2 = setup_strong_argon2();
-    let hashed = BASE64_URL_SAFE_NO_PAD.encode(argon2_hash_key(&argon2, &password));
+    let user = create_dashboard_user(
+        &mut db,
+        &username,
+        &argon2_hash_key(&argon2, &password),
+        role,
+    )
+    .await?;
 
-    println!("Password Hash: {hashed}");
+    println!("Dashboard user successfully created!");
+    println!("ID: {}", user.id);
 
     Ok(())
 }
@@ -91,10 +234,16 @@ async fn main() -> Result<(), Box<dyn StdError>> {
         "app" => run_app().await.unwrap(),
         "migrate_db" => run_migrate_db().await.unwrap(),
         "create_platform" => run_create_platform().await.unwrap(),
-        "hash_admin_password" => run_hash_admin_password().unwrap(),
-        "" => panic!("You must type a command, one of: api, create_platform, hash_admin_password"),
+        "prune_visits" => run_prune_visits(args.next()).await.unwrap(),
+        "create_dashboard_user" => run_create_dashboard_user().await.unwrap(),
+        "prune_login_tokens" => run_prune_login_tokens().await.unwrap(),
+        "" => panic!(
+            "You must type a command, one of: app, migrate_db, create_platform, prune_visits, create_dashboard_user, prune_login_tokens"
+        ),
         unknown_command => {
-            panic!("Unknown command {unknown_command}, you must type one of: api, create_platform")
+            panic!(
+                "Unknown command {unknown_command}, you must type one of: app, migrate_db, create_platform, prune_visits, create_dashboard_user, prune_login_tokens"
+            )
         }
     };
 
@@ -1,17 +1,40 @@
-use chrono::{DateTime, Utc};
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use chrono::{DateTime, Datelike, Months, NaiveTime, Utc};
 use poem::{
     Body, Route,
-    http::StatusCode,
-    web::{Data, Json},
+    http::{StatusCode, header},
+    web::{Data, Json, Path, Query},
 };
+use serde::{Deserialize, Deserializer};
+use serde_valid::Validate;
+use sqlx::{Connection, PgConnection};
 
 use crate::{
-    common::platform_auth::AuthedPlatform,
-    db::links::{Link, create_link, get_link},
+    common::{
+        link_cache::LinkCache,
+        link_validation::{validate_link_slug, validate_link_url},
+        platform_auth::AuthedPlatform,
+        validation::{validate_if_present, validate_to_poem_error},
+    },
+    db::{
+        links::{
+            Link, LinkSettings, LinksFilter, LinksPageCursor, RedirectType, UpdateLinkData,
+            create_link, delete_link, get_link, get_links_page, update_link,
+        },
+        platforms::{Platform, claim_monthly_link_quota},
+    },
 };
 
 pub fn routes() -> Route {
-    Route::new().at("", poem::post(post_create_link))
+    Route::new()
+        .at("", poem::get(get_list_links).post(post_create_link))
+        .at(
+            "/:slug/",
+            poem::get(get_link_details)
+                .patch(patch_update_link)
+                .delete(delete_existing_link),
+        )
+        .at("/:slug/stats/", poem::get(super::stats::get_link_stats))
 }
 
 #[derive(Debug, thiserror::Error, serde::Serialize)]
@@ -20,14 +43,26 @@ pub fn routes() -> Route {
 enum PostCreateLinkError {
     #[error("slug is already in use for existing link")]
     SlugAlreadyUsed(LinkDetailsView),
+    #[error("slug is already in use by another platform")]
+    SlugUnavailable,
 }
 
-#[derive(serde::Deserialize)]
-#[cfg_attr(test, derive(serde::Serialize))]
+#[derive(Validate, serde::Deserialize)]
+#[cfg_attr(test, derive(serde::Serialize, Default))]
 struct PostCreateLinkRequest {
+    #[validate(custom = |slug| validate_if_present(slug, validate_link_slug))]
     slug: Option<String>,
+    #[validate(custom = validate_link_url)]
     url: String,
     metadata: Option<serde_json::Value>,
+    #[serde(default)]
+    expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    #[validate(minimum = 1)]
+    max_visits: Option<i64>,
+    /// If not specified, the platform's default redirect type is used
+    #[serde(default)]
+    redirect_type: Option<RedirectType>,
 }
 
 #[derive(Debug, serde::Serialize)]
@@ -37,54 +72,337 @@ struct LinkDetailsView {
     url: String,
     metadata: Option<serde_json::Value>,
     created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+    max_visits: Option<i64>,
+    remaining_visits: Option<i64>,
+    /// If null, the platform's default redirect type is used
+    redirect_type: Option<RedirectType>,
+    disabled: bool,
 }
 
 impl From<Link> for LinkDetailsView {
     fn from(value: Link) -> Self {
         LinkDetailsView {
+            remaining_visits: value.remaining_visits(),
             slug: value.slug,
             url: value.url,
             metadata: value.metadata,
             created_at: value.created_at,
+            expires_at: value.expires_at,
+            max_visits: value.max_visits,
+            redirect_type: value.redirect_type,
+            disabled: value.disabled,
         }
     }
 }
 
+/// Retrieves a link by its slug, returning a NOT_FOUND error if the link doesn't exist or if it
+/// belongs to a platform other than the specified one
+pub(super) async fn get_platform_link(
+    db: &mut PgConnection,
+    platform: &Platform,
+    slug: &str,
+) -> poem::Result<Link> {
+    match get_link(&mut *db, slug).await.unwrap() {
+        Some(link) if link.platform_id == platform.id => Ok(link),
+        _ => Err(poem::Error::from_string(
+            "link not found",
+            StatusCode::NOT_FOUND,
+        )),
+    }
+}
+
 #[poem::handler]
 pub async fn post_create_link(
     db: Data<&sqlx::PgPool>,
+    link_cache: Data<&LinkCache>,
     Json(create_request): Json<PostCreateLinkRequest>,
     AuthedPlatform(platform): AuthedPlatform,
 ) -> poem::Result<Json<LinkDetailsView>> {
+    let create_request = validate_to_poem_error(create_request)?;
+
     let mut db = db.acquire().await.unwrap();
 
     if let Some(custom_slug) = &create_request.slug {
         let link_for_slug = get_link(&mut db, custom_slug.as_str()).await.unwrap();
 
         if let Some(link_for_slug) = link_for_slug {
+            let error = if link_for_slug.platform_id == platform.id {
+                PostCreateLinkError::SlugAlreadyUsed(LinkDetailsView::from(link_for_slug))
+            } else {
+                PostCreateLinkError::SlugUnavailable
+            };
+
             return Err(poem::Error::from_response(
                 poem::Response::builder()
                     .status(StatusCode::BAD_REQUEST)
-                    .body(
-                        Body::from_json(PostCreateLinkError::SlugAlreadyUsed(
-                            LinkDetailsView::from(link_for_slug),
-                        ))
-                        .unwrap(),
-                    ),
+                    .body(Body::from_json(error).unwrap()),
             ));
         }
     }
 
-    let link = create_link(
-        &mut db,
+    let now = Utc::now();
+    let month = now.date_naive().with_day(1).unwrap();
+
+    let mut tx = db.begin().await.unwrap();
+
+    if !claim_monthly_link_quota(&mut tx, &platform.id, month, platform.monthly_link_quota)
+        .await
+        .unwrap()
+    {
+        let next_month = month.checked_add_months(Months::new(1)).unwrap();
+        let retry_after = next_month.and_time(NaiveTime::MIN).and_utc() - now;
+
+        return Err(poem::Error::from_response(
+            poem::Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .header(header::RETRY_AFTER, retry_after.num_seconds().max(1))
+                .body("monthly link quota exceeded"),
+        ));
+    }
+
+    let link = match create_link(
+        &mut tx,
         &platform.id,
         create_request.slug,
         create_request.url,
         create_request.metadata,
+        &LinkSettings {
+            expires_at: create_request.expires_at,
+            max_visits: create_request.max_visits,
+            redirect_type: create_request.redirect_type,
+        },
+    )
+    .await
+    {
+        // The custom slug was taken after it was checked above, dropping the transaction also
+        // releases the claimed quota
+        Err(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
+            return Err(poem::Error::from_response(
+                poem::Response::builder()
+                    .status(StatusCode::CONFLICT)
+                    .body(Body::from_json(PostCreateLinkError::SlugUnavailable).unwrap()),
+            ));
+        }
+        result => result.unwrap(),
+    };
+
+    tx.commit().await.unwrap();
+
+    link_cache.invalidate(&link.slug);
+
+    Ok(Json(LinkDetailsView::from(link)))
+}
+
+#[derive(Validate, Deserialize)]
+#[cfg_attr(test, derive(serde::Serialize, Default))]
+struct GetListLinksQueryParams {
+    #[validate(minimum = 1)]
+    #[validate(maximum = 100)]
+    limit: Option<i64>,
+    /// Opaque cursor from the `next_cursor` of a previous page
+    cursor: Option<String>,
+    url_contains: Option<String>,
+    created_before: Option<DateTime<Utc>>,
+    created_after: Option<DateTime<Utc>>,
+    /// JSON which the link's metadata must contain, e.g. `{"something_id": 42}`
+    metadata: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[cfg_attr(test, derive(serde::Deserialize))]
+struct LinksPageView {
+    links: Vec<LinkDetailsView>,
+    next_cursor: Option<String>,
+}
+
+const DEFAULT_LINKS_PAGE_LIMIT: i64 = 50;
+
+fn encode_links_page_cursor(cursor: &LinksPageCursor) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(serde_json::to_vec(cursor).unwrap())
+}
+
+fn decode_links_page_cursor(cursor: &str) -> Option<LinksPageCursor> {
+    let cursor_json = BASE64_URL_SAFE_NO_PAD.decode(cursor).ok()?;
+    serde_json::from_slice(&cursor_json).ok()
+}
+
+#[poem::handler]
+pub async fn get_list_links(
+    db: Data<&sqlx::PgPool>,
+    Query(query_params): Query<GetListLinksQueryParams>,
+    AuthedPlatform(platform): AuthedPlatform,
+) -> poem::Result<Json<LinksPageView>> {
+    let GetListLinksQueryParams {
+        limit,
+        cursor,
+        url_contains,
+        created_before,
+        created_after,
+        metadata,
+    } = validate_to_poem_error(query_params)?;
+
+    let cursor =
+        match cursor {
+            None => None,
+            Some(cursor) => Some(decode_links_page_cursor(&cursor).ok_or_else(|| {
+                poem::Error::from_string("invalid cursor", StatusCode::BAD_REQUEST)
+            })?),
+        };
+
+    let metadata_contains = match metadata {
+        None => None,
+        Some(metadata) => Some(serde_json::from_str(&metadata).map_err(|_| {
+            poem::Error::from_string("metadata must be valid JSON", StatusCode::BAD_REQUEST)
+        })?),
+    };
+
+    let limit = limit.unwrap_or(DEFAULT_LINKS_PAGE_LIMIT);
+
+    let mut db = db.acquire().await.unwrap();
+
+    // Fetch an extra link to find out whether there's another page after this one
+    let mut links = get_links_page(
+        &mut db,
+        &platform.id,
+        &LinksFilter {
+            url_contains,
+            created_before,
+            created_after,
+            metadata_contains,
+        },
+        cursor.as_ref(),
+        limit + 1,
     )
     .await
     .unwrap();
 
+    let next_cursor = if links.len() as i64 > limit {
+        links.truncate(limit as usize);
+        links
+            .last()
+            .map(|link| encode_links_page_cursor(&LinksPageCursor::from(link)))
+    } else {
+        None
+    };
+
+    Ok(Json(LinksPageView {
+        links: links.into_iter().map(LinkDetailsView::from).collect(),
+        next_cursor,
+    }))
+}
+
+#[poem::handler]
+pub async fn get_link_details(
+    db: Data<&sqlx::PgPool>,
+    Path((slug,)): Path<(String,)>,
+    AuthedPlatform(platform): AuthedPlatform,
+) -> poem::Result<Json<LinkDetailsView>> {
+    let mut db = db.acquire().await.unwrap();
+
+    let link = get_platform_link(&mut db, &platform, &slug).await?;
+
+    Ok(Json(LinkDetailsView::from(link)))
+}
+
+/// Deserializes a present value (including null) as Some, so that missing fields can be told apart
+/// from fields explicitly set to null
+fn deserialize_present<'de, T: Deserialize<'de>, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<T>, D::Error> {
+    T::deserialize(deserializer).map(Some)
+}
+
+#[derive(Validate, serde::Deserialize)]
+#[cfg_attr(test, derive(serde::Serialize, Default))]
+struct PatchUpdateLinkRequest {
+    #[serde(default)]
+    #[cfg_attr(test, serde(skip_serializing_if = "Option::is_none"))]
+    #[validate(custom = |url| validate_if_present(url, validate_link_url))]
+    url: Option<String>,
+    /// Explicitly setting metadata to null clears it
+    #[serde(default, deserialize_with = "deserialize_present")]
+    #[cfg_attr(test, serde(skip_serializing_if = "Option::is_none"))]
+    metadata: Option<serde_json::Value>,
+    /// Explicitly setting expires_at to null removes the link's expiration
+    #[serde(default, deserialize_with = "deserialize_present")]
+    #[cfg_attr(test, serde(skip_serializing_if = "Option::is_none"))]
+    expires_at: Option<Option<DateTime<Utc>>>,
+    /// Explicitly setting max_visits to null removes the link's visit limit
+    #[serde(default, deserialize_with = "deserialize_present")]
+    #[cfg_attr(test, serde(skip_serializing_if = "Option::is_none"))]
+    #[validate(custom = validate_max_visits_update)]
+    max_visits: Option<Option<i64>>,
+    /// Explicitly setting redirect_type to null makes the link use the platform's default
+    #[serde(default, deserialize_with = "deserialize_present")]
+    #[cfg_attr(test, serde(skip_serializing_if = "Option::is_none"))]
+    redirect_type: Option<Option<RedirectType>>,
+    /// Disabled links can't be visited until they're enabled again
+    #[cfg_attr(test, serde(skip_serializing_if = "Option::is_none"))]
+    disabled: Option<bool>,
+}
+
+fn validate_max_visits_update(
+    max_visits: &Option<Option<i64>>,
+) -> Result<(), serde_valid::validation::Error> {
+    match max_visits {
+        Some(Some(max_visits)) if *max_visits < 1 => Err(serde_valid::validation::Error::Custom(
+            "max_visits must be at least 1".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[poem::handler]
+pub async fn patch_update_link(
+    db: Data<&sqlx::PgPool>,
+    link_cache: Data<&LinkCache>,
+    Path((slug,)): Path<(String,)>,
+    Json(update_request): Json<PatchUpdateLinkRequest>,
+    AuthedPlatform(platform): AuthedPlatform,
+) -> poem::Result<Json<LinkDetailsView>> {
+    let update_request = validate_to_poem_error(update_request)?;
+
+    let mut db = db.acquire().await.unwrap();
+
+    let link = update_link(
+        &mut db,
+        &platform.id,
+        &slug,
+        &UpdateLinkData {
+            url: update_request.url,
+            metadata: update_request.metadata,
+            expires_at: update_request.expires_at,
+            max_visits: update_request.max_visits,
+            redirect_type: update_request.redirect_type,
+            disabled: update_request.disabled,
+        },
+    )
+    .await
+    .unwrap()
+    .ok_or_else(|| poem::Error::from_string("link not found", StatusCode::NOT_FOUND))?;
+
+    link_cache.invalidate(&link.slug);
+
+    Ok(Json(LinkDetailsView::from(link)))
+}
+
+#[poem::handler]
+pub async fn delete_existing_link(
+    db: Data<&sqlx::PgPool>,
+    link_cache: Data<&LinkCache>,
+    Path((slug,)): Path<(String,)>,
+    AuthedPlatform(platform): AuthedPlatform,
+) -> poem::Result<Json<LinkDetailsView>> {
+    let mut db = db.acquire().await.unwrap();
+
+    let link = delete_link(&mut db, &platform.id, &slug)
+        .await
+        .unwrap()
+        .ok_or_else(|| poem::Error::from_string("link not found", StatusCode::NOT_FOUND))?;
+
+    link_cache.invalidate(&link.slug);
+
     Ok(Json(LinkDetailsView::from(link)))
 }
 
@@ -98,7 +416,10 @@ mod tests {
 
     use crate::{
         common::testing::app::{api_test_client, platform_auth_header},
-        db::platforms::create_platform,
+        db::{
+            links::get_links,
+            platforms::{UpdatePlatformData, create_platform, update_platform},
+        },
     };
 
     #[sqlx::test]
@@ -117,6 +438,7 @@ mod tests {
                 slug: Some("corn".to_string()),
                 url: "https://cornhub.website/".to_string(),
                 metadata: Some(json!({"something_id": 4206669})),
+                ..Default::default()
             })
             .send()
             .await;
@@ -150,16 +472,14 @@ mod tests {
                 slug: None,
                 url: "https://iapetus11.me/".to_string(),
                 metadata: Some(json!({"something_id": 4206669})),
+                ..Default::default()
             })
             .send()
             .await;
 
         response.assert_status_is_ok();
 
-        let link = sqlx::query_as!(Link, "SELECT * FROM links")
-            .fetch_one(&mut *db)
-            .await
-            .unwrap();
+        let link = get_links(&mut db, &platform.id).await.unwrap().remove(0);
 
         response
             .assert_json(LinkDetailsView::from(link.clone()))
@@ -170,6 +490,34 @@ mod tests {
         assert_eq!(link.url, "https://iapetus11.me/");
     }
 
+    #[sqlx::test]
+    async fn test_post_create_link_with_redirect_type(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (api_key, platform) = create_platform(&mut db, "what are thooooooooseeee")
+            .await
+            .unwrap();
+
+        let api = api_test_client(db_pool);
+        let response = api
+            .post("/admin/api/links/")
+            .typed_header(platform_auth_header(&platform.id, &api_key))
+            .body_json(&json!({
+                "slug": "seo",
+                "url": "https://iapetus11.me/",
+                "redirect_type": "permanent_redirect",
+            }))
+            .send()
+            .await;
+
+        response.assert_status_is_ok();
+
+        let link = get_link(&mut db, "seo").await.unwrap().unwrap();
+        assert_eq!(link.redirect_type, Some(RedirectType::PermanentRedirect));
+
+        response.assert_json(LinkDetailsView::from(link)).await;
+    }
+
     #[sqlx::test]
     async fn test_post_create_link_but_slug_already_used(db_pool: PgPool) {
         let mut db = db_pool.acquire().await.unwrap();
@@ -182,6 +530,7 @@ mod tests {
             Some("duplicate".to_string()),
             "https://example.com/".to_string(),
             None,
+            &LinkSettings::default(),
         )
         .await
         .unwrap();
@@ -194,6 +543,7 @@ mod tests {
                 slug: Some(link.slug.clone()),
                 url: "https://villagerbot.com/".to_string(),
                 metadata: None,
+                ..Default::default()
             })
             .send()
             .await;
@@ -212,4 +562,649 @@ mod tests {
         assert_eq!(link.url, link_after_request.url);
         assert_eq!(link.created_at, link_after_request.created_at);
     }
+
+    #[sqlx::test]
+    async fn test_post_create_link_but_slug_used_by_other_platform(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (api_key, platform) = create_platform(&mut db, "testy mctestface").await.unwrap();
+        let (_, other_platform) = create_platform(&mut db, "other").await.unwrap();
+
+        let link = create_link(
+            &mut db,
+            &other_platform.id,
+            Some("taken".to_string()),
+            "https://example.com/".to_string(),
+            None,
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+
+        let api = api_test_client(db_pool);
+        let response = api
+            .post("/admin/api/links/")
+            .typed_header(platform_auth_header(&platform.id, &api_key))
+            .body_json(&PostCreateLinkRequest {
+                slug: Some(link.slug.clone()),
+                url: "https://villagerbot.com/".to_string(),
+                metadata: None,
+                ..Default::default()
+            })
+            .send()
+            .await;
+
+        response.assert_status(StatusCode::BAD_REQUEST);
+        response
+            .assert_json(PostCreateLinkError::SlugUnavailable)
+            .await;
+    }
+
+    #[sqlx::test]
+    async fn test_post_create_link_invalid(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (api_key, platform) = create_platform(&mut db, "testy mctestface").await.unwrap();
+
+        let api = api_test_client(db_pool);
+
+        for (request, invalid_field) in [
+            (
+                PostCreateLinkRequest {
+                    slug: Some("admin".to_string()),
+                    url: "https://villagerbot.com/".to_string(),
+                    metadata: None,
+                    ..Default::default()
+                },
+                "slug",
+            ),
+            (
+                PostCreateLinkRequest {
+                    slug: Some("not/a/slug".to_string()),
+                    url: "https://villagerbot.com/".to_string(),
+                    metadata: None,
+                    ..Default::default()
+                },
+                "slug",
+            ),
+            (
+                PostCreateLinkRequest {
+                    slug: None,
+                    url: "javascript:alert(1)".to_string(),
+                    metadata: None,
+                    ..Default::default()
+                },
+                "url",
+            ),
+        ] {
+            let response = api
+                .post("/admin/api/links/")
+                .typed_header(platform_auth_header(&platform.id, &api_key))
+                .body_json(&request)
+                .send()
+                .await;
+
+            response.assert_status(StatusCode::BAD_REQUEST);
+
+            let json = response.json().await;
+            let errors = json.value().object();
+            errors.get("errors").array().assert_is_empty();
+            errors
+                .get("properties")
+                .object()
+                .get(invalid_field)
+                .object()
+                .get("errors")
+                .array()
+                .assert_len(1);
+        }
+
+        assert_eq!(
+            sqlx::query!("SELECT COUNT(*) FROM links")
+                .fetch_one(&mut *db)
+                .await
+                .unwrap()
+                .count,
+            Some(0)
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_post_create_link_but_monthly_quota_reached(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (api_key, platform) = create_platform(&mut db, "Quota").await.unwrap();
+        update_platform(
+            &mut db,
+            &platform.id,
+            &UpdatePlatformData {
+                monthly_link_quota: Some(Some(1)),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let api = api_test_client(db_pool);
+
+        for expected_status in [StatusCode::OK, StatusCode::TOO_MANY_REQUESTS] {
+            let response = api
+                .post("/admin/api/links/")
+                .typed_header(platform_auth_header(&platform.id, &api_key))
+                .body_json(&PostCreateLinkRequest {
+                    url: "https://villagerbot.com/".to_string(),
+                    ..Default::default()
+                })
+                .send()
+                .await;
+
+            response.assert_status(expected_status);
+
+            if expected_status == StatusCode::TOO_MANY_REQUESTS {
+                response.assert_header_exist("Retry-After");
+                response.assert_text("monthly link quota exceeded").await;
+            }
+        }
+
+        assert_eq!(get_links(&mut db, &platform.id).await.unwrap().len(), 1);
+    }
+
+    #[sqlx::test]
+    async fn test_requests_rate_limited(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (api_key, platform) = create_platform(&mut db, "Rate Limit").await.unwrap();
+        update_platform(
+            &mut db,
+            &platform.id,
+            &UpdatePlatformData {
+                api_requests_per_minute: Some(Some(2)),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let api = api_test_client(db_pool);
+
+        for remaining in ["1", "0"] {
+            let response = api
+                .get("/admin/api/links/")
+                .typed_header(platform_auth_header(&platform.id, &api_key))
+                .send()
+                .await;
+
+            response.assert_status_is_ok();
+            response.assert_header("X-RateLimit-Limit", "2");
+            response.assert_header("X-RateLimit-Remaining", remaining);
+            response.assert_header_exist("X-RateLimit-Reset");
+        }
+
+        let response = api
+            .get("/admin/api/links/")
+            .typed_header(platform_auth_header(&platform.id, &api_key))
+            .send()
+            .await;
+
+        response.assert_status(StatusCode::TOO_MANY_REQUESTS);
+        response.assert_header("X-RateLimit-Remaining", "0");
+        response.assert_header_exist("Retry-After");
+    }
+
+    #[sqlx::test]
+    async fn test_invalid_api_key_requests_rate_limited(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (api_key, platform) = create_platform(&mut db, "Rate Limit").await.unwrap();
+        update_platform(
+            &mut db,
+            &platform.id,
+            &UpdatePlatformData {
+                api_requests_per_minute: Some(Some(2)),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let api = api_test_client(db_pool);
+
+        let list_links = async |api_key: &str| {
+            api.get("/admin/api/links/")
+                .typed_header(platform_auth_header(&platform.id, api_key))
+                .send()
+                .await
+        };
+
+        for _ in 0..2 {
+            list_links("wrong")
+                .await
+                .assert_status(StatusCode::UNAUTHORIZED);
+        }
+
+        list_links("wrong")
+            .await
+            .assert_status(StatusCode::TOO_MANY_REQUESTS);
+
+        // The right key is rejected as well until the bucket refills, without being checked
+        list_links(&api_key)
+            .await
+            .assert_status(StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[sqlx::test]
+    async fn test_get_list_links_paginated(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (api_key, platform) = create_platform(&mut db, "Villager Bot").await.unwrap();
+        let (_, other_platform) = create_platform(&mut db, "Not Villager Bot").await.unwrap();
+
+        let mut links = vec![];
+        for i in 0..5 {
+            links.push(
+                create_link(
+                    &mut db,
+                    &platform.id,
+                    None,
+                    format!("https://villagerbot.com/{i}"),
+                    None,
+                    &LinkSettings::default(),
+                )
+                .await
+                .unwrap(),
+            );
+        }
+        create_link(
+            &mut db,
+            &other_platform.id,
+            None,
+            "https://example.com/".to_string(),
+            None,
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+        links.reverse();
+
+        let api = api_test_client(db_pool);
+
+        let mut cursor = None;
+        let mut retrieved_slugs = vec![];
+        for expected_page_len in [2, 2, 1] {
+            let mut request = api
+                .get("/admin/api/links/")
+                .typed_header(platform_auth_header(&platform.id, &api_key))
+                .query("limit", &2);
+            if let Some(cursor) = &cursor {
+                request = request.query("cursor", cursor);
+            }
+            let response = request.send().await;
+
+            response.assert_status_is_ok();
+
+            let page = response.json().await.value().deserialize::<LinksPageView>();
+            assert_eq!(page.links.len(), expected_page_len);
+
+            retrieved_slugs.extend(page.links.into_iter().map(|l| l.slug));
+            cursor = page.next_cursor;
+        }
+
+        assert!(cursor.is_none());
+        assert_eq!(
+            retrieved_slugs,
+            links.into_iter().map(|l| l.slug).collect::<Vec<_>>()
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_get_list_links_filtered(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (api_key, platform) = create_platform(&mut db, "Villager Bot").await.unwrap();
+
+        let link_a = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://villagerbot.com/".to_string(),
+            Some(json!({"something_id": 42, "other": "value"})),
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+        create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://villagerbot.com/".to_string(),
+            Some(json!({"something_id": 43})),
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+        let link_c = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://iapetus11.me/".to_string(),
+            None,
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+
+        let api = api_test_client(db_pool);
+
+        let response = api
+            .get("/admin/api/links/")
+            .typed_header(platform_auth_header(&platform.id, &api_key))
+            .query("metadata", &json!({"something_id": 42}).to_string())
+            .send()
+            .await;
+        response.assert_status_is_ok();
+        response
+            .assert_json(LinksPageView {
+                links: vec![LinkDetailsView::from(link_a)],
+                next_cursor: None,
+            })
+            .await;
+
+        let response = api
+            .get("/admin/api/links/")
+            .typed_header(platform_auth_header(&platform.id, &api_key))
+            .query("url_contains", &"iapetus")
+            .send()
+            .await;
+        response.assert_status_is_ok();
+        response
+            .assert_json(LinksPageView {
+                links: vec![LinkDetailsView::from(link_c)],
+                next_cursor: None,
+            })
+            .await;
+    }
+
+    #[sqlx::test]
+    async fn test_get_list_links_invalid_params(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (api_key, platform) = create_platform(&mut db, "Villager Bot").await.unwrap();
+
+        let api = api_test_client(db_pool);
+
+        for (key, value) in [
+            ("limit", "0"),
+            ("limit", "101"),
+            ("cursor", "not a cursor"),
+            ("metadata", "{not json"),
+        ] {
+            let response = api
+                .get("/admin/api/links/")
+                .typed_header(platform_auth_header(&platform.id, &api_key))
+                .query(key, &value)
+                .send()
+                .await;
+
+            response.assert_status(StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[sqlx::test]
+    async fn test_get_link_details(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (api_key, platform) = create_platform(&mut db, "Villager Bot").await.unwrap();
+
+        let link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://villagerbot.com/".to_string(),
+            Some(json!({"something_id": 42})),
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+
+        let api = api_test_client(db_pool);
+        let response = api
+            .get(format!("/admin/api/links/{}/", link.slug))
+            .typed_header(platform_auth_header(&platform.id, &api_key))
+            .send()
+            .await;
+
+        response.assert_status_is_ok();
+        response.assert_json(LinkDetailsView::from(link)).await;
+    }
+
+    #[sqlx::test]
+    async fn test_get_link_details_not_found(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (api_key, platform) = create_platform(&mut db, "Villager Bot").await.unwrap();
+        let (_, other_platform) = create_platform(&mut db, "Not Villager Bot").await.unwrap();
+
+        let other_link = create_link(
+            &mut db,
+            &other_platform.id,
+            None,
+            "https://example.com/".to_string(),
+            None,
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+
+        let api = api_test_client(db_pool);
+
+        for slug in ["nonexistent", other_link.slug.as_str()] {
+            let response = api
+                .get(format!("/admin/api/links/{slug}/"))
+                .typed_header(platform_auth_header(&platform.id, &api_key))
+                .send()
+                .await;
+
+            response.assert_status(StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[sqlx::test]
+    async fn test_patch_update_link(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (api_key, platform) = create_platform(&mut db, "Villager Bot").await.unwrap();
+
+        let link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://villagerbot.com/".to_string(),
+            Some(json!({"something_id": 42})),
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+
+        let api = api_test_client(db_pool);
+        let response = api
+            .patch(format!("/admin/api/links/{}/", link.slug))
+            .typed_header(platform_auth_header(&platform.id, &api_key))
+            .body_json(&PatchUpdateLinkRequest {
+                url: Some("https://iapetus11.me/".to_string()),
+                metadata: None,
+                ..Default::default()
+            })
+            .send()
+            .await;
+
+        response.assert_status_is_ok();
+
+        let updated_link = get_link(&mut db, &link.slug).await.unwrap().unwrap();
+        response
+            .assert_json(LinkDetailsView::from(updated_link.clone()))
+            .await;
+
+        assert_eq!(updated_link.url, "https://iapetus11.me/");
+        assert_eq!(updated_link.metadata, link.metadata);
+
+        let response = api
+            .patch(format!("/admin/api/links/{}/", link.slug))
+            .typed_header(platform_auth_header(&platform.id, &api_key))
+            .body_json(&json!({"metadata": null}))
+            .send()
+            .await;
+
+        response.assert_status_is_ok();
+
+        let updated_link = get_link(&mut db, &link.slug).await.unwrap().unwrap();
+        assert_eq!(updated_link.url, "https://iapetus11.me/");
+        assert_eq!(updated_link.metadata, None);
+        assert!(!updated_link.disabled);
+
+        let response = api
+            .patch(format!("/admin/api/links/{}/", link.slug))
+            .typed_header(platform_auth_header(&platform.id, &api_key))
+            .body_json(&json!({"disabled": true}))
+            .send()
+            .await;
+
+        response.assert_status_is_ok();
+
+        let updated_link = get_link(&mut db, &link.slug).await.unwrap().unwrap();
+        assert!(updated_link.disabled);
+    }
+
+    #[sqlx::test]
+    async fn test_patch_update_link_invalid_url(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (api_key, platform) = create_platform(&mut db, "Villager Bot").await.unwrap();
+
+        let link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://villagerbot.com/".to_string(),
+            None,
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+
+        let api = api_test_client(db_pool);
+        let response = api
+            .patch(format!("/admin/api/links/{}/", link.slug))
+            .typed_header(platform_auth_header(&platform.id, &api_key))
+            .body_json(&PatchUpdateLinkRequest {
+                url: Some("javascript:alert(1)".to_string()),
+                metadata: None,
+                ..Default::default()
+            })
+            .send()
+            .await;
+
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        let link_after_request = get_link(&mut db, &link.slug).await.unwrap().unwrap();
+        assert_eq!(link_after_request.url, link.url);
+    }
+
+    #[sqlx::test]
+    async fn test_patch_update_link_of_other_platform(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (api_key, platform) = create_platform(&mut db, "Villager Bot").await.unwrap();
+        let (_, other_platform) = create_platform(&mut db, "Not Villager Bot").await.unwrap();
+
+        let other_link = create_link(
+            &mut db,
+            &other_platform.id,
+            None,
+            "https://example.com/".to_string(),
+            None,
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+
+        let api = api_test_client(db_pool);
+        let response = api
+            .patch(format!("/admin/api/links/{}/", other_link.slug))
+            .typed_header(platform_auth_header(&platform.id, &api_key))
+            .body_json(&PatchUpdateLinkRequest {
+                url: Some("https://iapetus11.me/".to_string()),
+                metadata: Some(json!({"hijacked": true})),
+                ..Default::default()
+            })
+            .send()
+            .await;
+
+        response.assert_status(StatusCode::NOT_FOUND);
+
+        let link_after_request = get_link(&mut db, &other_link.slug).await.unwrap().unwrap();
+        assert_eq!(link_after_request.url, other_link.url);
+        assert_eq!(link_after_request.metadata, other_link.metadata);
+    }
+
+    #[sqlx::test]
+    async fn test_delete_existing_link(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (api_key, platform) = create_platform(&mut db, "Villager Bot").await.unwrap();
+
+        let link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://villagerbot.com/".to_string(),
+            None,
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+
+        let api = api_test_client(db_pool);
+        let response = api
+            .delete(format!("/admin/api/links/{}/", link.slug))
+            .typed_header(platform_auth_header(&platform.id, &api_key))
+            .send()
+            .await;
+
+        response.assert_status_is_ok();
+        response
+            .assert_json(LinkDetailsView::from(link.clone()))
+            .await;
+
+        assert!(get_link(&mut db, &link.slug).await.unwrap().is_none());
+    }
+
+    #[sqlx::test]
+    async fn test_delete_existing_link_of_other_platform(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (api_key, platform) = create_platform(&mut db, "Villager Bot").await.unwrap();
+        let (_, other_platform) = create_platform(&mut db, "Not Villager Bot").await.unwrap();
+
+        let other_link = create_link(
+            &mut db,
+            &other_platform.id,
+            None,
+            "https://example.com/".to_string(),
+            None,
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+
+        let api = api_test_client(db_pool);
+        let response = api
+            .delete(format!("/admin/api/links/{}/", other_link.slug))
+            .typed_header(platform_auth_header(&platform.id, &api_key))
+            .send()
+            .await;
+
+        response.assert_status(StatusCode::NOT_FOUND);
+
+        assert!(get_link(&mut db, &other_link.slug).await.unwrap().is_some());
+    }
 }
@@ -1,7 +1,14 @@
-use poem::Route;
+use poem::{EndpointExt, Response, Route, endpoint::DynEndpoint};
+
+use crate::common::rate_limit::rate_limit_headers_middleware;
 
 mod links;
+mod stats;
 
-pub fn routes() -> Route {
-    Route::new().nest("/links/", links::routes())
+pub fn routes() -> Box<dyn DynEndpoint<Output = Response>> {
+    Route::new()
+        .nest("/links/", links::routes())
+        .nest("/stats/", stats::routes())
+        .around(rate_limit_headers_middleware)
+        .boxed()
 }
@@ -0,0 +1,415 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use poem::{
+    Route,
+    http::StatusCode,
+    web::{Data, Json, Path, Query},
+};
+use serde::Deserialize;
+use sqlx::PgConnection;
+
+use crate::{
+    common::platform_auth::AuthedPlatform,
+    db::link_stats::{
+        StatsBucket, TopValuesKind, VisitTotals, VisitsBucket, VisitsByValue, VisitsScope,
+        get_top_values, get_visit_totals, get_visits_time_series,
+    },
+};
+
+use super::links::get_platform_link;
+
+pub fn routes() -> Route {
+    Route::new().at("", poem::get(get_platform_stats))
+}
+
+const DEFAULT_STATS_PERIOD: TimeDelta = TimeDelta::days(30);
+const MAX_STATS_BUCKETS: i64 = 1000;
+const TOP_VALUES_LIMIT: i64 = 10;
+
+#[derive(Deserialize)]
+struct GetStatsQueryParams {
+    /// Defaults to 30 days before `to`
+    from: Option<DateTime<Utc>>,
+    /// Defaults to now
+    to: Option<DateTime<Utc>>,
+    #[serde(default)]
+    bucket: StatsBucket,
+    /// Whether visits from crawlers, link unfurlers, etc. are counted
+    #[serde(default)]
+    include_bots: bool,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[cfg_attr(test, derive(serde::Deserialize, PartialEq))]
+struct VisitsBucketView {
+    start: DateTime<Utc>,
+    visits: i64,
+    unique_visits: i64,
+}
+
+impl From<VisitsBucket> for VisitsBucketView {
+    fn from(value: VisitsBucket) -> Self {
+        VisitsBucketView {
+            start: value.start,
+            visits: value.visits,
+            unique_visits: value.unique_visits,
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+#[cfg_attr(test, derive(serde::Deserialize, PartialEq))]
+struct VisitsByValueView {
+    value: String,
+    visits: i64,
+}
+
+impl From<VisitsByValue> for VisitsByValueView {
+    fn from(value: VisitsByValue) -> Self {
+        VisitsByValueView {
+            value: value.value,
+            visits: value.visits,
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+#[cfg_attr(test, derive(serde::Deserialize))]
+struct VisitStatsView {
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    bucket: StatsBucket,
+    total_visits: i64,
+    /// Visits from distinct visitors, counted by a hash of their IP address and user agent salted
+    /// with a salt which changes every (UTC) day, so visitors are counted once per day they visit
+    unique_visits: i64,
+    time_series: Vec<VisitsBucketView>,
+    top_referrer_domains: Vec<VisitsByValueView>,
+    top_user_agents: Vec<VisitsByValueView>,
+    top_browsers: Vec<VisitsByValueView>,
+    top_operating_systems: Vec<VisitsByValueView>,
+    top_device_types: Vec<VisitsByValueView>,
+    /// ISO 3166-1 alpha-2 codes
+    top_countries: Vec<VisitsByValueView>,
+    top_regions: Vec<VisitsByValueView>,
+    top_cities: Vec<VisitsByValueView>,
+}
+
+/// Resolves the defaults of the query params into a scope, returning a BAD_REQUEST error if the
+/// period is invalid or would produce too many buckets
+fn stats_scope(
+    platform_id: uuid::Uuid,
+    link_slug: Option<String>,
+    query_params: &GetStatsQueryParams,
+) -> poem::Result<VisitsScope> {
+    let to = query_params.to.unwrap_or_else(Utc::now);
+    let from = query_params.from.unwrap_or(to - DEFAULT_STATS_PERIOD);
+
+    if from >= to {
+        return Err(poem::Error::from_string(
+            "from must be before to",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let bucket_count = (to - from).num_seconds() / query_params.bucket.duration().num_seconds();
+    if bucket_count > MAX_STATS_BUCKETS {
+        return Err(poem::Error::from_string(
+            format!("period is too long, it can contain at most {MAX_STATS_BUCKETS} buckets"),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    Ok(VisitsScope {
+        platform_id,
+        link_slug,
+        from,
+        to,
+        include_bots: query_params.include_bots,
+    })
+}
+
+async fn get_top_value_views(
+    db: &mut PgConnection,
+    scope: &VisitsScope,
+    kind: TopValuesKind,
+) -> Vec<VisitsByValueView> {
+    get_top_values(&mut *db, scope, kind, TOP_VALUES_LIMIT)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(VisitsByValueView::from)
+        .collect()
+}
+
+async fn get_visit_stats(
+    db: &mut PgConnection,
+    scope: &VisitsScope,
+    bucket: StatsBucket,
+) -> VisitStatsView {
+    let VisitTotals {
+        visits,
+        unique_visits,
+    } = get_visit_totals(&mut *db, scope).await.unwrap();
+
+    let time_series = get_visits_time_series(&mut *db, scope, bucket)
+        .await
+        .unwrap();
+
+    VisitStatsView {
+        from: scope.from,
+        to: scope.to,
+        bucket,
+        total_visits: visits,
+        unique_visits,
+        time_series: time_series
+            .into_iter()
+            .map(VisitsBucketView::from)
+            .collect(),
+        top_referrer_domains: get_top_value_views(&mut *db, scope, TopValuesKind::ReferrerDomain)
+            .await,
+        top_user_agents: get_top_value_views(&mut *db, scope, TopValuesKind::UserAgent).await,
+        top_browsers: get_top_value_views(&mut *db, scope, TopValuesKind::Browser).await,
+        top_operating_systems: get_top_value_views(&mut *db, scope, TopValuesKind::Os).await,
+        top_device_types: get_top_value_views(&mut *db, scope, TopValuesKind::DeviceType).await,
+        top_countries: get_top_value_views(&mut *db, scope, TopValuesKind::Country).await,
+        top_regions: get_top_value_views(&mut *db, scope, TopValuesKind::Region).await,
+        top_cities: get_top_value_views(&mut *db, scope, TopValuesKind::City).await,
+    }
+}
+
+#[poem::handler]
+pub async fn get_link_stats(
+    db: Data<&sqlx::PgPool>,
+    Path((slug,)): Path<(String,)>,
+    Query(query_params): Query<GetStatsQueryParams>,
+    AuthedPlatform(platform): AuthedPlatform,
+) -> poem::Result<Json<VisitStatsView>> {
+    let mut db = db.acquire().await.unwrap();
+
+    let link = get_platform_link(&mut db, &platform, &slug).await?;
+
+    let scope = stats_scope(platform.id, Some(link.slug), &query_params)?;
+
+    Ok(Json(
+        get_visit_stats(&mut db, &scope, query_params.bucket).await,
+    ))
+}
+
+#[poem::handler]
+pub async fn get_platform_stats(
+    db: Data<&sqlx::PgPool>,
+    Query(query_params): Query<GetStatsQueryParams>,
+    AuthedPlatform(platform): AuthedPlatform,
+) -> poem::Result<Json<VisitStatsView>> {
+    let mut db = db.acquire().await.unwrap();
+
+    let scope = stats_scope(platform.id, None, &query_params)?;
+
+    Ok(Json(
+        get_visit_stats(&mut db, &scope, query_params.bucket).await,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::TimeZone;
+    use sqlx::PgPool;
+
+    use crate::{
+        common::{
+            testing::app::{api_test_client, platform_auth_header},
+            user_agent::classify_user_agent,
+        },
+        db::{
+            link_visits::{NewLinkVisit, create_link_visits},
+            links::{LinkSettings, create_link},
+            platforms::create_platform,
+        },
+    };
+
+    use super::*;
+
+    const FIREFOX_USER_AGENT: &str =
+        "Mozilla/5.0 (X11; Linux x86_64; rv:127.0) Gecko/20100101 Firefox/127.0";
+
+    fn visit(link_slug: &str, at: DateTime<Utc>, user_agent: &str) -> NewLinkVisit {
+        NewLinkVisit {
+            link_slug: link_slug.to_string(),
+            at,
+            headers: HashMap::from([("user-agent".to_string(), vec![user_agent.to_string()])]),
+            ip_address: Some("127.0.0.1".to_string()),
+            user_agent: classify_user_agent(user_agent),
+            visitor: Some(format!("127.0.0.1\n{user_agent}")),
+            ..Default::default()
+        }
+    }
+
+    #[sqlx::test]
+    async fn test_get_link_and_platform_stats(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (api_key, platform) = create_platform(&mut db, "Stats").await.unwrap();
+
+        let mut slugs = vec![];
+        for _ in 0..2 {
+            let link = create_link(
+                &mut db,
+                &platform.id,
+                None,
+                "https://example.com/".to_string(),
+                None,
+                &LinkSettings::default(),
+            )
+            .await
+            .unwrap();
+            slugs.push(link.slug);
+        }
+
+        let at = |h: u32| Utc.with_ymd_and_hms(2025, 1, 1, h, 30, 0).unwrap();
+
+        create_link_visits(
+            &mut db,
+            &[
+                visit(&slugs[0], at(0), FIREFOX_USER_AGENT),
+                visit(&slugs[0], at(2), FIREFOX_USER_AGENT),
+                visit(&slugs[1], at(2), "curl/8.0"),
+            ],
+        )
+        .await
+        .unwrap();
+
+        let (from, to) = (at(0).to_rfc3339(), at(3).to_rfc3339());
+
+        let api = api_test_client(db_pool);
+
+        let response = api
+            .get(format!("/admin/api/links/{}/stats/", slugs[0]))
+            .query("from", &from)
+            .query("to", &to)
+            .query("bucket", &"hour")
+            .typed_header(platform_auth_header(&platform.id, &api_key))
+            .send()
+            .await;
+
+        response.assert_status_is_ok();
+
+        let stats: VisitStatsView = response.json().await.value().deserialize();
+        assert_eq!(stats.total_visits, 2);
+        assert_eq!(stats.unique_visits, 1);
+        assert_eq!(
+            stats
+                .time_series
+                .iter()
+                .map(|bucket| bucket.visits)
+                .collect::<Vec<_>>(),
+            vec![1, 0, 1, 0]
+        );
+        assert_eq!(
+            stats.top_user_agents,
+            vec![VisitsByValueView {
+                value: FIREFOX_USER_AGENT.to_string(),
+                visits: 2,
+            }]
+        );
+        assert_eq!(
+            stats.top_browsers,
+            vec![VisitsByValueView {
+                value: "Firefox".to_string(),
+                visits: 2,
+            }]
+        );
+        assert_eq!(
+            stats.top_device_types,
+            vec![VisitsByValueView {
+                value: "desktop".to_string(),
+                visits: 2,
+            }]
+        );
+
+        let response = api
+            .get("/admin/api/stats/")
+            .query("from", &from)
+            .query("to", &to)
+            .query("bucket", &"hour")
+            .typed_header(platform_auth_header(&platform.id, &api_key))
+            .send()
+            .await;
+
+        response.assert_status_is_ok();
+
+        let stats: VisitStatsView = response.json().await.value().deserialize();
+        assert_eq!(stats.total_visits, 2);
+        assert_eq!(stats.top_user_agents.len(), 1);
+        assert!(stats.top_referrer_domains.is_empty());
+
+        let response = api
+            .get("/admin/api/stats/")
+            .query("from", &from)
+            .query("to", &to)
+            .query("include_bots", &true)
+            .typed_header(platform_auth_header(&platform.id, &api_key))
+            .send()
+            .await;
+
+        response.assert_status_is_ok();
+
+        let stats: VisitStatsView = response.json().await.value().deserialize();
+        assert_eq!(stats.total_visits, 3);
+        assert_eq!(stats.top_user_agents.len(), 2);
+    }
+
+    #[sqlx::test]
+    async fn test_get_link_stats_of_other_platform(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (api_key, platform) = create_platform(&mut db, "Stats").await.unwrap();
+        let (_, other_platform) = create_platform(&mut db, "Other").await.unwrap();
+
+        let other_link = create_link(
+            &mut db,
+            &other_platform.id,
+            None,
+            "https://example.com/".to_string(),
+            None,
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+
+        let api = api_test_client(db_pool);
+        let response = api
+            .get(format!("/admin/api/links/{}/stats/", other_link.slug))
+            .typed_header(platform_auth_header(&platform.id, &api_key))
+            .send()
+            .await;
+
+        response.assert_status(StatusCode::NOT_FOUND);
+    }
+
+    #[sqlx::test]
+    async fn test_get_platform_stats_invalid_period(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (api_key, platform) = create_platform(&mut db, "Stats").await.unwrap();
+
+        let api = api_test_client(db_pool);
+
+        for (from, to, bucket) in [
+            ("2025-01-02T00:00:00Z", "2025-01-01T00:00:00Z", "day"),
+            ("2020-01-01T00:00:00Z", "2025-01-01T00:00:00Z", "hour"),
+        ] {
+            let response = api
+                .get("/admin/api/stats/")
+                .query("from", &from)
+                .query("to", &to)
+                .query("bucket", &bucket)
+                .typed_header(platform_auth_header(&platform.id, &api_key))
+                .send()
+                .await;
+
+            response.assert_status(StatusCode::BAD_REQUEST);
+        }
+    }
+}
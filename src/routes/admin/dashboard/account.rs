@@ -0,0 +1,562 @@
+use askama::Template;
+use chrono::{DateTime, Utc};
+use poem::{
+    EndpointExt, Response,
+    endpoint::DynEndpoint,
+    get,
+    http::{StatusCode, header},
+    post,
+    session::Session,
+    web::{Data, Form, Html, Redirect},
+};
+use serde::Deserialize;
+use serde_valid::Validate;
+use sqlx::{Acquire, PgConnection};
+use uuid::Uuid;
+
+use crate::{
+    common::{
+        argon2::{
+            argon2_check_key_against_hash, argon2_hash_key, setup_strong_argon2, setup_weak_argon2,
+        },
+        client_ip::ClientIp,
+        csrf::CsrfToken,
+        dashboard_auth::{
+            check_dashboard_second_factor, dashboard_auth_middleware, hash_recovery_codes,
+            validate_dashboard_password,
+        },
+        login_throttle::{
+            LoginAttemptReservation, format_wait, login_attempt_succeeded,
+            login_throttle_ip_address, reserve_login_attempt,
+        },
+        totp::{
+            base32_encode, generate_recovery_codes, generate_totp_secret, totp_provisioning_uri,
+            verify_totp_code,
+        },
+        validation::validate_to_poem_error,
+    },
+    config::CONFIG,
+    db::{
+        dashboard_login_token::delete_user_dashboard_login_tokens,
+        dashboard_recovery_codes::{
+            get_unused_dashboard_recovery_codes, replace_dashboard_recovery_codes,
+        },
+        dashboard_users::{
+            DashboardUser, UpdateDashboardUserData, enable_dashboard_user_totp,
+            set_dashboard_user_totp_secret, update_dashboard_user, use_dashboard_user_totp_step,
+        },
+    },
+};
+
+pub fn routes() -> Box<dyn DynEndpoint<Output = Response>> {
+    poem::Route::new()
+        .at("", get(get_view))
+        .at("/password/", post(post_change_password))
+        .at("/totp/start/", post(post_start_totp))
+        .at("/totp/confirm/", post(post_confirm_totp))
+        .at(
+            "/totp/recovery-codes/",
+            post(post_regenerate_recovery_codes),
+        )
+        .at("/totp/disable/", post(post_disable_totp))
+        .around(dashboard_auth_middleware)
+        .boxed()
+}
+
+/// Shown while enrolling, until the user confirms a code from their app
+struct TotpEnrollment {
+    provisioning_uri: String,
+    /// For typing into apps which can't scan the provisioning URI
+    secret: String,
+}
+
+#[derive(askama::Template)]
+#[template(path = "views/admin/dashboard/account.html")]
+struct AccountViewTemplate<'a> {
+    user: &'a DashboardUser,
+    csrf_token: &'a str,
+    enrollment: Option<TotpEnrollment>,
+    unused_recovery_codes: usize,
+    /// New recovery codes, which are only shown once
+    new_recovery_codes: Option<Vec<String>>,
+}
+
+async fn render_view(
+    db: &mut PgConnection,
+    user: &DashboardUser,
+    csrf_token: &CsrfToken,
+    new_recovery_codes: Option<Vec<String>>,
+) -> Html<String> {
+    let enrollment = match (&user.totp_secret, user.totp_enabled) {
+        (Some(secret), false) => Some(TotpEnrollment {
+            provisioning_uri: totp_provisioning_uri(secret, &user.username),
+            secret: base32_encode(secret),
+        }),
+        _ => None,
+    };
+
+    let unused_recovery_codes = get_unused_dashboard_recovery_codes(db, &user.id)
+        .await
+        .unwrap()
+        .len();
+
+    Html(
+        AccountViewTemplate {
+            user,
+            csrf_token: &csrf_token.0,
+            enrollment,
+            unused_recovery_codes,
+            new_recovery_codes,
+        }
+        .render()
+        .unwrap(),
+    )
+}
+
+fn account_redirect() -> Redirect {
+    Redirect::see_other("/admin/dashboard/account/")
+}
+
+fn incorrect_code_error() -> poem::Error {
+    poem::Error::from_string("Incorrect code, please try again", StatusCode::BAD_REQUEST)
+}
+
+fn locked_out_error(locked_until: DateTime<Utc>, now: DateTime<Utc>) -> poem::Error {
+    let wait = locked_until - now;
+
+    poem::Error::from_response(
+        Response::builder()
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .header(
+                header::RETRY_AFTER,
+                ((wait.num_milliseconds() + 999) / 1000).to_string(),
+            )
+            .body(format!(
+                "Too many failed attempts, please try again in {}",
+                format_wait(wait)
+            )),
+    )
+}
+
+/// Attempts at the user's password or second factor count towards the same lockouts as logins, so a
+/// hijacked session can't be used to guess them
+async fn reserve_account_attempt(
+    db: &mut PgConnection,
+    user: &DashboardUser,
+    ClientIp(client_ip): ClientIp,
+    now: DateTime<Utc>,
+) -> poem::Result<Uuid> {
+    let throttle_ip_address = client_ip.map(login_throttle_ip_address);
+
+    match reserve_login_attempt(
+        db,
+        throttle_ip_address.as_deref(),
+        &user.username,
+        now,
+        &CONFIG.dashboard_login_throttle,
+    )
+    .await
+    .unwrap()
+    {
+        LoginAttemptReservation::Reserved(attempt_id) => Ok(attempt_id),
+        LoginAttemptReservation::LockedOut { until } => Err(locked_out_error(until, now)),
+    }
+}
+
+/// Checks the user's TOTP or recovery code, see reserve_account_attempt
+async fn check_second_factor_throttled(
+    db: &mut PgConnection,
+    user: &DashboardUser,
+    code: &str,
+    client_ip: ClientIp,
+) -> poem::Result<()> {
+    if !user.totp_enabled {
+        return Err(incorrect_code_error());
+    }
+
+    let now = Utc::now();
+    let attempt_id = reserve_account_attempt(db, user, client_ip, now).await?;
+
+    if !check_dashboard_second_factor(db, user, code, now)
+        .await
+        .unwrap()
+    {
+        return Err(incorrect_code_error());
+    }
+
+    login_attempt_succeeded(db, &attempt_id).await.unwrap();
+
+    Ok(())
+}
+
+#[poem::handler]
+pub async fn get_view(
+    db_pool: Data<&sqlx::PgPool>,
+    Data(user): Data<&DashboardUser>,
+    Data(csrf_token): Data<&CsrfToken>,
+) -> Html<String> {
+    let mut db = db_pool.acquire().await.unwrap();
+
+    render_view(&mut db, user, csrf_token, None).await
+}
+
+#[derive(Validate, Deserialize)]
+pub struct PostChangePasswordRequest {
+    current_password: String,
+
+    #[validate(custom = validate_dashboard_password)]
+    new_password: String,
+}
+
+/// Changes the user's password and logs them out everywhere, including this session
+#[poem::handler]
+pub async fn post_change_password(
+    session: &Session,
+    db_pool: Data<&sqlx::PgPool>,
+    Data(user): Data<&DashboardUser>,
+    client_ip: ClientIp,
+    Form(change_password_request): Form<PostChangePasswordRequest>,
+) -> poem::Result<Redirect> {
+    let PostChangePasswordRequest {
+        current_password,
+        new_password,
+    } = validate_to_poem_error(change_password_request)?;
+
+    let mut db = db_pool.acquire().await.unwrap();
+
+    let attempt_id = reserve_account_attempt(&mut db, user, client_ip, Utc::now()).await?;
+
+    if !argon2_check_key_against_hash(&setup_weak_argon2(), &current_password, &user.password_hash)
+    {
+        return Err(poem::Error::from_string(
+            "Incorrect current password, please try again",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    login_attempt_succeeded(&mut db, &attempt_id).await.unwrap();
+
+    let mut db = db.begin().await.unwrap();
+
+    update_dashboard_user(
+        &mut db,
+        &user.id,
+        &UpdateDashboardUserData {
+            password_hash: Some(argon2_hash_key(&setup_strong_argon2(), &new_password)),
+            role: None,
+        },
+    )
+    .await
+    .unwrap();
+    delete_user_dashboard_login_tokens(&mut db, &user.id)
+        .await
+        .unwrap();
+
+    db.commit().await.unwrap();
+
+    session.purge();
+
+    Ok(Redirect::see_other("/admin/dashboard/login/"))
+}
+
+#[poem::handler]
+pub async fn post_start_totp(
+    db_pool: Data<&sqlx::PgPool>,
+    Data(user): Data<&DashboardUser>,
+) -> poem::Result<Redirect> {
+    if user.totp_enabled {
+        return Err(poem::Error::from_string(
+            "Two-factor authentication is already enabled",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let mut db = db_pool.acquire().await.unwrap();
+
+    set_dashboard_user_totp_secret(&mut db, &user.id, Some(&generate_totp_secret()))
+        .await
+        .unwrap();
+
+    Ok(account_redirect())
+}
+
+#[derive(Deserialize)]
+pub struct PostTotpCodeRequest {
+    code: String,
+}
+
+/// Enables two-factor authentication once the user proves their app has the secret, showing their
+/// recovery codes
+#[poem::handler]
+pub async fn post_confirm_totp(
+    db_pool: Data<&sqlx::PgPool>,
+    Data(user): Data<&DashboardUser>,
+    Data(csrf_token): Data<&CsrfToken>,
+    Form(PostTotpCodeRequest { code }): Form<PostTotpCodeRequest>,
+) -> poem::Result<Html<String>> {
+    let (Some(secret), false) = (&user.totp_secret, user.totp_enabled) else {
+        return Err(poem::Error::from_string(
+            "Start setting up two-factor authentication first",
+            StatusCode::BAD_REQUEST,
+        ));
+    };
+
+    let Some(step) = verify_totp_code(secret, &code, Utc::now(), user.totp_last_used_step) else {
+        return Err(incorrect_code_error());
+    };
+
+    let mut db = db_pool.begin().await.unwrap();
+
+    use_dashboard_user_totp_step(&mut db, &user.id, step)
+        .await
+        .unwrap();
+    enable_dashboard_user_totp(&mut db, &user.id).await.unwrap();
+
+    let recovery_codes = generate_recovery_codes();
+    replace_dashboard_recovery_codes(&mut db, &user.id, &hash_recovery_codes(&recovery_codes))
+        .await
+        .unwrap();
+
+    db.commit().await.unwrap();
+
+    let mut db = db_pool.acquire().await.unwrap();
+    let user = DashboardUser {
+        totp_enabled: true,
+        ..user.clone()
+    };
+
+    Ok(render_view(&mut db, &user, csrf_token, Some(recovery_codes)).await)
+}
+
+/// Replaces the user's recovery codes, for when they've used or lost some
+#[poem::handler]
+pub async fn post_regenerate_recovery_codes(
+    db_pool: Data<&sqlx::PgPool>,
+    Data(user): Data<&DashboardUser>,
+    Data(csrf_token): Data<&CsrfToken>,
+    client_ip: ClientIp,
+    Form(PostTotpCodeRequest { code }): Form<PostTotpCodeRequest>,
+) -> poem::Result<Html<String>> {
+    let mut db = db_pool.acquire().await.unwrap();
+
+    check_second_factor_throttled(&mut db, user, &code, client_ip).await?;
+
+    let recovery_codes = generate_recovery_codes();
+    replace_dashboard_recovery_codes(&mut db, &user.id, &hash_recovery_codes(&recovery_codes))
+        .await
+        .unwrap();
+
+    Ok(render_view(&mut db, user, csrf_token, Some(recovery_codes)).await)
+}
+
+#[poem::handler]
+pub async fn post_disable_totp(
+    db_pool: Data<&sqlx::PgPool>,
+    Data(user): Data<&DashboardUser>,
+    client_ip: ClientIp,
+    Form(PostTotpCodeRequest { code }): Form<PostTotpCodeRequest>,
+) -> poem::Result<Redirect> {
+    let mut db = db_pool.acquire().await.unwrap();
+
+    check_second_factor_throttled(&mut db, user, &code, client_ip).await?;
+
+    let mut db = db.begin().await.unwrap();
+
+    set_dashboard_user_totp_secret(&mut db, &user.id, None)
+        .await
+        .unwrap();
+    replace_dashboard_recovery_codes(&mut db, &user.id, &[])
+        .await
+        .unwrap();
+
+    db.commit().await.unwrap();
+
+    Ok(account_redirect())
+}
+
+#[cfg(test)]
+mod tests {
+    use poem::http::header;
+    use sqlx::PgPool;
+
+    use crate::{
+        common::{
+            csrf::CSRF_TOKEN_HEADER,
+            testing::app::{api_test_client, dashboard_test_session},
+            totp::current_totp_code,
+        },
+        db::dashboard_users::{DashboardUserRole, get_dashboard_users},
+    };
+
+    use super::*;
+
+    #[sqlx::test]
+    async fn test_enable_and_disable_totp(db_pool: PgPool) {
+        let cli = api_test_client(db_pool.clone());
+        let session = dashboard_test_session(&cli, &db_pool, DashboardUserRole::Viewer, &[]).await;
+
+        let get_user = async || {
+            let mut db = db_pool.acquire().await.unwrap();
+            get_dashboard_users(&mut db).await.unwrap().remove(0)
+        };
+
+        cli.post("/admin/dashboard/account/totp/start/")
+            .header(header::COOKIE, &session.cookie)
+            .header(CSRF_TOKEN_HEADER, &session.csrf_token)
+            .send()
+            .await
+            .assert_status(StatusCode::SEE_OTHER);
+
+        let user = get_user().await;
+        let secret = user.totp_secret.unwrap();
+        assert!(!user.totp_enabled);
+
+        let resp = cli
+            .get("/admin/dashboard/account/")
+            .header(header::COOKIE, &session.cookie)
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        assert!(
+            resp.0
+                .into_body()
+                .into_string()
+                .await
+                .unwrap()
+                .contains(&base32_encode(&secret))
+        );
+
+        cli.post("/admin/dashboard/account/totp/confirm/")
+            .header(header::COOKIE, &session.cookie)
+            .header(CSRF_TOKEN_HEADER, &session.csrf_token)
+            .form(&[("code", "000000")])
+            .send()
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+        assert!(!get_user().await.totp_enabled);
+
+        let code = current_totp_code(&secret);
+        let resp = cli
+            .post("/admin/dashboard/account/totp/confirm/")
+            .header(header::COOKIE, &session.cookie)
+            .header(CSRF_TOKEN_HEADER, &session.csrf_token)
+            .form(&[("code", &code)])
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        let body = resp.0.into_body().into_string().await.unwrap();
+        assert!(get_user().await.totp_enabled);
+
+        // The recovery codes are shown once, and one can be used to disable two-factor
+        // authentication since the app's code has already been used
+        let recovery_code = body
+            .lines()
+            .map(str::trim)
+            .find_map(|line| line.strip_prefix("<li>")?.strip_suffix("</li>"))
+            .unwrap()
+            .to_string();
+
+        cli.post("/admin/dashboard/account/totp/disable/")
+            .header(header::COOKIE, &session.cookie)
+            .header(CSRF_TOKEN_HEADER, &session.csrf_token)
+            .form(&[("code", &code)])
+            .send()
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+
+        cli.post("/admin/dashboard/account/totp/disable/")
+            .header(header::COOKIE, &session.cookie)
+            .header(CSRF_TOKEN_HEADER, &session.csrf_token)
+            .form(&[("code", recovery_code)])
+            .send()
+            .await
+            .assert_status(StatusCode::SEE_OTHER);
+
+        let user = get_user().await;
+        assert!(!user.totp_enabled);
+        assert!(user.totp_secret.is_none());
+    }
+
+    #[sqlx::test]
+    async fn test_second_factor_attempts_locked_out(db_pool: PgPool) {
+        let cli = api_test_client(db_pool.clone());
+        let session = dashboard_test_session(&cli, &db_pool, DashboardUserRole::Viewer, &[]).await;
+
+        let secret = b"12345678901234567890";
+        let mut db = db_pool.acquire().await.unwrap();
+        let user = get_dashboard_users(&mut db).await.unwrap().remove(0);
+        set_dashboard_user_totp_secret(&mut db, &user.id, Some(secret))
+            .await
+            .unwrap();
+        enable_dashboard_user_totp(&mut db, &user.id).await.unwrap();
+        drop(db);
+
+        let disable_totp = |code: String| {
+            cli.post("/admin/dashboard/account/totp/disable/")
+                .header(header::COOKIE, &session.cookie)
+                .header(CSRF_TOKEN_HEADER, &session.csrf_token)
+                .header("x-forwarded-for", "1.1.1.1")
+                .form(&[("code", code)])
+                .send()
+        };
+
+        for _ in 0..CONFIG.dashboard_login_throttle.free_attempts_per_ip {
+            disable_totp("000000".to_string())
+                .await
+                .assert_status(StatusCode::BAD_REQUEST);
+        }
+
+        disable_totp("000000".to_string())
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+
+        // Even the right code isn't checked while locked out
+        let resp = disable_totp(current_totp_code(secret)).await;
+        resp.assert_status(StatusCode::TOO_MANY_REQUESTS);
+        resp.assert_header(header::RETRY_AFTER, "30");
+    }
+
+    #[sqlx::test]
+    async fn test_change_password(db_pool: PgPool) {
+        let cli = api_test_client(db_pool.clone());
+        let session = dashboard_test_session(&cli, &db_pool, DashboardUserRole::Viewer, &[]).await;
+
+        let change_password = |current_password: &'static str, new_password: &'static str| {
+            cli.post("/admin/dashboard/account/password/")
+                .header(header::COOKIE, &session.cookie)
+                .header(CSRF_TOKEN_HEADER, &session.csrf_token)
+                .form(&[
+                    ("current_password", current_password),
+                    ("new_password", new_password),
+                ])
+                .send()
+        };
+
+        change_password("wrong password", "new password")
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+        change_password("password", "short")
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+
+        let resp = change_password("password", "new password").await;
+        resp.assert_status(StatusCode::SEE_OTHER);
+        resp.assert_header(header::LOCATION, "/admin/dashboard/login/");
+
+        let mut db = db_pool.acquire().await.unwrap();
+        let user = get_dashboard_users(&mut db).await.unwrap().remove(0);
+        assert!(argon2_check_key_against_hash(
+            &setup_weak_argon2(),
+            "new password",
+            &user.password_hash
+        ));
+        drop(db);
+
+        // The session was logged out along with any others
+        cli.get("/admin/dashboard/account/")
+            .header(header::COOKIE, &session.cookie)
+            .send()
+            .await
+            .assert_status(StatusCode::SEE_OTHER);
+    }
+}
@@ -1,4 +1,7 @@
+use std::collections::HashMap;
+
 use askama::Template;
+use chrono::{DateTime, Datelike, NaiveDateTime, TimeDelta, Utc};
 use poem::{
     EndpointExt, Response,
     endpoint::DynEndpoint,
@@ -14,15 +17,24 @@ use uuid::Uuid;
 
 use crate::{
     common::{
-        dashboard_auth::dashboard_auth_middleware,
+        csrf::CsrfToken,
+        dashboard_auth::{dashboard_auth_middleware, require_dashboard_permission},
+        link_cache::LinkCache,
+        link_validation::{validate_link_slug, validate_link_url},
         platform_auth::{PlatformApiKeyAndHash, generate_platform_api_key},
-        validation::validate_to_poem_error,
+        validation::{validate_if_present, validate_to_poem_error},
     },
     db::{
-        links::{Link, create_link, delete_link, get_links},
+        dashboard_users::DashboardUser,
+        link_stats::get_visit_counts_by_link,
+        links::{
+            Link, LinkSettings, RedirectType, UpdateLinkData, create_link, delete_link, get_link,
+            get_links, update_link,
+        },
         platforms::{
-            Platform, UpdatePlatformData, create_platform, delete_platform, get_platform,
-            get_platform_by_name, get_platforms, update_platform,
+            Platform, UpdatePlatformData, create_platform, delete_platform,
+            get_monthly_links_created, get_platform, get_platform_by_name, get_platforms,
+            update_platform,
         },
     },
 };
@@ -32,8 +44,11 @@ pub fn routes() -> Box<dyn DynEndpoint<Output = Response>> {
         .at("", get(get_view))
         .at("/reset-api-key/", post(post_reset_api_key))
         .at("/create-platform/", post(post_create_platform))
+        .at("/update-platform/", post(post_update_platform))
         .at("/delete-platform/", post(post_delete_platform))
         .at("/create-link/", post(post_create_link))
+        .at("/update-link-disabled/", post(post_update_link_disabled))
+        .at("/update-link-limits/", post(post_update_link_limits))
         .at("/delete-link/", post(post_delete_link))
         .around(dashboard_auth_middleware)
         .boxed()
@@ -61,17 +76,25 @@ pub struct HomeViewQueryParams {
 #[derive(askama::Template)]
 #[template(path = "views/admin/dashboard/home.html")]
 struct HomeViewTemplate<'a> {
+    user: &'a DashboardUser,
+    csrf_token: &'a str,
     platforms: &'a Vec<Platform>,
     links: &'a Vec<Link>,
+    /// All-time visits by link slug, links without visits are missing
+    visit_counts: &'a HashMap<String, i64>,
 
     state: &'a PageState,
 
     selected_platform: Option<&'a Platform>,
+    /// Links the selected platform has created through the API this month, for its quota
+    links_created_this_month: i64,
 }
 
 #[poem::handler]
 pub async fn get_view(
     db_pool: Data<&sqlx::PgPool>,
+    Data(user): Data<&DashboardUser>,
+    Data(csrf_token): Data<&CsrfToken>,
     session: &Session,
     Query(HomeViewQueryParams {
         platform: selected_platform_id,
@@ -79,16 +102,33 @@ pub async fn get_view(
 ) -> poem::Result<Html<String>> {
     let mut db = db_pool.acquire().await.unwrap();
 
-    let platforms = get_platforms(&mut db).await.unwrap();
+    let mut platforms = get_platforms(&mut db).await.unwrap();
+    platforms.retain(|platform| user.can_view_platform(&platform.id));
 
     let links: Vec<Link>;
+    let visit_counts: HashMap<String, i64>;
     let selected_platform: Option<&Platform>;
+    let links_created_this_month: i64;
     if let Some(selected_platform_id) = selected_platform_id {
+        require_dashboard_permission(user.can_view_platform(&selected_platform_id))?;
+
         links = get_links(&mut db, &selected_platform_id).await.unwrap();
+        visit_counts = get_visit_counts_by_link(&mut db, &selected_platform_id)
+            .await
+            .unwrap();
         selected_platform = platforms.iter().find(|p| p.id == selected_platform_id);
+        links_created_this_month = get_monthly_links_created(
+            &mut db,
+            &selected_platform_id,
+            Utc::now().date_naive().with_day(1).unwrap(),
+        )
+        .await
+        .unwrap();
     } else {
         links = vec![];
+        visit_counts = HashMap::new();
         selected_platform = None;
+        links_created_this_month = 0;
     };
 
     let mut page_state = session.get::<PageState>(PAGE_STATE_KEY).unwrap_or_default();
@@ -102,10 +142,14 @@ pub async fn get_view(
 
     Ok(Html(
         HomeViewTemplate {
+            user,
+            csrf_token: &csrf_token.0,
             platforms: &platforms,
             links: &links,
+            visit_counts: &visit_counts,
             state: &page_state,
             selected_platform,
+            links_created_this_month,
         }
         .render()
         .unwrap(),
@@ -120,9 +164,12 @@ struct PostResetAPIKeyRequest {
 #[poem::handler]
 pub async fn post_reset_api_key(
     db_pool: Data<&sqlx::PgPool>,
+    Data(user): Data<&DashboardUser>,
     Form(PostResetAPIKeyRequest { platform_id }): Form<PostResetAPIKeyRequest>,
     session: &Session,
 ) -> poem::Result<Redirect> {
+    require_dashboard_permission(user.can_edit_platform(&platform_id))?;
+
     let mut db = db_pool.acquire().await.unwrap();
 
     let PlatformApiKeyAndHash {
@@ -167,9 +214,12 @@ pub struct PostCreatePlatformRequest {
 #[poem::handler]
 pub async fn post_create_platform(
     db_pool: Data<&sqlx::PgPool>,
+    Data(user): Data<&DashboardUser>,
     Form(create_platform_request): Form<PostCreatePlatformRequest>,
     session: &Session,
 ) -> poem::Result<Redirect> {
+    require_dashboard_permission(user.is_owner())?;
+
     let PostCreatePlatformRequest { name } = validate_to_poem_error(create_platform_request)?;
 
     let mut db = db_pool.acquire().await.unwrap();
@@ -197,6 +247,107 @@ pub async fn post_create_platform(
     )))
 }
 
+#[derive(Validate, Deserialize)]
+pub struct PostUpdatePlatformRequest {
+    platform_id: Uuid,
+
+    default_redirect_type: RedirectType,
+
+    /// Empty to remove the platform's fallback URL
+    #[validate(custom = |url| validate_if_present(url, validate_link_url))]
+    unavailable_fallback_url: Option<String>,
+
+    /// Empty to remove the platform's fallback HTML
+    unavailable_fallback_html: Option<String>,
+
+    /// Empty to remove the platform's rate limit
+    api_requests_per_minute: Option<String>,
+
+    /// Empty to remove the platform's quota
+    monthly_link_quota: Option<String>,
+}
+
+/// Parses the value of an optional number input, which must be a whole number of at least 1
+fn parse_positive_number_input<T: std::str::FromStr + PartialOrd + From<u8>>(
+    value: Option<String>,
+    name: &str,
+) -> poem::Result<Option<T>> {
+    match value.filter(|v| !v.is_empty()) {
+        None => Ok(None),
+        Some(value) => Some(
+            value
+                .parse::<T>()
+                .ok()
+                .filter(|v| *v >= T::from(1))
+                .ok_or_else(|| {
+                    poem::Error::from_string(
+                        format!("{name} must be a whole number of at least 1"),
+                        StatusCode::BAD_REQUEST,
+                    )
+                }),
+        )
+        .transpose(),
+    }
+}
+
+#[poem::handler]
+pub async fn post_update_platform(
+    db_pool: Data<&sqlx::PgPool>,
+    Data(user): Data<&DashboardUser>,
+    link_cache: Data<&LinkCache>,
+    Form(mut update_platform_request): Form<PostUpdatePlatformRequest>,
+) -> poem::Result<Redirect> {
+    require_dashboard_permission(user.is_owner())?;
+
+    update_platform_request.unavailable_fallback_url = update_platform_request
+        .unavailable_fallback_url
+        .filter(|u| !u.is_empty());
+    update_platform_request.unavailable_fallback_html = update_platform_request
+        .unavailable_fallback_html
+        .filter(|h| !h.trim().is_empty());
+
+    let PostUpdatePlatformRequest {
+        platform_id,
+        default_redirect_type,
+        unavailable_fallback_url,
+        unavailable_fallback_html,
+        api_requests_per_minute,
+        monthly_link_quota,
+    } = validate_to_poem_error(update_platform_request)?;
+
+    let api_requests_per_minute =
+        parse_positive_number_input::<i32>(api_requests_per_minute, "API requests per minute")?;
+    let monthly_link_quota =
+        parse_positive_number_input::<i64>(monthly_link_quota, "Monthly link quota")?;
+
+    let mut db = db_pool.acquire().await.unwrap();
+
+    let platform = update_platform(
+        &mut db,
+        &platform_id,
+        &UpdatePlatformData {
+            default_redirect_type: Some(default_redirect_type),
+            unavailable_fallback_url: Some(unavailable_fallback_url),
+            unavailable_fallback_html: Some(unavailable_fallback_html),
+            api_requests_per_minute: Some(api_requests_per_minute),
+            monthly_link_quota: Some(monthly_link_quota),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    // Cached links include their platform's redirect type and fallbacks
+    link_cache.invalidate_all();
+
+    match platform {
+        None => Err(poem::Error::from_status(StatusCode::BAD_REQUEST)),
+        Some(_) => Ok(Redirect::see_other(format!(
+            "/admin/dashboard/?platform={platform_id}"
+        ))),
+    }
+}
+
 #[derive(Deserialize)]
 pub struct PostDeletePlatformRequest {
     platform_id: Uuid,
@@ -205,12 +356,19 @@ pub struct PostDeletePlatformRequest {
 #[poem::handler]
 pub async fn post_delete_platform(
     db_pool: Data<&sqlx::PgPool>,
+    Data(user): Data<&DashboardUser>,
+    link_cache: Data<&LinkCache>,
     Form(PostDeletePlatformRequest { platform_id }): Form<PostDeletePlatformRequest>,
 ) -> poem::Result<Redirect> {
+    require_dashboard_permission(user.is_owner())?;
+
     let mut db = db_pool.acquire().await.unwrap();
 
     let deleted_platform = delete_platform(&mut db, &platform_id).await.unwrap();
 
+    // The platform's links were deleted along with it
+    link_cache.invalidate_all();
+
     match deleted_platform {
         None => Err(poem::Error::from_status(StatusCode::BAD_REQUEST)),
         Some(_) => Ok(Redirect::see_other("/admin/dashboard/")),
@@ -221,26 +379,66 @@ pub async fn post_delete_platform(
 pub struct PostCreateLinkRequest {
     platform_id: Uuid,
 
-    #[validate(min_length = 7)]
-    #[validate(max_length = 1000)]
-    #[validate(
-        pattern = r"https?:\/\/(.+)?[-a-zA-Z0-9@:%._\+~#=]{1,256}(\.[a-zA-Z0-9()]{1,6})?\b([-a-zA-Z0-9()@:%_\+.~#?&//=]*)"
-    )]
+    #[validate(custom = validate_link_url)]
     url: String,
 
-    #[validate(min_length = 2)]
-    #[validate(max_length = 100)]
-    #[validate(pattern = r"^[\w\-]{2,28}$")]
+    #[validate(custom = |slug| validate_if_present(slug, validate_link_slug))]
     slug: Option<String>,
 
     metadata: Option<serde_json::Value>,
+
+    /// Local date & time from a datetime-local input
+    expires_at: Option<String>,
+
+    /// Offset of expires_at from UTC, see parse_datetime_local_input
+    timezone_offset: Option<String>,
+
+    max_visits: Option<String>,
+
+    /// Empty to use the platform's default
+    redirect_type: Option<String>,
+}
+
+/// Parses the value of a datetime-local input, which is in the browser's timezone. The offset is
+/// what `Date.getTimezoneOffset()` returns in the browser for the entered date & time, UTC minus
+/// local time in minutes. Without an offset, e.g. if JavaScript is disabled, the value is assumed to
+/// be in UTC
+fn parse_datetime_local_input(value: &str, timezone_offset: Option<&str>) -> Option<DateTime<Utc>> {
+    let offset_minutes = match timezone_offset.filter(|o| !o.is_empty()) {
+        None => 0,
+        Some(offset) => offset.parse::<i64>().ok().filter(|o| o.abs() <= 24 * 60)?,
+    };
+
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S"))
+        .ok()
+        .map(|datetime| datetime.and_utc() + TimeDelta::minutes(offset_minutes))
+}
+
+/// Parses the optional expiration date input of a link form
+fn parse_expires_at_input(
+    expires_at: Option<String>,
+    timezone_offset: Option<String>,
+) -> poem::Result<Option<DateTime<Utc>>> {
+    match expires_at.filter(|e| !e.is_empty()) {
+        None => Ok(None),
+        Some(expires_at) => parse_datetime_local_input(&expires_at, timezone_offset.as_deref())
+            .map(Some)
+            .ok_or_else(|| {
+                poem::Error::from_string("Invalid expiration date", StatusCode::BAD_REQUEST)
+            }),
+    }
 }
 
 #[poem::handler]
 pub async fn post_create_link(
     db_pool: Data<&sqlx::PgPool>,
+    Data(user): Data<&DashboardUser>,
+    link_cache: Data<&LinkCache>,
     Form(mut create_link_request): Form<PostCreateLinkRequest>,
 ) -> poem::Result<Redirect> {
+    require_dashboard_permission(user.can_edit_platform(&create_link_request.platform_id))?;
+
     if create_link_request
         .slug
         .as_ref()
@@ -260,33 +458,35 @@ pub async fn post_create_link(
         } {
             create_link_request.metadata = None;
         // Take string from text input and re-parse it
-        } else if let serde_json::Value::String(string) = metadata {
-            if let Ok(parsed_str) = serde_json::from_str::<serde_json::Value>(string) {
-                create_link_request.metadata = Some(parsed_str);
-            }
+        } else if let serde_json::Value::String(string) = metadata
+            && let Ok(parsed_str) = serde_json::from_str::<serde_json::Value>(string)
+        {
+            create_link_request.metadata = Some(parsed_str);
         }
     }
 
     let PostCreateLinkRequest {
         platform_id,
         url,
-        mut slug,
-        mut metadata,
+        slug,
+        metadata,
+        expires_at,
+        timezone_offset,
+        max_visits,
+        redirect_type,
     } = validate_to_poem_error(create_link_request)?;
 
-    let mut db = db_pool.acquire().await.unwrap();
+    let expires_at = parse_expires_at_input(expires_at, timezone_offset)?;
+    let max_visits = parse_positive_number_input::<i64>(max_visits, "Max visits")?;
 
-    // See src/routes/mod.rs::routes
-    const BLACKLISTED_SLUGS: &[&str] = &["admin", "static"];
-    if slug
-        .as_ref()
-        .is_some_and(|slug| BLACKLISTED_SLUGS.contains(&slug.as_str()))
-    {
-        return Err(poem::Error::from_string(
-            format!("Slug cannot be one of: {}", BLACKLISTED_SLUGS.join(", ")),
-            StatusCode::BAD_REQUEST,
-        ));
-    }
+    let redirect_type = match redirect_type.filter(|r| !r.is_empty()) {
+        None => None,
+        Some(redirect_type) => Some(redirect_type.parse::<RedirectType>().map_err(|_| {
+            poem::Error::from_string("Invalid redirect type", StatusCode::BAD_REQUEST)
+        })?),
+    };
+
+    let mut db = db_pool.acquire().await.unwrap();
 
     if get_platform(&mut db, &platform_id).await.unwrap().is_none() {
         return Err(poem::Error::from_string(
@@ -295,15 +495,150 @@ pub async fn post_create_link(
         ));
     }
 
-    create_link(&mut db, &platform_id, slug, url, metadata)
-        .await
-        .unwrap();
+    let link = create_link(
+        &mut db,
+        &platform_id,
+        slug,
+        url,
+        metadata,
+        &LinkSettings {
+            expires_at,
+            max_visits,
+            redirect_type,
+        },
+    )
+    .await
+    .unwrap();
+
+    link_cache.invalidate(&link.slug);
 
     Ok(Redirect::see_other(format!(
         "/admin/dashboard/?platform={platform_id}"
     )))
 }
 
+/// Rejects the request unless the user can edit the platform of the link, if it exists
+/// Returns the ID of the link's platform, which writes to the link should be scoped to
+async fn require_link_edit_permission(
+    db: &mut sqlx::PgConnection,
+    user: &DashboardUser,
+    link_slug: &str,
+) -> poem::Result<Uuid> {
+    match get_link(db, link_slug).await.unwrap() {
+        None => Err(poem::Error::from_string(
+            "Link for specified slug does not exist",
+            StatusCode::NOT_FOUND,
+        )),
+        Some(link) => {
+            require_dashboard_permission(user.can_edit_platform(&link.platform_id))?;
+            Ok(link.platform_id)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PostUpdateLinkDisabledRequest {
+    link_slug: String,
+    disabled: bool,
+}
+
+#[poem::handler]
+pub async fn post_update_link_disabled(
+    db_pool: Data<&sqlx::PgPool>,
+    Data(user): Data<&DashboardUser>,
+    link_cache: Data<&LinkCache>,
+    Form(PostUpdateLinkDisabledRequest {
+        link_slug,
+        disabled,
+    }): Form<PostUpdateLinkDisabledRequest>,
+) -> poem::Result<Redirect> {
+    let mut db = db_pool.acquire().await.unwrap();
+
+    let platform_id = require_link_edit_permission(&mut db, user, &link_slug).await?;
+
+    let updated_link = update_link(
+        &mut db,
+        &platform_id,
+        &link_slug,
+        &UpdateLinkData {
+            disabled: Some(disabled),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    link_cache.invalidate(&link_slug);
+
+    match updated_link {
+        None => Err(poem::Error::from_string(
+            "Link for specified slug does not exist",
+            StatusCode::NOT_FOUND,
+        )),
+        Some(updated_link) => Ok(Redirect::see_other(format!(
+            "/admin/dashboard/?platform={}",
+            updated_link.platform_id
+        ))),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PostUpdateLinkLimitsRequest {
+    link_slug: String,
+    /// Local date & time from a datetime-local input, empty to remove the link's expiration
+    expires_at: Option<String>,
+    /// Offset of expires_at from UTC, see parse_datetime_local_input
+    timezone_offset: Option<String>,
+    /// Empty to remove the link's visit limit
+    max_visits: Option<String>,
+}
+
+#[poem::handler]
+pub async fn post_update_link_limits(
+    db_pool: Data<&sqlx::PgPool>,
+    Data(user): Data<&DashboardUser>,
+    link_cache: Data<&LinkCache>,
+    Form(PostUpdateLinkLimitsRequest {
+        link_slug,
+        expires_at,
+        timezone_offset,
+        max_visits,
+    }): Form<PostUpdateLinkLimitsRequest>,
+) -> poem::Result<Redirect> {
+    let expires_at = parse_expires_at_input(expires_at, timezone_offset)?;
+    let max_visits = parse_positive_number_input::<i64>(max_visits, "Max visits")?;
+
+    let mut db = db_pool.acquire().await.unwrap();
+
+    let platform_id = require_link_edit_permission(&mut db, user, &link_slug).await?;
+
+    let updated_link = update_link(
+        &mut db,
+        &platform_id,
+        &link_slug,
+        &UpdateLinkData {
+            expires_at: Some(expires_at),
+            max_visits: Some(max_visits),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    link_cache.invalidate(&link_slug);
+
+    match updated_link {
+        None => Err(poem::Error::from_string(
+            "Link for specified slug does not exist",
+            StatusCode::NOT_FOUND,
+        )),
+        Some(updated_link) => Ok(Redirect::see_other(format!(
+            "/admin/dashboard/?platform={}",
+            updated_link.platform_id
+        ))),
+    }
+}
+
 #[derive(Deserialize)]
 pub struct PostDeleteLinkRequest {
     link_slug: String,
@@ -312,11 +647,19 @@ pub struct PostDeleteLinkRequest {
 #[poem::handler]
 pub async fn post_delete_link(
     db_pool: Data<&sqlx::PgPool>,
+    Data(user): Data<&DashboardUser>,
+    link_cache: Data<&LinkCache>,
     Form(PostDeleteLinkRequest { link_slug }): Form<PostDeleteLinkRequest>,
 ) -> poem::Result<Redirect> {
     let mut db = db_pool.acquire().await.unwrap();
 
-    let deleted_link = delete_link(&mut db, &link_slug).await.unwrap();
+    let platform_id = require_link_edit_permission(&mut db, user, &link_slug).await?;
+
+    let deleted_link = delete_link(&mut db, &platform_id, &link_slug)
+        .await
+        .unwrap();
+
+    link_cache.invalidate(&link_slug);
 
     match deleted_link {
         None => Err(poem::Error::from_string(
@@ -329,3 +672,231 @@ pub async fn post_delete_link(
         ))),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use poem::http::header;
+    use sqlx::PgPool;
+
+    use crate::{
+        common::{
+            csrf::CSRF_TOKEN_HEADER,
+            testing::app::{DashboardTestSession, api_test_client, dashboard_test_session},
+        },
+        db::dashboard_users::DashboardUserRole,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_parse_datetime_local_input() {
+        let expected = Utc.with_ymd_and_hms(2025, 6, 1, 10, 30, 0).unwrap();
+
+        assert_eq!(
+            parse_datetime_local_input("2025-06-01T10:30", None),
+            Some(expected)
+        );
+        // Browsers east of UTC have negative offsets
+        assert_eq!(
+            parse_datetime_local_input("2025-06-01T12:30", Some("-120")),
+            Some(expected)
+        );
+        assert_eq!(
+            parse_datetime_local_input("2025-06-01T05:30:00", Some("300")),
+            Some(expected)
+        );
+        assert_eq!(
+            parse_datetime_local_input("2025-06-01T10:30", Some("x")),
+            None
+        );
+        assert_eq!(parse_datetime_local_input("tomorrow", None), None);
+    }
+
+    #[sqlx::test]
+    async fn test_platform_permissions(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+        let (_, platform) = create_platform(&mut db, "Platform").await.unwrap();
+        let (_, other_platform) = create_platform(&mut db, "Other Platform").await.unwrap();
+        drop(db);
+
+        let cli = api_test_client(db_pool.clone());
+        let editor =
+            dashboard_test_session(&cli, &db_pool, DashboardUserRole::Editor, &[platform.id]).await;
+        let viewer =
+            dashboard_test_session(&cli, &db_pool, DashboardUserRole::Viewer, &[platform.id]).await;
+
+        let create_link = |session: &DashboardTestSession, platform_id: Uuid| {
+            cli.post("/admin/dashboard/create-link/")
+                .header(header::COOKIE, &session.cookie)
+                .header(CSRF_TOKEN_HEADER, &session.csrf_token)
+                .form(&[
+                    ("platform_id", platform_id.to_string()),
+                    ("url", "https://example.com".to_string()),
+                ])
+                .send()
+        };
+
+        // Users without access to a platform can't view it
+        for (session, platform_id, status) in [
+            (&editor, platform.id, StatusCode::OK),
+            (&editor, other_platform.id, StatusCode::FORBIDDEN),
+            (&viewer, platform.id, StatusCode::OK),
+        ] {
+            cli.get(format!("/admin/dashboard/?platform={platform_id}"))
+                .header(header::COOKIE, &session.cookie)
+                .send()
+                .await
+                .assert_status(status);
+        }
+
+        create_link(&editor, platform.id)
+            .await
+            .assert_status(StatusCode::SEE_OTHER);
+        create_link(&editor, other_platform.id)
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+        create_link(&viewer, platform.id)
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+
+        let mut db = db_pool.acquire().await.unwrap();
+        let link = get_links(&mut db, &platform.id).await.unwrap().remove(0);
+        drop(db);
+
+        for (session, status) in [
+            (&viewer, StatusCode::FORBIDDEN),
+            (&editor, StatusCode::SEE_OTHER),
+        ] {
+            cli.post("/admin/dashboard/delete-link/")
+                .header(header::COOKIE, &session.cookie)
+                .header(CSRF_TOKEN_HEADER, &session.csrf_token)
+                .form(&[("link_slug", &link.slug)])
+                .send()
+                .await
+                .assert_status(status);
+        }
+
+        // Only owners can manage platforms
+        cli.post("/admin/dashboard/delete-platform/")
+            .header(header::COOKIE, &editor.cookie)
+            .header(CSRF_TOKEN_HEADER, &editor.csrf_token)
+            .form(&[("platform_id", platform.id.to_string())])
+            .send()
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+    }
+
+    #[sqlx::test]
+    async fn test_csrf_token_required(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+        let (_, platform) = create_platform(&mut db, "Platform").await.unwrap();
+        drop(db);
+
+        let cli = api_test_client(db_pool.clone());
+        let owner = dashboard_test_session(&cli, &db_pool, DashboardUserRole::Owner, &[]).await;
+        let other_owner =
+            dashboard_test_session(&cli, &db_pool, DashboardUserRole::Owner, &[]).await;
+
+        // The token is embedded in the dashboard's forms
+        let resp = cli
+            .get("/admin/dashboard/")
+            .header(header::COOKIE, &owner.cookie)
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        assert!(
+            resp.0
+                .into_body()
+                .into_string()
+                .await
+                .unwrap()
+                .contains(&format!(r#"value="{}""#, owner.csrf_token))
+        );
+
+        let delete_platform = |csrf_token: Option<&str>| {
+            let mut form = vec![("platform_id", platform.id.to_string())];
+            if let Some(csrf_token) = csrf_token {
+                form.push(("csrf_token", csrf_token.to_string()));
+            }
+
+            cli.post("/admin/dashboard/delete-platform/")
+                .header(header::COOKIE, &owner.cookie)
+                .form(&form)
+                .send()
+        };
+
+        for csrf_token in [None, Some("wrong"), Some(other_owner.csrf_token.as_str())] {
+            delete_platform(csrf_token)
+                .await
+                .assert_status(StatusCode::FORBIDDEN);
+        }
+
+        let mut db = db_pool.acquire().await.unwrap();
+        assert!(get_platform(&mut db, &platform.id).await.unwrap().is_some());
+        drop(db);
+
+        delete_platform(Some(&owner.csrf_token))
+            .await
+            .assert_status(StatusCode::SEE_OTHER);
+    }
+
+    #[sqlx::test]
+    async fn test_update_link_limits(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+        let (_, platform) = create_platform(&mut db, "Platform").await.unwrap();
+        let link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://example.com/".to_string(),
+            None,
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+        drop(db);
+
+        let cli = api_test_client(db_pool.clone());
+        let editor =
+            dashboard_test_session(&cli, &db_pool, DashboardUserRole::Editor, &[platform.id]).await;
+
+        let update_link_limits = |expires_at: &str, timezone_offset: &str, max_visits: &str| {
+            cli.post("/admin/dashboard/update-link-limits/")
+                .header(header::COOKIE, &editor.cookie)
+                .header(CSRF_TOKEN_HEADER, &editor.csrf_token)
+                .form(&[
+                    ("link_slug", link.slug.as_str()),
+                    ("expires_at", expires_at),
+                    ("timezone_offset", timezone_offset),
+                    ("max_visits", max_visits),
+                ])
+                .send()
+        };
+
+        update_link_limits("2099-06-01T12:30", "-120", "5")
+            .await
+            .assert_status(StatusCode::SEE_OTHER);
+
+        let mut db = db_pool.acquire().await.unwrap();
+        let updated_link = get_link(&mut db, &link.slug).await.unwrap().unwrap();
+        assert_eq!(
+            updated_link.expires_at,
+            Some(Utc.with_ymd_and_hms(2099, 6, 1, 10, 30, 0).unwrap())
+        );
+        assert_eq!(updated_link.max_visits, Some(5));
+
+        update_link_limits("", "", "0")
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+
+        // Empty inputs remove the limits
+        update_link_limits("", "", "")
+            .await
+            .assert_status(StatusCode::SEE_OTHER);
+
+        let updated_link = get_link(&mut db, &link.slug).await.unwrap().unwrap();
+        assert_eq!(updated_link.expires_at, None);
+        assert_eq!(updated_link.max_visits, None);
+    }
+}
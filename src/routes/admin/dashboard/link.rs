@@ -0,0 +1,278 @@
+use askama::Template;
+use chrono::{TimeDelta, Utc};
+use poem::{
+    EndpointExt, Response,
+    endpoint::DynEndpoint,
+    get,
+    http::StatusCode,
+    web::{Data, Html, Path, Query},
+};
+use serde::Deserialize;
+use serde_valid::json::ToJsonString;
+
+use crate::{
+    common::dashboard_auth::{dashboard_auth_middleware, require_dashboard_permission},
+    db::{
+        dashboard_users::DashboardUser,
+        link_stats::{
+            StatsBucket, TopValuesKind, VisitTotals, VisitsBucket, VisitsScope, get_top_values,
+            get_visit_totals, get_visits_time_series,
+        },
+        link_visits::{LinkVisit, get_recent_link_visits},
+        links::{Link, get_link},
+        platforms::{Platform, get_platform},
+    },
+};
+
+pub fn routes() -> Box<dyn DynEndpoint<Output = Response>> {
+    poem::Route::new()
+        .at("/:slug/", get(get_view))
+        .around(dashboard_auth_middleware)
+        .boxed()
+}
+
+const CHART_WIDTH: f64 = 800.0;
+const CHART_HEIGHT: f64 = 200.0;
+const TOP_VALUES_LIMIT: i64 = 10;
+const RECENT_VISITS_LIMIT: i64 = 50;
+
+struct ChartBar {
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+    /// Shown when hovering over the bar
+    title: String,
+}
+
+/// Bar chart of visits over time, rendered as an SVG by the template
+struct VisitsChart {
+    bars: Vec<ChartBar>,
+    max_visits: i64,
+    first_label: String,
+    last_label: String,
+}
+
+impl VisitsChart {
+    fn new(buckets: &[VisitsBucket], bucket: StatsBucket) -> Self {
+        let label_format = match bucket {
+            StatsBucket::Hour => "%m-%d %H:00",
+            StatsBucket::Day | StatsBucket::Week => "%Y-%m-%d",
+        };
+
+        let max_visits = buckets.iter().map(|b| b.visits).max().unwrap_or(0);
+        let slot_width = CHART_WIDTH / buckets.len().max(1) as f64;
+
+        let bars = buckets
+            .iter()
+            .enumerate()
+            .map(|(i, b)| {
+                let height = CHART_HEIGHT * b.visits as f64 / max_visits.max(1) as f64;
+
+                ChartBar {
+                    x: i as f64 * slot_width + slot_width * 0.1,
+                    y: CHART_HEIGHT - height,
+                    width: slot_width * 0.8,
+                    height,
+                    title: format!(
+                        "{} UTC: {} visits ({} unique)",
+                        b.start.format(label_format),
+                        b.visits,
+                        b.unique_visits
+                    ),
+                }
+            })
+            .collect();
+
+        let label = |b: Option<&VisitsBucket>| {
+            b.map(|b| b.start.format(label_format).to_string())
+                .unwrap_or_default()
+        };
+
+        VisitsChart {
+            bars,
+            max_visits,
+            first_label: label(buckets.first()),
+            last_label: label(buckets.last()),
+        }
+    }
+}
+
+/// One of the most common values of a kind, like a referrer domain or browser
+struct ValueRow {
+    value: String,
+    visits: i64,
+    /// Share of all visits in the period
+    percent: f64,
+}
+
+/// Breakdown of visits by a kind of value, shown as a table
+struct ValuesTable {
+    title: &'static str,
+    column: &'static str,
+    rows: Vec<ValueRow>,
+}
+
+#[derive(Deserialize)]
+pub struct LinkViewQueryParams {
+    #[serde(default)]
+    bucket: StatsBucket,
+    #[serde(default)]
+    include_bots: bool,
+}
+
+#[derive(askama::Template)]
+#[template(path = "views/admin/dashboard/link.html")]
+struct LinkViewTemplate<'a> {
+    link: &'a Link,
+    platform: &'a Platform,
+    bucket: StatsBucket,
+    include_bots: bool,
+    totals: &'a VisitTotals,
+    chart: &'a VisitsChart,
+    values_tables: &'a Vec<ValuesTable>,
+    recent_visits: &'a Vec<LinkVisit>,
+}
+
+/// How far back the chart goes for each bucket size
+fn stats_period(bucket: StatsBucket) -> TimeDelta {
+    match bucket {
+        StatsBucket::Hour => TimeDelta::hours(48),
+        StatsBucket::Day => TimeDelta::days(30),
+        StatsBucket::Week => TimeDelta::weeks(26),
+    }
+}
+
+#[poem::handler]
+pub async fn get_view(
+    db_pool: Data<&sqlx::PgPool>,
+    Data(user): Data<&DashboardUser>,
+    Path((slug,)): Path<(String,)>,
+    Query(LinkViewQueryParams {
+        bucket,
+        include_bots,
+    }): Query<LinkViewQueryParams>,
+) -> poem::Result<Html<String>> {
+    let mut db = db_pool.acquire().await.unwrap();
+
+    let link = get_link(&mut db, &slug).await.unwrap().ok_or_else(|| {
+        poem::Error::from_string(
+            "Link for specified slug does not exist",
+            StatusCode::NOT_FOUND,
+        )
+    })?;
+
+    require_dashboard_permission(user.can_view_platform(&link.platform_id))?;
+
+    let platform = get_platform(&mut db, &link.platform_id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let to = Utc::now();
+    let scope = VisitsScope {
+        platform_id: platform.id,
+        link_slug: Some(link.slug.clone()),
+        from: to - stats_period(bucket),
+        to,
+        include_bots,
+    };
+
+    let totals = get_visit_totals(&mut db, &scope).await.unwrap();
+
+    let time_series = get_visits_time_series(&mut db, &scope, bucket)
+        .await
+        .unwrap();
+
+    let mut values_tables = vec![];
+    for (kind, title, column) in [
+        (
+            TopValuesKind::ReferrerDomain,
+            "Top referrer domains",
+            "Domain",
+        ),
+        (TopValuesKind::Browser, "Browsers", "Browser"),
+        (TopValuesKind::Os, "Operating systems", "Operating system"),
+        (TopValuesKind::DeviceType, "Device types", "Device type"),
+        (TopValuesKind::Country, "Countries", "Country"),
+        (TopValuesKind::City, "Cities", "City"),
+    ] {
+        let rows = get_top_values(&mut db, &scope, kind, TOP_VALUES_LIMIT)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|v| ValueRow {
+                percent: 100.0 * v.visits as f64 / totals.visits.max(1) as f64,
+                value: v.value,
+                visits: v.visits,
+            })
+            .collect();
+
+        values_tables.push(ValuesTable {
+            title,
+            column,
+            rows,
+        });
+    }
+
+    let recent_visits = get_recent_link_visits(&mut db, &link.slug, RECENT_VISITS_LIMIT)
+        .await
+        .unwrap();
+
+    Ok(Html(
+        LinkViewTemplate {
+            link: &link,
+            platform: &platform,
+            bucket,
+            include_bots,
+            totals: &totals,
+            chart: &VisitsChart::new(&time_series, bucket),
+            values_tables: &values_tables,
+            recent_visits: &recent_visits,
+        }
+        .render()
+        .unwrap(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_visits_chart() {
+        let start = Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap();
+        let buckets = [4, 0, 2, 1]
+            .into_iter()
+            .enumerate()
+            .map(|(i, visits)| VisitsBucket {
+                start: start + TimeDelta::days(i as i64),
+                visits,
+                unique_visits: visits,
+            })
+            .collect::<Vec<_>>();
+
+        let chart = VisitsChart::new(&buckets, StatsBucket::Day);
+
+        assert_eq!(chart.max_visits, 4);
+        assert_eq!(chart.first_label, "2025-01-01");
+        assert_eq!(chart.last_label, "2025-01-04");
+        assert_eq!(
+            chart.bars.iter().map(|b| b.height).collect::<Vec<_>>(),
+            vec![CHART_HEIGHT, 0.0, CHART_HEIGHT / 2.0, CHART_HEIGHT / 4.0]
+        );
+        assert_eq!(chart.bars[1].x, CHART_WIDTH / 4.0 + CHART_WIDTH / 40.0);
+        assert_eq!(chart.bars[2].title, "2025-01-03 UTC: 2 visits (2 unique)");
+    }
+
+    #[test]
+    fn test_visits_chart_no_buckets() {
+        let chart = VisitsChart::new(&[], StatsBucket::Hour);
+
+        assert_eq!(chart.max_visits, 0);
+        assert!(chart.bars.is_empty());
+        assert_eq!(chart.first_label, "");
+    }
+}
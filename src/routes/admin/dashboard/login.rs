@@ -1,36 +1,169 @@
 use askama::Template;
+use chrono::{DateTime, Utc};
 use poem::{
-    Body, Response, get,
+    Body, IntoResponse, Response, get,
+    http::{HeaderMap, StatusCode, header},
     session::Session,
     web::{Data, Form, Html, Redirect},
 };
 use serde::Deserialize;
+use sqlx::PgConnection;
+use uuid::Uuid;
 
-use crate::common::dashboard_auth::attempt_log_in_dashboard_session;
+use crate::{
+    common::{
+        client_ip::ClientIp,
+        csrf::CsrfToken,
+        dashboard_auth::{
+            DashboardLoginAttempt, DashboardLoginClient,
+            attempt_complete_dashboard_login_challenge, attempt_log_in_dashboard_session,
+            get_dashboard_login_challenge_user, log_out_dashboard_session,
+        },
+        login_throttle::{
+            LoginAttemptReservation, cancel_login_attempt, format_wait, get_login_locked_until,
+            login_attempt_succeeded, login_throttle_ip_address, reserve_login_attempt,
+        },
+    },
+    config::CONFIG,
+};
 
 pub fn routes() -> poem::Route {
-    poem::Route::new().at("", get(get_login).post(post_login))
+    poem::Route::new()
+        .at("", get(get_login).post(post_login))
+        .at("/verify/", get(get_verify).post(post_verify))
+}
+
+/// Longer user agents are cut off before being stored with login tokens
+const MAX_STORED_USER_AGENT_LENGTH: usize = 512;
+
+fn login_user_agent(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(|user_agent| {
+            user_agent
+                .chars()
+                .take(MAX_STORED_USER_AGENT_LENGTH)
+                .collect()
+        })
 }
 
 #[derive(askama::Template)]
 #[template(path = "views/admin/dashboard/login.html")]
-struct LoginViewTemplate {
-    password_was_wrong: bool,
+struct LoginViewTemplate<'a> {
+    /// Whether to ask for the second factor instead of the username and password
+    challenge: bool,
+    csrf_token: &'a str,
+    error: Option<String>,
 }
 
 #[poem::handler]
-pub async fn get_login() -> Html<String> {
+pub async fn get_login(Data(csrf_token): Data<&CsrfToken>) -> Html<String> {
     Html(
         LoginViewTemplate {
-            password_was_wrong: false,
+            challenge: false,
+            csrf_token: &csrf_token.0,
+            error: None,
+        }
+        .render()
+        .unwrap(),
+    )
+}
+
+fn login_error_body(challenge: bool, csrf_token: &str, error: String) -> Body {
+    Body::from_string(
+        LoginViewTemplate {
+            challenge,
+            csrf_token,
+            error: Some(error),
         }
         .render()
         .unwrap(),
     )
 }
 
+fn locked_out_error(
+    challenge: bool,
+    csrf_token: &str,
+    locked_until: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> poem::Error {
+    let wait = locked_until - now;
+
+    poem::Error::from_response(
+        Response::builder()
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .header(
+                header::RETRY_AFTER,
+                ((wait.num_milliseconds() + 999) / 1000).to_string(),
+            )
+            .content_type("text/html")
+            .body(login_error_body(
+                challenge,
+                csrf_token,
+                format!(
+                    "Too many failed login attempts, please try again in {}.",
+                    format_wait(wait)
+                ),
+            )),
+    )
+}
+
+/// Reserves the attempt, rejecting it if the IP address is locked out. Locked out attempts aren't
+/// recorded, so retrying while locked out doesn't extend the lockout
+async fn reserve_login_attempt_or_reject(
+    db: &mut PgConnection,
+    challenge: bool,
+    csrf_token: &str,
+    ip_address: Option<&str>,
+    username: &str,
+    now: DateTime<Utc>,
+) -> poem::Result<Uuid> {
+    match reserve_login_attempt(
+        db,
+        ip_address,
+        username,
+        now,
+        &CONFIG.dashboard_login_throttle,
+    )
+    .await
+    .unwrap()
+    {
+        LoginAttemptReservation::Reserved(attempt_id) => Ok(attempt_id),
+        LoginAttemptReservation::LockedOut { until } => {
+            Err(locked_out_error(challenge, csrf_token, until, now))
+        }
+    }
+}
+
+/// Returns the error page for the failed attempt, which was recorded when it was reserved, or the
+/// lockout it caused
+async fn login_failed(
+    db: &mut PgConnection,
+    challenge: bool,
+    csrf_token: &str,
+    ip_address: Option<&str>,
+    now: DateTime<Utc>,
+    error: &str,
+) -> poem::Error {
+    if let Some(locked_until) =
+        get_login_locked_until(db, ip_address, now, &CONFIG.dashboard_login_throttle)
+            .await
+            .unwrap()
+    {
+        return locked_out_error(challenge, csrf_token, locked_until, now);
+    }
+
+    poem::Error::from_response(
+        Response::builder()
+            .content_type("text/html")
+            .body(login_error_body(challenge, csrf_token, error.to_string())),
+    )
+}
+
 #[derive(Deserialize)]
 pub struct PostLoginRequest {
+    username: String,
     password: String,
 }
 
@@ -38,26 +171,437 @@ pub struct PostLoginRequest {
 pub async fn post_login(
     session: &Session,
     Data(db_pool): Data<&sqlx::PgPool>,
-    Form(PostLoginRequest { password }): Form<PostLoginRequest>,
+    ClientIp(client_ip): ClientIp,
+    headers: &HeaderMap,
+    Data(csrf_token): Data<&CsrfToken>,
+    Form(PostLoginRequest { username, password }): Form<PostLoginRequest>,
 ) -> poem::Result<Redirect> {
     let mut db = db_pool.acquire().await.unwrap();
 
-    if !attempt_log_in_dashboard_session(&mut db, session, &password)
+    let ip_address = client_ip.map(|ip| ip.to_string());
+    let throttle_ip_address = client_ip.map(login_throttle_ip_address);
+    let user_agent = login_user_agent(headers);
+    let now = Utc::now();
+
+    let attempt_id = reserve_login_attempt_or_reject(
+        &mut db,
+        false,
+        &csrf_token.0,
+        throttle_ip_address.as_deref(),
+        &username,
+        now,
+    )
+    .await?;
+
+    match attempt_log_in_dashboard_session(
+        &mut db,
+        session,
+        &username,
+        &password,
+        DashboardLoginClient {
+            ip_address: ip_address.as_deref(),
+            user_agent: user_agent.as_deref(),
+        },
+    )
+    .await
+    .unwrap()
+    {
+        DashboardLoginAttempt::WrongCredentials => Err(login_failed(
+            &mut db,
+            false,
+            &csrf_token.0,
+            throttle_ip_address.as_deref(),
+            now,
+            "Incorrect username or password, please try again.",
+        )
+        .await),
+        // Whether the login succeeded is recorded once the second factor has been checked
+        DashboardLoginAttempt::ChallengeRequired => {
+            cancel_login_attempt(&mut db, &attempt_id).await.unwrap();
+
+            Ok(Redirect::see_other("/admin/dashboard/login/verify/"))
+        }
+        DashboardLoginAttempt::LoggedIn => {
+            login_attempt_succeeded(&mut db, &attempt_id).await.unwrap();
+
+            Ok(Redirect::see_other("/admin/dashboard/"))
+        }
+    }
+}
+
+#[poem::handler]
+pub async fn get_verify(
+    session: &Session,
+    Data(db_pool): Data<&sqlx::PgPool>,
+    Data(csrf_token): Data<&CsrfToken>,
+) -> poem::Result<Html<String>> {
+    let mut db = db_pool.acquire().await.unwrap();
+
+    if get_dashboard_login_challenge_user(&mut db, session)
         .await
         .unwrap()
+        .is_none()
     {
         return Err(poem::Error::from_response(
-            Response::builder()
-                .content_type("text/html")
-                .body(Body::from_string(
-                    LoginViewTemplate {
-                        password_was_wrong: true,
-                    }
-                    .render()
-                    .unwrap(),
-                )),
+            Redirect::see_other("/admin/dashboard/login/").into_response(),
         ));
     }
 
-    Ok(Redirect::see_other("/admin/dashboard/"))
+    Ok(Html(
+        LoginViewTemplate {
+            challenge: true,
+            csrf_token: &csrf_token.0,
+            error: None,
+        }
+        .render()
+        .unwrap(),
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct PostVerifyRequest {
+    /// A TOTP code or one of the user's recovery codes
+    code: String,
+}
+
+#[poem::handler]
+pub async fn post_verify(
+    session: &Session,
+    Data(db_pool): Data<&sqlx::PgPool>,
+    ClientIp(client_ip): ClientIp,
+    headers: &HeaderMap,
+    Data(csrf_token): Data<&CsrfToken>,
+    Form(PostVerifyRequest { code }): Form<PostVerifyRequest>,
+) -> poem::Result<Redirect> {
+    let mut db = db_pool.acquire().await.unwrap();
+
+    let ip_address = client_ip.map(|ip| ip.to_string());
+    let throttle_ip_address = client_ip.map(login_throttle_ip_address);
+    let user_agent = login_user_agent(headers);
+    let now = Utc::now();
+
+    // The challenge expired, so the password has to be entered again
+    let Some(user) = get_dashboard_login_challenge_user(&mut db, session)
+        .await
+        .unwrap()
+    else {
+        return Ok(Redirect::see_other("/admin/dashboard/login/"));
+    };
+
+    let attempt_id = reserve_login_attempt_or_reject(
+        &mut db,
+        true,
+        &csrf_token.0,
+        throttle_ip_address.as_deref(),
+        &user.username,
+        now,
+    )
+    .await?;
+
+    match attempt_complete_dashboard_login_challenge(
+        &mut db,
+        session,
+        &code,
+        DashboardLoginClient {
+            ip_address: ip_address.as_deref(),
+            user_agent: user_agent.as_deref(),
+        },
+    )
+    .await
+    .unwrap()
+    {
+        // The challenge expired since it was checked above
+        None => {
+            cancel_login_attempt(&mut db, &attempt_id).await.unwrap();
+
+            Ok(Redirect::see_other("/admin/dashboard/login/"))
+        }
+        Some(false) => Err(login_failed(
+            &mut db,
+            true,
+            &csrf_token.0,
+            throttle_ip_address.as_deref(),
+            now,
+            "Incorrect code, please try again.",
+        )
+        .await),
+        Some(true) => {
+            login_attempt_succeeded(&mut db, &attempt_id).await.unwrap();
+
+            Ok(Redirect::see_other("/admin/dashboard/"))
+        }
+    }
+}
+
+/// Logs out of the session, which works even if it has already expired or been revoked
+#[poem::handler]
+pub async fn post_logout(session: &Session, Data(db_pool): Data<&sqlx::PgPool>) -> Redirect {
+    let mut db = db_pool.acquire().await.unwrap();
+
+    log_out_dashboard_session(&mut db, session).await.unwrap();
+
+    Redirect::see_other("/admin/dashboard/login/")
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::PgPool;
+
+    use crate::{
+        common::{
+            argon2::{argon2_hash_key, setup_weak_argon2},
+            csrf::CSRF_TOKEN_HEADER,
+            dashboard_auth::hash_recovery_codes,
+            testing::app::{
+                DashboardTestSession, api_test_client, dashboard_login_page_session,
+                response_cookie,
+            },
+            totp::current_totp_code,
+        },
+        db::{
+            dashboard_recovery_codes::replace_dashboard_recovery_codes,
+            dashboard_users::{
+                DashboardUserRole, create_dashboard_user, enable_dashboard_user_totp,
+                set_dashboard_user_totp_secret,
+            },
+        },
+    };
+
+    use super::*;
+
+    #[sqlx::test]
+    async fn test_post_login_locked_out(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+        create_dashboard_user(
+            &mut db,
+            "admin",
+            &argon2_hash_key(&setup_weak_argon2(), "password"),
+            DashboardUserRole::Owner,
+        )
+        .await
+        .unwrap();
+        drop(db);
+
+        let cli = api_test_client(db_pool);
+
+        let session = dashboard_login_page_session(&cli).await;
+
+        let attempt_login = |ip: &'static str, password: &'static str| {
+            cli.post("/admin/dashboard/login/")
+                .header(header::COOKIE, &session.cookie)
+                .header(CSRF_TOKEN_HEADER, &session.csrf_token)
+                .header("x-forwarded-for", ip)
+                .form(&[("username", "admin"), ("password", password)])
+                .send()
+        };
+
+        for _ in 0..CONFIG.dashboard_login_throttle.free_attempts_per_ip {
+            let resp = attempt_login("1.1.1.1", "wrong").await;
+            resp.assert_status_is_ok();
+            assert!(
+                resp.0
+                    .into_body()
+                    .into_string()
+                    .await
+                    .unwrap()
+                    .contains("Incorrect username or password")
+            );
+        }
+
+        let resp = attempt_login("1.1.1.1", "wrong").await;
+        resp.assert_status(StatusCode::TOO_MANY_REQUESTS);
+        resp.assert_header(header::RETRY_AFTER, "30");
+        assert!(
+            resp.0
+                .into_body()
+                .into_string()
+                .await
+                .unwrap()
+                .contains("please try again in 30 seconds")
+        );
+
+        // Even the right password isn't checked while locked out
+        let resp = attempt_login("1.1.1.1", "password").await;
+        resp.assert_status(StatusCode::TOO_MANY_REQUESTS);
+
+        let resp = attempt_login("2.2.2.2", "password").await;
+        resp.assert_status(StatusCode::SEE_OTHER);
+    }
+
+    #[sqlx::test]
+    async fn test_post_login_other_user_doesnt_reset_lockout(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+        for username in ["admin", "viewer"] {
+            create_dashboard_user(
+                &mut db,
+                username,
+                &argon2_hash_key(&setup_weak_argon2(), "password"),
+                DashboardUserRole::Viewer,
+            )
+            .await
+            .unwrap();
+        }
+        drop(db);
+
+        let cli = api_test_client(db_pool);
+
+        let attempt_login = async |username: &str, password: &str| {
+            let session = dashboard_login_page_session(&cli).await;
+
+            cli.post("/admin/dashboard/login/")
+                .header(header::COOKIE, &session.cookie)
+                .header(CSRF_TOKEN_HEADER, &session.csrf_token)
+                .header("x-forwarded-for", "1.1.1.1")
+                .form(&[("username", username), ("password", password)])
+                .send()
+                .await
+        };
+
+        for _ in 0..CONFIG.dashboard_login_throttle.free_attempts_per_ip {
+            attempt_login("admin", "wrong").await.assert_status_is_ok();
+            // Logging in to their own account in between guesses doesn't help
+            attempt_login("viewer", "password")
+                .await
+                .assert_status(StatusCode::SEE_OTHER);
+        }
+
+        attempt_login("admin", "wrong")
+            .await
+            .assert_status(StatusCode::TOO_MANY_REQUESTS);
+        attempt_login("admin", "password")
+            .await
+            .assert_status(StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[sqlx::test]
+    async fn test_post_login_rotates_csrf_token(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+        create_dashboard_user(
+            &mut db,
+            "admin",
+            &argon2_hash_key(&setup_weak_argon2(), "password"),
+            DashboardUserRole::Owner,
+        )
+        .await
+        .unwrap();
+        drop(db);
+
+        let cli = api_test_client(db_pool);
+
+        let DashboardTestSession { cookie, csrf_token } = dashboard_login_page_session(&cli).await;
+
+        let resp = cli
+            .post("/admin/dashboard/login/")
+            .header(header::COOKIE, cookie)
+            .header(CSRF_TOKEN_HEADER, &csrf_token)
+            .form(&[("username", "admin"), ("password", "password")])
+            .send()
+            .await;
+        resp.assert_status(StatusCode::SEE_OTHER);
+        let cookie = response_cookie(&resp);
+
+        // The token from before logging in can't be used to log out
+        cli.post("/admin/dashboard/logout/")
+            .header(header::COOKIE, &cookie)
+            .header(CSRF_TOKEN_HEADER, &csrf_token)
+            .send()
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+    }
+
+    #[sqlx::test]
+    async fn test_post_login_with_totp(db_pool: PgPool) {
+        let secret = b"12345678901234567890";
+
+        let mut db = db_pool.acquire().await.unwrap();
+        let user = create_dashboard_user(
+            &mut db,
+            "admin",
+            &argon2_hash_key(&setup_weak_argon2(), "password"),
+            DashboardUserRole::Owner,
+        )
+        .await
+        .unwrap();
+        set_dashboard_user_totp_secret(&mut db, &user.id, Some(secret))
+            .await
+            .unwrap();
+        enable_dashboard_user_totp(&mut db, &user.id).await.unwrap();
+        replace_dashboard_recovery_codes(
+            &mut db,
+            &user.id,
+            &hash_recovery_codes(&["abcde-fghij".to_string()]),
+        )
+        .await
+        .unwrap();
+        drop(db);
+
+        let cli = api_test_client(db_pool);
+
+        // Returns the session, which only holds the challenge until the code is verified
+        let log_in = async || {
+            let DashboardTestSession { cookie, csrf_token } =
+                dashboard_login_page_session(&cli).await;
+
+            let resp = cli
+                .post("/admin/dashboard/login/")
+                .header(header::COOKIE, cookie)
+                .header(CSRF_TOKEN_HEADER, &csrf_token)
+                .form(&[("username", "admin"), ("password", "password")])
+                .send()
+                .await;
+            resp.assert_status(StatusCode::SEE_OTHER);
+            resp.assert_header(header::LOCATION, "/admin/dashboard/login/verify/");
+
+            DashboardTestSession {
+                cookie: response_cookie(&resp),
+                csrf_token,
+            }
+        };
+
+        let verify = async |session: &DashboardTestSession, code: &str| {
+            cli.post("/admin/dashboard/login/verify/")
+                .header(header::COOKIE, &session.cookie)
+                .header(CSRF_TOKEN_HEADER, &session.csrf_token)
+                .form(&[("code", code)])
+                .send()
+                .await
+        };
+
+        let session = log_in().await;
+
+        // The challenge alone doesn't give access to the dashboard
+        cli.get("/admin/dashboard/")
+            .header(header::COOKIE, &session.cookie)
+            .send()
+            .await
+            .assert_status(StatusCode::SEE_OTHER);
+
+        let resp = verify(&session, "000000").await;
+        resp.assert_status_is_ok();
+        assert!(
+            resp.0
+                .into_body()
+                .into_string()
+                .await
+                .unwrap()
+                .contains("Incorrect code")
+        );
+
+        let resp = verify(&session, "ABCDE-FGHIJ").await;
+        resp.assert_status(StatusCode::SEE_OTHER);
+        resp.assert_header(header::LOCATION, "/admin/dashboard/");
+
+        // Recovery codes can only be used once
+        let session = log_in().await;
+        verify(&session, "abcde-fghij").await.assert_status_is_ok();
+
+        let code = current_totp_code(secret);
+        verify(&session, &code)
+            .await
+            .assert_status(StatusCode::SEE_OTHER);
+
+        // And so can authenticator app codes
+        let session = log_in().await;
+        verify(&session, &code).await.assert_status_is_ok();
+    }
 }
@@ -3,19 +3,30 @@ use std::time::Duration;
 use poem::{
     EndpointExt, Response,
     endpoint::DynEndpoint,
+    post,
     session::{CookieConfig, CookieSession},
     web::cookie::SameSite,
 };
 
-use crate::config::CONFIG;
+use crate::{common::csrf::csrf_middleware, config::CONFIG};
 
+mod account;
 mod home;
+mod link;
 mod login;
+mod sessions;
+mod users;
 
 pub fn routes() -> Box<dyn DynEndpoint<Output = Response>> {
     poem::Route::new()
         .nest("", home::routes())
+        .nest("/account/", account::routes())
+        .nest("/links/", link::routes())
         .nest("/login/", login::routes())
+        .at("/logout/", post(login::post_logout))
+        .nest("/sessions/", sessions::routes())
+        .nest("/users/", users::routes())
+        .around(csrf_middleware)
         .with(CookieSession::new(
             CookieConfig::new()
                 .max_age(Some(Duration::from_secs(
@@ -0,0 +1,283 @@
+use askama::Template;
+use chrono::{DateTime, Utc};
+use poem::{
+    EndpointExt, Response,
+    endpoint::DynEndpoint,
+    get,
+    http::StatusCode,
+    post,
+    session::Session,
+    web::{Data, Form, Html, Redirect},
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    common::{
+        csrf::CsrfToken,
+        dashboard_auth::{DashboardSessionId, dashboard_auth_middleware},
+        user_agent::classify_user_agent,
+    },
+    db::{
+        dashboard_login_token::{
+            delete_dashboard_login_token, delete_user_dashboard_login_tokens,
+            get_user_dashboard_login_tokens,
+        },
+        dashboard_users::DashboardUser,
+    },
+};
+
+pub fn routes() -> Box<dyn DynEndpoint<Output = Response>> {
+    poem::Route::new()
+        .at("", get(get_view))
+        .at("/revoke/", post(post_revoke_session))
+        .at("/revoke-all/", post(post_revoke_all_sessions))
+        .around(dashboard_auth_middleware)
+        .boxed()
+}
+
+struct SessionRow {
+    id: Uuid,
+    created_at: DateTime<Utc>,
+    last_used_at: DateTime<Utc>,
+    ip_address: Option<String>,
+    user_agent: Option<String>,
+    /// Browser and OS from the user agent, e.g. "Firefox on Windows"
+    client: Option<String>,
+    is_current: bool,
+}
+
+#[derive(askama::Template)]
+#[template(path = "views/admin/dashboard/sessions.html")]
+struct SessionsViewTemplate<'a> {
+    csrf_token: &'a str,
+    sessions: Vec<SessionRow>,
+}
+
+fn describe_client(user_agent: &str) -> Option<String> {
+    let info = classify_user_agent(user_agent);
+
+    match (info.browser, info.os) {
+        (Some(browser), Some(os)) => Some(format!("{browser} on {os}")),
+        (browser, os) => browser.or(os),
+    }
+}
+
+#[poem::handler]
+pub async fn get_view(
+    db_pool: Data<&sqlx::PgPool>,
+    Data(user): Data<&DashboardUser>,
+    Data(current_session_id): Data<&DashboardSessionId>,
+    Data(csrf_token): Data<&CsrfToken>,
+) -> Html<String> {
+    let mut db = db_pool.acquire().await.unwrap();
+
+    let sessions = get_user_dashboard_login_tokens(&mut db, &user.id)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|token| SessionRow {
+            id: token.id,
+            created_at: token.created_at,
+            last_used_at: token.last_used_at,
+            client: token.user_agent.as_deref().and_then(describe_client),
+            ip_address: token.ip_address,
+            user_agent: token.user_agent,
+            is_current: token.id == current_session_id.0,
+        })
+        .collect();
+
+    Html(
+        SessionsViewTemplate {
+            csrf_token: &csrf_token.0,
+            sessions,
+        }
+        .render()
+        .unwrap(),
+    )
+}
+
+#[derive(Deserialize)]
+pub struct PostRevokeSessionRequest {
+    session_id: Uuid,
+}
+
+/// Logs out one of the user's sessions, going to the login page if it's the current one
+#[poem::handler]
+pub async fn post_revoke_session(
+    session: &Session,
+    db_pool: Data<&sqlx::PgPool>,
+    Data(user): Data<&DashboardUser>,
+    Data(current_session_id): Data<&DashboardSessionId>,
+    Form(PostRevokeSessionRequest { session_id }): Form<PostRevokeSessionRequest>,
+) -> poem::Result<Redirect> {
+    let mut db = db_pool.acquire().await.unwrap();
+
+    if !delete_dashboard_login_token(&mut db, &session_id, &user.id)
+        .await
+        .unwrap()
+    {
+        return Err(poem::Error::from_status(StatusCode::BAD_REQUEST));
+    }
+
+    if session_id == current_session_id.0 {
+        session.purge();
+        return Ok(Redirect::see_other("/admin/dashboard/login/"));
+    }
+
+    Ok(Redirect::see_other("/admin/dashboard/sessions/"))
+}
+
+/// Logs the user out everywhere, including the current session
+#[poem::handler]
+pub async fn post_revoke_all_sessions(
+    session: &Session,
+    db_pool: Data<&sqlx::PgPool>,
+    Data(user): Data<&DashboardUser>,
+) -> Redirect {
+    let mut db = db_pool.acquire().await.unwrap();
+
+    delete_user_dashboard_login_tokens(&mut db, &user.id)
+        .await
+        .unwrap();
+    session.purge();
+
+    Redirect::see_other("/admin/dashboard/login/")
+}
+
+#[cfg(test)]
+mod tests {
+    use poem::http::header;
+    use sqlx::PgPool;
+
+    use crate::{
+        common::{
+            csrf::CSRF_TOKEN_HEADER,
+            testing::app::{api_test_client, dashboard_test_session},
+        },
+        db::dashboard_users::DashboardUserRole,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_describe_client() {
+        assert_eq!(
+            describe_client(
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:128.0) Gecko/20100101 Firefox/128.0"
+            )
+            .as_deref(),
+            Some("Firefox on Windows")
+        );
+        assert_eq!(describe_client("").as_deref(), None);
+    }
+
+    #[sqlx::test]
+    async fn test_logout_and_revoke_sessions(db_pool: PgPool) {
+        let cli = api_test_client(db_pool.clone());
+
+        let assert_logged_in = async |cookie: &str, logged_in: bool| {
+            let resp = cli
+                .get("/admin/dashboard/sessions/")
+                .header(header::COOKIE, cookie)
+                .send()
+                .await;
+
+            if logged_in {
+                resp.assert_status_is_ok();
+            } else {
+                resp.assert_status(StatusCode::SEE_OTHER);
+                resp.assert_header(header::LOCATION, "/admin/dashboard/login/");
+            }
+        };
+
+        // Logging out deletes the token, so a copy of the cookie can't be used either
+        let session = dashboard_test_session(&cli, &db_pool, DashboardUserRole::Owner, &[]).await;
+        assert_logged_in(&session.cookie, true).await;
+
+        cli.post("/admin/dashboard/logout/")
+            .header(header::COOKIE, &session.cookie)
+            .header(CSRF_TOKEN_HEADER, &session.csrf_token)
+            .send()
+            .await
+            .assert_status(StatusCode::SEE_OTHER);
+        assert_logged_in(&session.cookie, false).await;
+
+        // Another user's sessions can't be revoked
+        let other_session =
+            dashboard_test_session(&cli, &db_pool, DashboardUserRole::Viewer, &[]).await;
+        let session = dashboard_test_session(&cli, &db_pool, DashboardUserRole::Viewer, &[]).await;
+
+        let mut db = db_pool.acquire().await.unwrap();
+        let other_session_id = sqlx::query_scalar!(
+            "SELECT id FROM dashboard_login_tokens ORDER BY created_at LIMIT 1"
+        )
+        .fetch_one(&mut *db)
+        .await
+        .unwrap();
+        drop(db);
+
+        cli.post("/admin/dashboard/sessions/revoke/")
+            .header(header::COOKIE, &session.cookie)
+            .header(CSRF_TOKEN_HEADER, &session.csrf_token)
+            .form(&[("session_id", other_session_id.to_string())])
+            .send()
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+        assert_logged_in(&other_session.cookie, true).await;
+
+        cli.post("/admin/dashboard/sessions/revoke-all/")
+            .header(header::COOKIE, &session.cookie)
+            .header(CSRF_TOKEN_HEADER, &session.csrf_token)
+            .send()
+            .await
+            .assert_status(StatusCode::SEE_OTHER);
+        assert_logged_in(&session.cookie, false).await;
+        assert_logged_in(&other_session.cookie, true).await;
+    }
+
+    #[sqlx::test]
+    async fn test_sessions_expire_once_unused(db_pool: PgPool) {
+        let cli = api_test_client(db_pool.clone());
+        let session = dashboard_test_session(&cli, &db_pool, DashboardUserRole::Owner, &[]).await;
+
+        // Logged in before the expiry duration, but used recently
+        let mut db = db_pool.acquire().await.unwrap();
+        sqlx::query!(
+            r#"
+                UPDATE dashboard_login_tokens
+                SET created_at = NOW() - INTERVAL '1 day', last_used_at = NOW() - INTERVAL '5 minutes';
+            "#
+        )
+        .execute(&mut *db)
+        .await
+        .unwrap();
+
+        let resp = cli
+            .get("/admin/dashboard/sessions/")
+            .header(header::COOKIE, &session.cookie)
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        // The cookie is renewed along with the token
+        assert!(resp.0.headers().contains_key(header::SET_COOKIE));
+
+        let last_used_at =
+            sqlx::query_scalar!("SELECT last_used_at FROM dashboard_login_tokens LIMIT 1")
+                .fetch_one(&mut *db)
+                .await
+                .unwrap();
+        assert!(last_used_at > Utc::now() - chrono::TimeDelta::minutes(1));
+
+        sqlx::query!("UPDATE dashboard_login_tokens SET last_used_at = NOW() - INTERVAL '1 day'")
+            .execute(&mut *db)
+            .await
+            .unwrap();
+
+        cli.get("/admin/dashboard/sessions/")
+            .header(header::COOKIE, &session.cookie)
+            .send()
+            .await
+            .assert_status(StatusCode::SEE_OTHER);
+    }
+}
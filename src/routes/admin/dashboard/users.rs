@@ -0,0 +1,602 @@
+use askama::Template;
+use poem::{
+    EndpointExt, Response,
+    endpoint::DynEndpoint,
+    get,
+    http::StatusCode,
+    post,
+    web::{Data, Form, Html, Redirect},
+};
+use serde::Deserialize;
+use serde_valid::Validate;
+use uuid::Uuid;
+
+use crate::{
+    common::{
+        argon2::{argon2_hash_key, setup_strong_argon2},
+        csrf::CsrfToken,
+        dashboard_auth::{
+            dashboard_auth_middleware, require_dashboard_permission, validate_dashboard_password,
+            validate_dashboard_username,
+        },
+        validation::{validate_if_present, validate_to_poem_error},
+    },
+    db::{
+        dashboard_login_token::delete_user_dashboard_login_tokens,
+        dashboard_recovery_codes::replace_dashboard_recovery_codes,
+        dashboard_users::{
+            DashboardUser, DashboardUserRole, UpdateDashboardUserData, create_dashboard_user,
+            delete_dashboard_user, get_dashboard_user_by_username, get_dashboard_users,
+            grant_dashboard_user_platform, revoke_dashboard_user_platform,
+            set_dashboard_user_totp_secret, update_dashboard_user,
+        },
+        platforms::{Platform, get_platform, get_platforms},
+    },
+};
+
+pub fn routes() -> Box<dyn DynEndpoint<Output = Response>> {
+    poem::Route::new()
+        .at("", get(get_view))
+        .at("/create-user/", post(post_create_user))
+        .at("/update-user/", post(post_update_user))
+        .at("/delete-user/", post(post_delete_user))
+        .at("/reset-totp/", post(post_reset_totp))
+        .at("/grant-platform/", post(post_grant_platform))
+        .at("/revoke-platform/", post(post_revoke_platform))
+        .around(dashboard_auth_middleware)
+        .boxed()
+}
+
+struct UserRow<'a> {
+    user: DashboardUser,
+    /// Platforms the user has been given access to
+    platforms: Vec<&'a Platform>,
+    /// Platforms the user can still be given access to
+    other_platforms: Vec<&'a Platform>,
+}
+
+#[derive(askama::Template)]
+#[template(path = "views/admin/dashboard/users.html")]
+struct UsersViewTemplate<'a> {
+    current_user: &'a DashboardUser,
+    csrf_token: &'a str,
+    users: &'a Vec<UserRow<'a>>,
+}
+
+#[poem::handler]
+pub async fn get_view(
+    db_pool: Data<&sqlx::PgPool>,
+    Data(current_user): Data<&DashboardUser>,
+    Data(csrf_token): Data<&CsrfToken>,
+) -> poem::Result<Html<String>> {
+    require_dashboard_permission(current_user.is_owner())?;
+
+    let mut db = db_pool.acquire().await.unwrap();
+
+    let platforms = get_platforms(&mut db).await.unwrap();
+
+    let users = get_dashboard_users(&mut db)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|user| {
+            let (platforms, other_platforms) = platforms
+                .iter()
+                .partition(|platform| user.platform_ids.contains(&platform.id));
+
+            UserRow {
+                user,
+                platforms,
+                other_platforms,
+            }
+        })
+        .collect();
+
+    Ok(Html(
+        UsersViewTemplate {
+            current_user,
+            csrf_token: &csrf_token.0,
+            users: &users,
+        }
+        .render()
+        .unwrap(),
+    ))
+}
+
+fn users_redirect() -> Redirect {
+    Redirect::see_other("/admin/dashboard/users/")
+}
+
+#[derive(Validate, Deserialize)]
+pub struct PostCreateUserRequest {
+    #[validate(custom = validate_dashboard_username)]
+    username: String,
+
+    #[validate(custom = validate_dashboard_password)]
+    password: String,
+
+    role: DashboardUserRole,
+}
+
+#[poem::handler]
+pub async fn post_create_user(
+    db_pool: Data<&sqlx::PgPool>,
+    Data(current_user): Data<&DashboardUser>,
+    Form(create_user_request): Form<PostCreateUserRequest>,
+) -> poem::Result<Redirect> {
+    require_dashboard_permission(current_user.is_owner())?;
+
+    let PostCreateUserRequest {
+        username,
+        password,
+        role,
+    } = validate_to_poem_error(create_user_request)?;
+
+    let user_exists_error = || {
+        poem::Error::from_string(
+            format!("A user named {username} already exists"),
+            StatusCode::BAD_REQUEST,
+        )
+    };
+
+    let mut db = db_pool.acquire().await.unwrap();
+
+    if get_dashboard_user_by_username(&mut db, &username)
+        .await
+        .unwrap()
+        .is_some()
+    {
+        return Err(user_exists_error());
+    }
+
+    let password_hash = argon2_hash_key(&setup_strong_argon2(), &password);
+
+    match create_dashboard_user(&mut db, &username, &password_hash, role).await {
+        // The username was taken after it was checked above
+        Err(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
+            return Err(user_exists_error());
+        }
+        result => result.unwrap(),
+    };
+
+    Ok(users_redirect())
+}
+
+#[derive(Validate, Deserialize)]
+pub struct PostUpdateUserRequest {
+    user_id: Uuid,
+
+    role: DashboardUserRole,
+
+    /// Empty to keep the user's password
+    #[validate(custom = |password| validate_if_present(password, validate_dashboard_password))]
+    password: Option<String>,
+}
+
+#[poem::handler]
+pub async fn post_update_user(
+    db_pool: Data<&sqlx::PgPool>,
+    Data(current_user): Data<&DashboardUser>,
+    Form(mut update_user_request): Form<PostUpdateUserRequest>,
+) -> poem::Result<Redirect> {
+    require_dashboard_permission(current_user.is_owner())?;
+
+    update_user_request.password = update_user_request.password.filter(|p| !p.is_empty());
+
+    let PostUpdateUserRequest {
+        user_id,
+        role,
+        password,
+    } = validate_to_poem_error(update_user_request)?;
+
+    // Otherwise owners could lock everyone out of managing users
+    if user_id == current_user.id && role != current_user.role {
+        return Err(poem::Error::from_string(
+            "You can't change your own role",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let mut db = db_pool.begin().await.unwrap();
+
+    let password_changed = password.is_some();
+
+    let updated_user = update_dashboard_user(
+        &mut db,
+        &user_id,
+        &UpdateDashboardUserData {
+            password_hash: password
+                .map(|password| argon2_hash_key(&setup_strong_argon2(), &password)),
+            role: Some(role),
+        },
+    )
+    .await
+    .unwrap();
+
+    // Logs the user out everywhere, in case the password was changed because it was compromised
+    if password_changed {
+        delete_user_dashboard_login_tokens(&mut db, &user_id)
+            .await
+            .unwrap();
+    }
+
+    db.commit().await.unwrap();
+
+    match updated_user {
+        None => Err(poem::Error::from_status(StatusCode::BAD_REQUEST)),
+        Some(_) => Ok(users_redirect()),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PostDeleteUserRequest {
+    user_id: Uuid,
+}
+
+#[poem::handler]
+pub async fn post_delete_user(
+    db_pool: Data<&sqlx::PgPool>,
+    Data(current_user): Data<&DashboardUser>,
+    Form(PostDeleteUserRequest { user_id }): Form<PostDeleteUserRequest>,
+) -> poem::Result<Redirect> {
+    require_dashboard_permission(current_user.is_owner())?;
+
+    if user_id == current_user.id {
+        return Err(poem::Error::from_string(
+            "You can't delete yourself",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let mut db = db_pool.acquire().await.unwrap();
+
+    match delete_dashboard_user(&mut db, &user_id).await.unwrap() {
+        None => Err(poem::Error::from_status(StatusCode::BAD_REQUEST)),
+        Some(_) => Ok(users_redirect()),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PostResetTotpRequest {
+    user_id: Uuid,
+}
+
+/// Turns off two-factor authentication for a user who's lost their authenticator app and recovery
+/// codes
+#[poem::handler]
+pub async fn post_reset_totp(
+    db_pool: Data<&sqlx::PgPool>,
+    Data(current_user): Data<&DashboardUser>,
+    Form(PostResetTotpRequest { user_id }): Form<PostResetTotpRequest>,
+) -> poem::Result<Redirect> {
+    require_dashboard_permission(current_user.is_owner())?;
+
+    let mut db = db_pool.begin().await.unwrap();
+
+    set_dashboard_user_totp_secret(&mut db, &user_id, None)
+        .await
+        .unwrap();
+
+    replace_dashboard_recovery_codes(&mut db, &user_id, &[])
+        .await
+        .unwrap();
+
+    db.commit().await.unwrap();
+
+    Ok(users_redirect())
+}
+
+#[derive(Deserialize)]
+pub struct PostUserPlatformRequest {
+    user_id: Uuid,
+    platform_id: Uuid,
+}
+
+#[poem::handler]
+pub async fn post_grant_platform(
+    db_pool: Data<&sqlx::PgPool>,
+    Data(current_user): Data<&DashboardUser>,
+    Form(PostUserPlatformRequest {
+        user_id,
+        platform_id,
+    }): Form<PostUserPlatformRequest>,
+) -> poem::Result<Redirect> {
+    require_dashboard_permission(current_user.is_owner())?;
+
+    let mut db = db_pool.acquire().await.unwrap();
+
+    if get_platform(&mut db, &platform_id).await.unwrap().is_none() {
+        return Err(poem::Error::from_string(
+            format!("Can not find platform for ID: {platform_id:?}"),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    grant_dashboard_user_platform(&mut db, &user_id, &platform_id)
+        .await
+        .map_err(|_| poem::Error::from_status(StatusCode::BAD_REQUEST))?;
+
+    Ok(users_redirect())
+}
+
+#[poem::handler]
+pub async fn post_revoke_platform(
+    db_pool: Data<&sqlx::PgPool>,
+    Data(current_user): Data<&DashboardUser>,
+    Form(PostUserPlatformRequest {
+        user_id,
+        platform_id,
+    }): Form<PostUserPlatformRequest>,
+) -> poem::Result<Redirect> {
+    require_dashboard_permission(current_user.is_owner())?;
+
+    let mut db = db_pool.acquire().await.unwrap();
+
+    revoke_dashboard_user_platform(&mut db, &user_id, &platform_id)
+        .await
+        .unwrap();
+
+    Ok(users_redirect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use poem::http::header;
+    use sqlx::PgPool;
+
+    use crate::{
+        common::{
+            csrf::CSRF_TOKEN_HEADER,
+            testing::app::{api_test_client, dashboard_test_session},
+        },
+        db::{dashboard_users::get_dashboard_user, platforms::create_platform},
+    };
+
+    use super::*;
+
+    #[sqlx::test]
+    async fn test_users_require_owner(db_pool: PgPool) {
+        let cli = api_test_client(db_pool.clone());
+
+        for role in [DashboardUserRole::Editor, DashboardUserRole::Viewer] {
+            let session = dashboard_test_session(&cli, &db_pool, role, &[]).await;
+
+            cli.get("/admin/dashboard/users/")
+                .header(header::COOKIE, &session.cookie)
+                .send()
+                .await
+                .assert_status(StatusCode::FORBIDDEN);
+
+            cli.post("/admin/dashboard/users/create-user/")
+                .header(header::COOKIE, &session.cookie)
+                .header(CSRF_TOKEN_HEADER, &session.csrf_token)
+                .form(&[
+                    ("username", "sneaky"),
+                    ("password", "password"),
+                    ("role", "owner"),
+                ])
+                .send()
+                .await
+                .assert_status(StatusCode::FORBIDDEN);
+        }
+
+        let mut db = db_pool.acquire().await.unwrap();
+        assert!(
+            get_dashboard_user_by_username(&mut db, "sneaky")
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_manage_users(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+        let (_, platform) = create_platform(&mut db, "Platform").await.unwrap();
+        drop(db);
+
+        let cli = api_test_client(db_pool.clone());
+        let session = dashboard_test_session(&cli, &db_pool, DashboardUserRole::Owner, &[]).await;
+
+        cli.post("/admin/dashboard/users/create-user/")
+            .header(header::COOKIE, &session.cookie)
+            .header(CSRF_TOKEN_HEADER, &session.csrf_token)
+            .form(&[
+                ("username", "new.editor"),
+                ("password", "short"),
+                ("role", "editor"),
+            ])
+            .send()
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+
+        cli.post("/admin/dashboard/users/create-user/")
+            .header(header::COOKIE, &session.cookie)
+            .header(CSRF_TOKEN_HEADER, &session.csrf_token)
+            .form(&[
+                ("username", "new.editor"),
+                ("password", "password"),
+                ("role", "editor"),
+            ])
+            .send()
+            .await
+            .assert_status(StatusCode::SEE_OTHER);
+
+        let mut db = db_pool.acquire().await.unwrap();
+        let user = get_dashboard_user_by_username(&mut db, "new.editor")
+            .await
+            .unwrap()
+            .unwrap();
+        drop(db);
+        assert_eq!(user.role, DashboardUserRole::Editor);
+
+        cli.post("/admin/dashboard/users/grant-platform/")
+            .header(header::COOKIE, &session.cookie)
+            .header(CSRF_TOKEN_HEADER, &session.csrf_token)
+            .form(&[
+                ("user_id", user.id.to_string()),
+                ("platform_id", platform.id.to_string()),
+            ])
+            .send()
+            .await
+            .assert_status(StatusCode::SEE_OTHER);
+
+        cli.post("/admin/dashboard/users/update-user/")
+            .header(header::COOKIE, &session.cookie)
+            .header(CSRF_TOKEN_HEADER, &session.csrf_token)
+            .form(&[
+                ("user_id", user.id.to_string()),
+                ("role", "viewer".to_string()),
+                ("password", "".to_string()),
+            ])
+            .send()
+            .await
+            .assert_status(StatusCode::SEE_OTHER);
+
+        let mut db = db_pool.acquire().await.unwrap();
+        let updated_user = get_dashboard_user(&mut db, &user.id)
+            .await
+            .unwrap()
+            .unwrap();
+        drop(db);
+        assert_eq!(updated_user.role, DashboardUserRole::Viewer);
+        assert_eq!(updated_user.password_hash, user.password_hash);
+        assert_eq!(updated_user.platform_ids, vec![platform.id]);
+
+        let resp = cli
+            .get("/admin/dashboard/users/")
+            .header(header::COOKIE, &session.cookie)
+            .send()
+            .await;
+        resp.assert_status_is_ok();
+        assert!(
+            resp.0
+                .into_body()
+                .into_string()
+                .await
+                .unwrap()
+                .contains("new.editor")
+        );
+
+        cli.post("/admin/dashboard/users/delete-user/")
+            .header(header::COOKIE, &session.cookie)
+            .header(CSRF_TOKEN_HEADER, &session.csrf_token)
+            .form(&[("user_id", user.id.to_string())])
+            .send()
+            .await
+            .assert_status(StatusCode::SEE_OTHER);
+
+        let mut db = db_pool.acquire().await.unwrap();
+        assert!(
+            get_dashboard_user(&mut db, &user.id)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_create_user_taken_after_check(db_pool: PgPool) {
+        let cli = api_test_client(db_pool.clone());
+        let session = dashboard_test_session(&cli, &db_pool, DashboardUserRole::Owner, &[]).await;
+
+        // Another request creates the user after this one checks the username is free, but before
+        // it inserts the user
+        let mut other_request_tx = db_pool.begin().await.unwrap();
+        create_dashboard_user(
+            &mut other_request_tx,
+            "new.editor",
+            "hash",
+            DashboardUserRole::Editor,
+        )
+        .await
+        .unwrap();
+
+        let commit_once_insert_waits = async {
+            let mut db = db_pool.acquire().await.unwrap();
+
+            while !sqlx::query_scalar!(
+                r#"
+                    SELECT EXISTS(
+                        SELECT 1 FROM pg_stat_activity
+                        WHERE datname = current_database() AND wait_event_type = 'Lock'
+                    ) AS "waiting!";
+                "#
+            )
+            .fetch_one(&mut *db)
+            .await
+            .unwrap()
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+
+            other_request_tx.commit().await.unwrap();
+        };
+
+        let (resp, ()) = tokio::join!(
+            cli.post("/admin/dashboard/users/create-user/")
+                .header(header::COOKIE, &session.cookie)
+                .header(CSRF_TOKEN_HEADER, &session.csrf_token)
+                .form(&[
+                    ("username", "new.editor"),
+                    ("password", "password"),
+                    ("role", "editor"),
+                ])
+                .send(),
+            commit_once_insert_waits,
+        );
+
+        resp.assert_status(StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test]
+    async fn test_update_user_password_logs_them_out(db_pool: PgPool) {
+        let cli = api_test_client(db_pool.clone());
+        let owner = dashboard_test_session(&cli, &db_pool, DashboardUserRole::Owner, &[]).await;
+        let editor = dashboard_test_session(&cli, &db_pool, DashboardUserRole::Editor, &[]).await;
+
+        let mut db = db_pool.acquire().await.unwrap();
+        let editor_user = get_dashboard_users(&mut db)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|user| user.role == DashboardUserRole::Editor)
+            .unwrap();
+        drop(db);
+
+        let update_user = |password: &'static str| {
+            cli.post("/admin/dashboard/users/update-user/")
+                .header(header::COOKIE, &owner.cookie)
+                .header(CSRF_TOKEN_HEADER, &owner.csrf_token)
+                .form(&[
+                    ("user_id", editor_user.id.to_string()),
+                    ("role", "editor".to_string()),
+                    ("password", password.to_string()),
+                ])
+                .send()
+        };
+
+        let assert_editor_logged_in = async |logged_in: bool| {
+            cli.get("/admin/dashboard/")
+                .header(header::COOKIE, &editor.cookie)
+                .send()
+                .await
+                .assert_status(if logged_in {
+                    StatusCode::OK
+                } else {
+                    StatusCode::SEE_OTHER
+                });
+        };
+
+        // Sessions are only revoked if the password changes
+        update_user("").await.assert_status(StatusCode::SEE_OTHER);
+        assert_editor_logged_in(true).await;
+
+        update_user("new password")
+            .await
+            .assert_status(StatusCode::SEE_OTHER);
+        assert_editor_logged_in(false).await;
+    }
+}
@@ -1,60 +1,228 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, net::IpAddr};
 
+use askama::Template;
 use poem::{
-    http::{HeaderMap, StatusCode},
-    web::{Data, Path, RealIp, Redirect},
+    IntoResponse, Response,
+    http::{HeaderMap, StatusCode, header},
+    web::{Data, Path, Redirect},
 };
 
-use crate::db::{link_visits::create_link_visit, links::get_link};
+use crate::{
+    common::{
+        client_ip::ClientIp,
+        geoip::{GeoIp, VisitLocation},
+        link_cache::{CachedLink, LinkCache},
+        referrer::{VisitReferrer, parse_referrer},
+        user_agent::{UserAgentInfo, classify_user_agent},
+        visit_privacy::{anonymize_ip_address, filter_visit_headers, requests_no_tracking},
+        visit_recorder::VisitRecorder,
+    },
+    config::CONFIG,
+    db::{
+        link_visits::NewLinkVisit,
+        links::{RedirectType, claim_link_visit},
+        platforms::Platform,
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UnavailableReason {
+    NotFound,
+    /// The link's expiration date has passed or its visit limit has been reached
+    Expired,
+    Disabled,
+}
+
+impl UnavailableReason {
+    fn status(&self) -> StatusCode {
+        match self {
+            UnavailableReason::NotFound => StatusCode::NOT_FOUND,
+            UnavailableReason::Expired | UnavailableReason::Disabled => StatusCode::GONE,
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            UnavailableReason::NotFound => "Link Not Found",
+            UnavailableReason::Expired => "Link Expired",
+            UnavailableReason::Disabled => "Link Disabled",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            UnavailableReason::NotFound => {
+                "This link doesn't exist, double check that it was typed correctly."
+            }
+            UnavailableReason::Expired => "This link has expired and can no longer be visited.",
+            UnavailableReason::Disabled => "This link has been disabled by its owner.",
+        }
+    }
+}
+
+#[derive(askama::Template)]
+#[template(path = "views/link_unavailable.html")]
+struct LinkUnavailableTemplate {
+    reason: UnavailableReason,
+}
+
+/// Creates a visit containing only what the privacy settings allow to be stored
+fn private_link_visit(
+    slug: String,
+    headers: &HeaderMap,
+    remote_ip: Option<IpAddr>,
+    geoip: &GeoIp,
+) -> NewLinkVisit {
+    let settings = &CONFIG.visit_privacy;
+
+    let raw_user_agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok());
+    let user_agent = raw_user_agent.map(classify_user_agent).unwrap_or_default();
+
+    if settings.honour_do_not_track && requests_no_tracking(headers) {
+        // Whether it's a bot is still needed to keep bots out of the stats
+        return NewLinkVisit {
+            link_slug: slug,
+            at: chrono::Utc::now(),
+            headers: HashMap::new(),
+            ip_address: None,
+            user_agent: UserAgentInfo {
+                is_bot: user_agent.is_bot,
+                ..Default::default()
+            },
+            location: VisitLocation::default(),
+            referrer: VisitReferrer::default(),
+            visitor: None,
+        };
+    }
+
+    let filtered_headers = filter_visit_headers(headers, settings);
+
+    let mut referrer = headers
+        .get(header::REFERER)
+        .and_then(|value| value.to_str().ok())
+        .map(parse_referrer)
+        .unwrap_or_default();
+
+    // The domain is kept like the user agent's classification, but the full URL is only stored if
+    // the Referer header may be
+    if !filtered_headers.contains_key(header::REFERER.as_str()) {
+        referrer.url = None;
+    }
+
+    NewLinkVisit {
+        link_slug: slug,
+        at: chrono::Utc::now(),
+        headers: filtered_headers,
+        ip_address: remote_ip.and_then(|ip| anonymize_ip_address(ip, settings)),
+        user_agent,
+        location: remote_ip.map(|ip| geoip.locate(ip)).unwrap_or_default(),
+        referrer,
+        // Unique visitors are counted by IP address and user agent, regardless of how the IP
+        // address is stored
+        visitor: remote_ip.map(|ip| format!("{ip}\n{}", raw_user_agent.unwrap_or_default())),
+    }
+}
+
+/// Response for links which can't be visited, the platform's fallback takes priority over the
+/// globally configured fallback URL, which takes priority over the default page
+fn link_unavailable(platform: Option<&Platform>, reason: UnavailableReason) -> Response {
+    if let Some(platform) = platform {
+        if let Some(fallback_url) = &platform.unavailable_fallback_url {
+            return Redirect::temporary(fallback_url).into_response();
+        }
+
+        if let Some(fallback_html) = &platform.unavailable_fallback_html {
+            // Sandboxed into a unique origin, so scripts in it can't act on dashboard sessions
+            return Response::builder()
+                .status(reason.status())
+                .content_type("text/html; charset=utf-8")
+                .header(header::CONTENT_SECURITY_POLICY, "sandbox")
+                .body(fallback_html.clone());
+        }
+    }
+
+    if reason != UnavailableReason::NotFound
+        && let Some(fallback_url) = &CONFIG.link_unavailable_fallback_url
+    {
+        return Redirect::temporary(fallback_url).into_response();
+    }
+
+    Response::builder()
+        .status(reason.status())
+        .content_type("text/html; charset=utf-8")
+        .body(LinkUnavailableTemplate { reason }.render().unwrap())
+}
+
+fn redirect_response(redirect_type: RedirectType, url: &str) -> Response {
+    let status = match redirect_type {
+        RedirectType::MovedPermanently => StatusCode::MOVED_PERMANENTLY,
+        RedirectType::Found => StatusCode::FOUND,
+        RedirectType::TemporaryRedirect => StatusCode::TEMPORARY_REDIRECT,
+        RedirectType::PermanentRedirect => StatusCode::PERMANENT_REDIRECT,
+    };
+
+    Response::builder()
+        .status(status)
+        .header(header::LOCATION, url)
+        .finish()
+}
 
 #[poem::handler]
 pub async fn redirect(
     db: Data<&sqlx::PgPool>,
+    link_cache: Data<&LinkCache>,
+    visit_recorder: Data<&VisitRecorder>,
+    geoip: Data<&GeoIp>,
     Path((slug,)): Path<(String,)>,
-    RealIp(remote_ip): RealIp,
+    ClientIp(remote_ip): ClientIp,
     headers: &HeaderMap,
-) -> poem::Result<Redirect> {
-    let mut db = db.acquire().await.unwrap();
-
-    let Some(link) = get_link(&mut db, &slug).await.unwrap() else {
-        return Err(poem::Error::from_status(StatusCode::NOT_FOUND)); // TODO: Add nice 404 page + option for custom 404 page
+) -> poem::Result<Response> {
+    let Some(CachedLink { link, platform }) = link_cache.get_link(&db, &slug).await.unwrap() else {
+        return Ok(link_unavailable(None, UnavailableReason::NotFound));
     };
 
-    let remote_ip = remote_ip.map(|a| a.to_string());
+    let unavailable_reason = if link.disabled {
+        Some(UnavailableReason::Disabled)
+    // Only links with a visit limit need a connection, the rest skip the counter so they don't need
+    // to wait on a row lock either
+    } else if link.is_expired()
+        || (link.max_visits.is_some()
+            && !claim_link_visit(&mut db.acquire().await.unwrap(), &slug)
+                .await
+                .unwrap())
+    {
+        Some(UnavailableReason::Expired)
+    } else {
+        None
+    };
 
-    let mut header_hashmap = HashMap::<String, Vec<String>>::with_capacity(headers.keys_len());
-    for (header_name, header_value) in headers {
-        if let Ok(header_value) = header_value.to_str().map(|hv| hv.to_string()) {
-            let header_name = header_name.as_str().to_string();
-            header_hashmap
-                .entry(header_name.to_lowercase())
-                .or_default()
-                .push(header_value);
-        }
+    if let Some(unavailable_reason) = unavailable_reason {
+        return Ok(link_unavailable(Some(&platform), unavailable_reason));
     }
 
-    create_link_visit(
-        &mut db,
-        &slug,
-        header_hashmap,
-        match remote_ip {
-            Some(ref ip) => Some(ip.as_str()),
-            None => None,
-        },
-    )
-    .await
-    .unwrap();
+    visit_recorder.record(private_link_visit(slug, headers, remote_ip, &geoip));
+
+    let redirect_type = link.redirect_type.unwrap_or(platform.default_redirect_type);
 
-    Ok(Redirect::temporary(link.url))
+    Ok(redirect_response(redirect_type, &link.url))
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
     use sqlx::PgPool;
 
     use crate::{
-        common::testing::app::api_test_client,
-        db::{links::create_link, platforms::create_platform},
+        common::testing::app::{
+            api_test_client, api_test_client_with_visit_recorder, platform_auth_header,
+        },
+        db::{
+            links::{LinkSettings, UpdateLinkData, create_link, get_link, update_link},
+            platforms::{UpdatePlatformData, create_platform, update_platform},
+        },
     };
 
     use super::*;
@@ -70,6 +238,7 @@ mod tests {
             None,
             "https://example.com/".to_string(),
             None,
+            &LinkSettings::default(),
         )
         .await
         .unwrap();
@@ -78,6 +247,16 @@ mod tests {
         let response = api.get("/notit/").send().await;
 
         response.assert_status(StatusCode::NOT_FOUND);
+        response.assert_content_type("text/html; charset=utf-8");
+        response
+            .assert_text(
+                LinkUnavailableTemplate {
+                    reason: UnavailableReason::NotFound,
+                }
+                .render()
+                .unwrap(),
+            )
+            .await;
     }
 
     #[sqlx::test]
@@ -91,20 +270,30 @@ mod tests {
             None,
             "https://example.com/".to_string(),
             None,
+            &LinkSettings::default(),
         )
         .await
         .unwrap();
 
-        let api = api_test_client(db_pool);
+        let (api, visit_recorder_handle) = api_test_client_with_visit_recorder(db_pool);
         let response = api
             .get(format!("/{}/", link.slug))
             .header("X-Test-Header", "here is a test value")
+            .header("Cookie", "session=secret")
+            .header("X-Real-IP", "203.0.113.195")
+            .header(
+                "User-Agent",
+                "Mozilla/5.0 (X11; Linux x86_64; rv:127.0) Gecko/20100101 Firefox/127.0",
+            )
+            .header("Referer", "https://l.facebook.com/l.php?u=lonk")
             .send()
             .await;
 
         response.assert_status(StatusCode::TEMPORARY_REDIRECT);
         response.assert_header("Location", link.url);
 
+        visit_recorder_handle.shutdown().await;
+
         let link_visit = sqlx::query!("SELECT * from link_visits;")
             .fetch_one(&mut *db)
             .await
@@ -121,5 +310,433 @@ mod tests {
             stored_headers.1.as_array().unwrap()[0].as_str().unwrap(),
             "here is a test value"
         );
+        assert!(
+            !link_visit
+                .headers
+                .as_object()
+                .unwrap()
+                .contains_key("cookie")
+        );
+        assert_eq!(link_visit.browser.as_deref(), Some("Firefox"));
+        assert_eq!(link_visit.os.as_deref(), Some("Linux"));
+        assert_eq!(link_visit.device_type.as_deref(), Some("desktop"));
+        assert!(!link_visit.is_bot);
+        assert_eq!(link_visit.country.as_deref(), Some("GB"));
+        assert_eq!(link_visit.region.as_deref(), Some("England"));
+        assert_eq!(link_visit.city.as_deref(), Some("London"));
+        assert_eq!(
+            link_visit.referrer.as_deref(),
+            Some("https://l.facebook.com/l.php?u=lonk")
+        );
+        assert_eq!(link_visit.referrer_domain.as_deref(), Some("facebook.com"));
+        assert_eq!(link_visit.visitor_hash.map(|hash| hash.len()), Some(32));
+    }
+
+    #[sqlx::test]
+    async fn test_redirect_with_do_not_track(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (_, platform) = create_platform(&mut db, "sad").await.unwrap();
+        let link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://example.com/".to_string(),
+            None,
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+
+        let (api, visit_recorder_handle) = api_test_client_with_visit_recorder(db_pool);
+        let response = api
+            .get(format!("/{}/", link.slug))
+            .header("X-Test-Header", "here is a test value")
+            .header("X-Real-IP", "203.0.113.195")
+            .header("Sec-GPC", "1")
+            .header("User-Agent", "Slackbot-LinkExpanding 1.0")
+            .header("Referer", "https://example.com/")
+            .send()
+            .await;
+
+        response.assert_status(StatusCode::TEMPORARY_REDIRECT);
+
+        visit_recorder_handle.shutdown().await;
+
+        let link_visit = sqlx::query!("SELECT * from link_visits;")
+            .fetch_one(&mut *db)
+            .await
+            .unwrap();
+        assert_eq!(link_visit.link_slug, link.slug);
+        assert_eq!(link_visit.headers, serde_json::json!({}));
+        assert_eq!(link_visit.ip_address, None);
+        assert_eq!(link_visit.browser, None);
+        assert!(link_visit.is_bot);
+        assert_eq!(link_visit.country, None);
+        assert_eq!(link_visit.referrer_domain, None);
+        assert_eq!(link_visit.visitor_hash, None);
+    }
+
+    #[sqlx::test]
+    async fn test_redirect_but_link_expired(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (_, platform) = create_platform(&mut db, "sad").await.unwrap();
+        let link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://example.com/".to_string(),
+            None,
+            &LinkSettings {
+                expires_at: Some(chrono::Utc::now() - chrono::TimeDelta::minutes(1)),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let (api, visit_recorder_handle) = api_test_client_with_visit_recorder(db_pool);
+        let response = api.get(format!("/{}/", link.slug)).send().await;
+
+        response.assert_status(StatusCode::GONE);
+
+        visit_recorder_handle.shutdown().await;
+
+        let visit_count = sqlx::query!("SELECT COUNT(*) FROM link_visits")
+            .fetch_one(&mut *db)
+            .await
+            .unwrap();
+        assert_eq!(visit_count.count, Some(0));
+    }
+
+    #[sqlx::test]
+    async fn test_redirect_until_max_visits_reached(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (_, platform) = create_platform(&mut db, "sad").await.unwrap();
+        let link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://example.com/".to_string(),
+            None,
+            &LinkSettings {
+                max_visits: Some(2),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let (api, visit_recorder_handle) = api_test_client_with_visit_recorder(db_pool);
+
+        for expected_status in [
+            StatusCode::TEMPORARY_REDIRECT,
+            StatusCode::TEMPORARY_REDIRECT,
+            StatusCode::GONE,
+        ] {
+            let response = api.get(format!("/{}/", link.slug)).send().await;
+            response.assert_status(expected_status);
+        }
+
+        visit_recorder_handle.shutdown().await;
+
+        let visit_count = sqlx::query!("SELECT COUNT(*) FROM link_visits")
+            .fetch_one(&mut *db)
+            .await
+            .unwrap();
+        assert_eq!(visit_count.count, Some(2));
+    }
+
+    #[sqlx::test]
+    async fn test_redirect_concurrently_until_max_visits_reached(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (_, platform) = create_platform(&mut db, "sad").await.unwrap();
+        let link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://example.com/".to_string(),
+            None,
+            &LinkSettings {
+                max_visits: Some(5),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let api = Arc::new(api_test_client(db_pool.clone()));
+
+        let tasks = (0..20)
+            .map(|_| {
+                let api = api.clone();
+                let path = format!("/{}/", link.slug);
+
+                tokio::spawn(async move { api.get(path).send().await.0.status() })
+            })
+            .collect::<Vec<_>>();
+
+        let mut redirected_count = 0;
+        for task in tasks {
+            match task.await.unwrap() {
+                StatusCode::TEMPORARY_REDIRECT => redirected_count += 1,
+                status => assert_eq!(status, StatusCode::GONE),
+            }
+        }
+
+        assert_eq!(redirected_count, 5);
+        assert_eq!(
+            get_link(&mut db, &link.slug)
+                .await
+                .unwrap()
+                .unwrap()
+                .visit_count,
+            5
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_redirect_with_link_redirect_type(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (_, platform) = create_platform(&mut db, "sad").await.unwrap();
+
+        let api = api_test_client(db_pool);
+
+        for (redirect_type, expected_status) in [
+            (
+                RedirectType::MovedPermanently,
+                StatusCode::MOVED_PERMANENTLY,
+            ),
+            (RedirectType::Found, StatusCode::FOUND),
+            (
+                RedirectType::TemporaryRedirect,
+                StatusCode::TEMPORARY_REDIRECT,
+            ),
+            (
+                RedirectType::PermanentRedirect,
+                StatusCode::PERMANENT_REDIRECT,
+            ),
+        ] {
+            let link = create_link(
+                &mut db,
+                &platform.id,
+                None,
+                "https://example.com/".to_string(),
+                None,
+                &LinkSettings {
+                    redirect_type: Some(redirect_type),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+            let response = api.get(format!("/{}/", link.slug)).send().await;
+
+            response.assert_status(expected_status);
+            response.assert_header("Location", link.url);
+        }
+    }
+
+    #[sqlx::test]
+    async fn test_redirect_with_platform_default_redirect_type(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (_, platform) = create_platform(&mut db, "sad").await.unwrap();
+        update_platform(
+            &mut db,
+            &platform.id,
+            &UpdatePlatformData {
+                default_redirect_type: Some(RedirectType::MovedPermanently),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://example.com/".to_string(),
+            None,
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+        let overriding_link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://example.com/".to_string(),
+            None,
+            &LinkSettings {
+                redirect_type: Some(RedirectType::Found),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let api = api_test_client(db_pool);
+
+        let response = api.get(format!("/{}/", link.slug)).send().await;
+        response.assert_status(StatusCode::MOVED_PERMANENTLY);
+
+        let response = api.get(format!("/{}/", overriding_link.slug)).send().await;
+        response.assert_status(StatusCode::FOUND);
+    }
+
+    #[sqlx::test]
+    async fn test_redirect_but_link_disabled(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (_, platform) = create_platform(&mut db, "sad").await.unwrap();
+        let link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://example.com/".to_string(),
+            None,
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+        update_link(
+            &mut db,
+            &link.platform_id,
+            &link.slug,
+            &UpdateLinkData {
+                disabled: Some(true),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let api = api_test_client(db_pool);
+        let response = api.get(format!("/{}/", link.slug)).send().await;
+
+        response.assert_status(StatusCode::GONE);
+        response
+            .assert_text(
+                LinkUnavailableTemplate {
+                    reason: UnavailableReason::Disabled,
+                }
+                .render()
+                .unwrap(),
+            )
+            .await;
+    }
+
+    #[sqlx::test]
+    async fn test_redirect_but_link_expired_with_platform_fallback(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (_, url_platform) = create_platform(&mut db, "url").await.unwrap();
+        update_platform(
+            &mut db,
+            &url_platform.id,
+            &UpdatePlatformData {
+                unavailable_fallback_url: Some(Some("https://example.com/gone".to_string())),
+                unavailable_fallback_html: Some(Some("<h1>Ignored</h1>".to_string())),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let (_, html_platform) = create_platform(&mut db, "html").await.unwrap();
+        update_platform(
+            &mut db,
+            &html_platform.id,
+            &UpdatePlatformData {
+                unavailable_fallback_html: Some(Some("<h1>Gone</h1>".to_string())),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let expired_settings = LinkSettings {
+            expires_at: Some(chrono::Utc::now() - chrono::TimeDelta::minutes(1)),
+            ..Default::default()
+        };
+        let url_link = create_link(
+            &mut db,
+            &url_platform.id,
+            None,
+            "https://example.com/".to_string(),
+            None,
+            &expired_settings,
+        )
+        .await
+        .unwrap();
+        let html_link = create_link(
+            &mut db,
+            &html_platform.id,
+            None,
+            "https://example.com/".to_string(),
+            None,
+            &expired_settings,
+        )
+        .await
+        .unwrap();
+
+        let api = api_test_client(db_pool);
+
+        let response = api.get(format!("/{}/", url_link.slug)).send().await;
+        response.assert_status(StatusCode::TEMPORARY_REDIRECT);
+        response.assert_header("Location", "https://example.com/gone");
+
+        let response = api.get(format!("/{}/", html_link.slug)).send().await;
+        response.assert_status(StatusCode::GONE);
+        response.assert_header("Content-Security-Policy", "sandbox");
+        response.assert_text("<h1>Gone</h1>").await;
+    }
+
+    #[sqlx::test]
+    async fn test_redirect_after_link_updated_through_api(db_pool: PgPool) {
+        let mut db = db_pool.acquire().await.unwrap();
+
+        let (api_key, platform) = create_platform(&mut db, "sad").await.unwrap();
+        let link = create_link(
+            &mut db,
+            &platform.id,
+            None,
+            "https://example.com/".to_string(),
+            None,
+            &LinkSettings::default(),
+        )
+        .await
+        .unwrap();
+
+        let api = api_test_client(db_pool);
+
+        let response = api.get(format!("/{}/", link.slug)).send().await;
+        response.assert_status(StatusCode::TEMPORARY_REDIRECT);
+
+        api.patch(format!("/admin/api/links/{}/", link.slug))
+            .typed_header(platform_auth_header(&platform.id, &api_key))
+            .body_json(&serde_json::json!({"url": "https://iapetus11.me/"}))
+            .send()
+            .await
+            .assert_status_is_ok();
+
+        let response = api.get(format!("/{}/", link.slug)).send().await;
+        response.assert_header("Location", "https://iapetus11.me/");
+
+        api.delete(format!("/admin/api/links/{}/", link.slug))
+            .typed_header(platform_auth_header(&platform.id, &api_key))
+            .send()
+            .await
+            .assert_status_is_ok();
+
+        let response = api.get(format!("/{}/", link.slug)).send().await;
+        response.assert_status(StatusCode::NOT_FOUND);
     }
 }